@@ -2,7 +2,7 @@
 
 require 'libfm'
 
-screen = LibFM::Screen.new
+screen = LibFM::Screen.new(fps: 60)
 viewport = LibFM::Viewport.new(screen, visible: true, decorations: true)
 viewport.resize(600, 600)
 
@@ -23,6 +23,4 @@ loop do
   sprite2.y = Math.cos(-t / 30.0) * 240 + 320 #+ 720 - 24
 
   screen.update
-
-  sleep(1.0 / 60.0)
 end
@@ -0,0 +1,15 @@
+// The checked-in header is only rewritten on request, so building never touches the source
+// tree. Run with LIBFM_UPDATE_HEADER=1 after changing the API, and the tests check it's current.
+fn main() {
+    let crate_dir = std::env::var("CARGO_MANIFEST_DIR").unwrap();
+    let out_dir = std::env::var("OUT_DIR").unwrap();
+    println!("cargo:rerun-if-changed=src");
+    println!("cargo:rerun-if-changed=cbindgen.toml");
+    println!("cargo:rerun-if-env-changed=LIBFM_UPDATE_HEADER");
+
+    let bindings = cbindgen::generate(&crate_dir).expect("failed to generate bindings");
+    bindings.write_to_file(std::path::Path::new(&out_dir).join("libfm.h"));
+    if std::env::var_os("LIBFM_UPDATE_HEADER").is_some() {
+        bindings.write_to_file(std::path::Path::new(&crate_dir).join("include/libfm.h"));
+    }
+}
@@ -0,0 +1,46 @@
+// Build with: cc examples/sprites.c -Iinclude -L../../target/debug -lfm
+#include <stdio.h>
+#include <math.h>
+
+#include "libfm.h"
+
+int main(void) {
+  FmScreen *screen = fm_screen_new(NULL);
+  if (!screen) {
+    fprintf(stderr, "failed to start screen: %s\n", fm_last_error());
+    return 1;
+  }
+
+  FmWindowConfig config = fm_window_config_default();
+  config.title = "libfm from C";
+  config.width = 600;
+  config.height = 600;
+  FmWindow *window = fm_window_new(screen, &config);
+
+  FmSprite *sprite = fm_sprite_new(window);
+  if (fm_sprite_set_image(sprite, "./examples/two_83c.png") != FM_STATUS_OK) {
+    fprintf(stderr, "%s\n", fm_last_error());
+  }
+
+  int running = 1;
+  for (int t = 0; running; t++) {
+    fm_sprite_move(sprite, sin(t / 30.0) * 240 + 240, cos(t / 30.0) * 240 + 240, 0);
+
+    if (fm_screen_update(screen) != FM_STATUS_OK) {
+      fprintf(stderr, "%s\n", fm_last_error());
+      break;
+    }
+
+    FmEvent event;
+    while (fm_screen_poll_event(screen, &event)) {
+      if (event.kind == FM_EVENT_KIND_CLOSE_REQUESTED && event.window == fm_window_id(window)) {
+        running = 0;
+      }
+    }
+  }
+
+  fm_sprite_free(sprite);
+  fm_window_free(window);
+  fm_screen_free(screen);
+  return 0;
+}
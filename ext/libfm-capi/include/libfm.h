@@ -0,0 +1,212 @@
+// C bindings for the libfm screen client.
+//
+// Every handle is owned by the caller and must be freed with its fm_*_free function.
+// Handles keep what they depend on alive, so they can be freed in any order.
+// Handle arguments may be NULL, which fails with FM_STATUS_INVALID_ARGUMENT, but must
+// otherwise point to a live handle. Strings are nul terminated UTF-8.
+//
+// Functions that fail return NULL or a status other than FM_STATUS_OK, and
+// fm_last_error describes what went wrong.
+
+#ifndef LIBFM_H
+#define LIBFM_H
+
+/* Generated by cbindgen from ext/libfm-capi/src/lib.rs, don't edit by hand. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+typedef enum FmEncoding {
+  FM_ENCODING_BINCODE,
+  // Line delimited JSON, for watching the traffic.
+  FM_ENCODING_JSON,
+} FmEncoding;
+
+typedef enum FmEventKind {
+  // The user asked to close `window`.
+  FM_EVENT_KIND_CLOSE_REQUESTED,
+} FmEventKind;
+
+typedef enum FmMode {
+  // Run the screen as a separate process.
+  FM_MODE_PROCESS,
+  // Run the screen on a thread in this process.
+  FM_MODE_IN_PROCESS,
+  // Connect to a screen running with `libfm-screen --serve` at `socket_addr`.
+  FM_MODE_CONNECT,
+} FmMode;
+
+typedef enum FmPresentMode {
+  FM_PRESENT_MODE_FIFO,
+  FM_PRESENT_MODE_MAILBOX,
+  FM_PRESENT_MODE_IMMEDIATE,
+} FmPresentMode;
+
+typedef enum FmStatus {
+  FM_STATUS_OK,
+  // A handle was `NULL` or a string wasn't valid UTF-8.
+  FM_STATUS_INVALID_ARGUMENT,
+  // The screen could not be started.
+  FM_STATUS_LAUNCH,
+  // The connection to the screen broke.
+  FM_STATUS_CONNECTION,
+  // The screen exited. `fm_last_error` includes its exit code.
+  FM_STATUS_SCREEN_DIED,
+  // The screen did not respond in time.
+  FM_STATUS_TIMED_OUT,
+  FM_STATUS_INTERRUPTED,
+  // The screen rejected an earlier message, e.g. one naming a window that was already closed.
+  FM_STATUS_PROTOCOL,
+  // An image could not be loaded.
+  FM_STATUS_ASSET_LOAD,
+  // The object was used after being disposed, e.g. a sprite whose window was freed.
+  FM_STATUS_DISPOSED,
+  FM_STATUS_IO,
+  // A shader failed to compile. `fm_last_error` says why.
+  FM_STATUS_SHADER,
+  // libfm hit a bug. `fm_last_error` says what went wrong, and the handles involved may no
+  // longer work.
+  FM_STATUS_PANIC,
+} FmStatus;
+
+// A connection to a screen.
+typedef struct FmScreen FmScreen;
+
+// An image drawn on a window.
+typedef struct FmSprite FmSprite;
+
+// A window on a screen.
+typedef struct FmWindow FmWindow;
+
+typedef struct FmScreenConfig {
+  enum FmMode mode;
+  // The screen binary to launch, or `NULL` to search for one.
+  const char *screen_path;
+  // The socket to talk over, or `NULL` to pick a unique one. Required to connect.
+  const char *socket_addr;
+  // The frame rate cap, or 0 for uncapped.
+  uint32_t fps;
+  enum FmPresentMode present_mode;
+  bool fixed_timestep;
+  // Queue everything sent until the next flush or update.
+  bool batch;
+  // Seconds to wait on the screen before giving up, or 0 to wait forever.
+  double timeout;
+  // Seconds to wait for the screen to connect after launching it.
+  double connect_timeout;
+  // A file to record the screen's traffic into, or `NULL` to not record.
+  const char *record;
+  enum FmEncoding encoding;
+} FmScreenConfig;
+
+typedef struct FmEvent {
+  enum FmEventKind kind;
+  // The id of the window the event is for, see `fm_window_id`.
+  uintptr_t window;
+} FmEvent;
+
+typedef struct FmWindowConfig {
+  // The window title, or `NULL` for none.
+  const char *title;
+  // Whether `x` and `y` are used. Otherwise the window manager places the window.
+  bool has_position;
+  int32_t x;
+  int32_t y;
+  uint32_t width;
+  uint32_t height;
+  bool visible;
+  bool decorations;
+  // Whether `z` is used.
+  bool has_z;
+  int32_t z;
+} FmWindowConfig;
+
+// A window's last known state, see `fm_window_state`.
+typedef struct FmWindowState {
+  int32_t x;
+  int32_t y;
+  uint32_t width;
+  uint32_t height;
+  bool visible;
+  bool focused;
+} FmWindowState;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Describes the last error on this thread, or returns `NULL` if there hasn't been one.
+//
+// The string is valid until the next failing call on this thread.
+const char *fm_last_error(void);
+
+// The configuration `fm_screen_new` uses when given `NULL`.
+struct FmScreenConfig fm_screen_config_default(void);
+
+// Starts a screen, returning `NULL` if it fails to start.
+struct FmScreen *fm_screen_new(const struct FmScreenConfig *config);
+
+// Frees a screen handle. The screen shuts down once its windows and sprites are freed too.
+void fm_screen_free(struct FmScreen *screen);
+
+bool fm_screen_is_alive(const struct FmScreen *screen);
+
+// Sends everything queued up so far, then waits for the next frame.
+enum FmStatus fm_screen_update(const struct FmScreen *screen);
+
+// Sends everything queued up so far without waiting for a frame.
+enum FmStatus fm_screen_flush(const struct FmScreen *screen);
+
+// Flushes the queue and handles whatever the screen has sent, without waiting.
+//
+// Any events picked up can then be taken with `fm_screen_poll_event`.
+enum FmStatus fm_screen_process_events(const struct FmScreen *screen);
+
+// Takes the oldest pending event, writing it to `event`. Returns false if there are none.
+bool fm_screen_poll_event(const struct FmScreen *screen, struct FmEvent *event);
+
+// The number of the last frame the screen reported.
+uint64_t fm_screen_frame_count(const struct FmScreen *screen);
+
+// Seconds between the last two frames the screen reported.
+double fm_screen_delta(const struct FmScreen *screen);
+
+// The configuration `fm_window_new` uses when given `NULL`.
+struct FmWindowConfig fm_window_config_default(void);
+
+// Opens a window on `screen`, returning `NULL` on failure.
+struct FmWindow *fm_window_new(const struct FmScreen *screen, const struct FmWindowConfig *config);
+
+// Closes a window, along with its sprites, and frees its handle. The sprites' handles stay
+// valid, but using them fails with `FM_STATUS_DISPOSED` until they are freed.
+void fm_window_free(struct FmWindow *window);
+
+// The id events use to refer to this window.
+uintptr_t fm_window_id(const struct FmWindow *window);
+
+enum FmStatus fm_window_move(const struct FmWindow *window, int32_t x, int32_t y);
+
+enum FmStatus fm_window_resize(const struct FmWindow *window, uint32_t width, uint32_t height);
+
+// Writes the window's last known state to `state`. It's kept up to date by
+// `fm_screen_update` and `fm_screen_process_events`.
+enum FmStatus fm_window_state(const struct FmWindow *window, struct FmWindowState *state);
+
+// Creates a sprite on `window`, returning `NULL` on failure.
+struct FmSprite *fm_sprite_new(const struct FmWindow *window);
+
+// Removes a sprite and frees its handle.
+void fm_sprite_free(struct FmSprite *sprite);
+
+// Sets the image the sprite draws, loaded by the screen from `path`.
+enum FmStatus fm_sprite_set_image(const struct FmSprite *sprite, const char *path);
+
+enum FmStatus fm_sprite_move(const struct FmSprite *sprite, int32_t x, int32_t y, int32_t z);
+
+#ifdef __cplusplus
+} // extern "C"
+#endif // __cplusplus
+
+#endif /* LIBFM_H */
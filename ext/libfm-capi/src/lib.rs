@@ -0,0 +1,664 @@
+// Copyright (C) 2023 Lily Lyons
+//
+// This file is part of libfm.
+//
+// libfm is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// libfm is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with libfm.  If not, see <http://www.gnu.org/licenses/>.
+
+//! C bindings for libfm-client, for embedding the screen in runtimes other than Ruby.
+//!
+//! The generated header is `include/libfm.h`.
+#![warn(rust_2018_idioms, clippy::all)]
+// Every pointer argument has the same contract, which is spelled out at the top of the header
+#![allow(clippy::missing_safety_doc)]
+
+use libfm_client::{
+    Encoding, Error, Event, FrameConfig, Mode, PresentMode, ScreenConfig, WindowConfig,
+};
+use std::cell::RefCell;
+use std::ffi::{c_char, CStr, CString};
+use std::panic::AssertUnwindSafe;
+use std::time::Duration;
+
+/// A connection to a screen.
+pub struct FmScreen(libfm_client::Screen);
+
+/// A window on a screen.
+pub struct FmWindow(libfm_client::Window);
+
+/// An image drawn on a window.
+pub struct FmSprite(libfm_client::Sprite);
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FmStatus {
+    Ok,
+    /// A handle was `NULL` or a string wasn't valid UTF-8.
+    InvalidArgument,
+    /// The screen could not be started.
+    Launch,
+    /// The connection to the screen broke.
+    Connection,
+    /// The screen exited. `fm_last_error` includes its exit code.
+    ScreenDied,
+    /// The screen did not respond in time.
+    TimedOut,
+    Interrupted,
+    /// The screen rejected an earlier message, e.g. one naming a window that was already closed.
+    Protocol,
+    /// An image could not be loaded.
+    AssetLoad,
+    /// The object was used after being disposed, e.g. a sprite whose window was freed.
+    Disposed,
+    Io,
+    /// A shader failed to compile. `fm_last_error` says why.
+    Shader,
+    /// libfm hit a bug. `fm_last_error` says what went wrong, and the handles involved may no
+    /// longer work.
+    Panic,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub enum FmMode {
+    /// Run the screen as a separate process.
+    Process,
+    /// Run the screen on a thread in this process.
+    InProcess,
+    /// Connect to a screen running with `libfm-screen --serve` at `socket_addr`.
+    Connect,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub enum FmPresentMode {
+    Fifo,
+    Mailbox,
+    Immediate,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub enum FmEncoding {
+    Bincode,
+    /// Line delimited JSON, for watching the traffic.
+    Json,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct FmScreenConfig {
+    pub mode: FmMode,
+    /// The screen binary to launch, or `NULL` to search for one.
+    pub screen_path: *const c_char,
+    /// The socket to talk over, or `NULL` to pick a unique one. Required to connect.
+    pub socket_addr: *const c_char,
+    /// The frame rate cap, or 0 for uncapped.
+    pub fps: u32,
+    pub present_mode: FmPresentMode,
+    pub fixed_timestep: bool,
+    /// Queue everything sent until the next flush or update.
+    pub batch: bool,
+    /// Seconds to wait on the screen before giving up, or 0 to wait forever.
+    pub timeout: f64,
+    /// Seconds to wait for the screen to connect after launching it.
+    pub connect_timeout: f64,
+    /// A file to record the screen's traffic into, or `NULL` to not record.
+    pub record: *const c_char,
+    pub encoding: FmEncoding,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct FmWindowConfig {
+    /// The window title, or `NULL` for none.
+    pub title: *const c_char,
+    /// Whether `x` and `y` are used. Otherwise the window manager places the window.
+    pub has_position: bool,
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+    pub visible: bool,
+    pub decorations: bool,
+    /// Whether `z` is used.
+    pub has_z: bool,
+    pub z: i32,
+}
+
+/// A window's last known state, see `fm_window_state`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct FmWindowState {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+    pub visible: bool,
+    pub focused: bool,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub enum FmEventKind {
+    /// The user asked to close `window`.
+    CloseRequested,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct FmEvent {
+    pub kind: FmEventKind,
+    /// The id of the window the event is for, see `fm_window_id`.
+    pub window: usize,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_error(message: impl ToString) {
+    // Interior nul bytes would cut the message short anyway
+    let message = message.to_string().replace('\0', "");
+    let message = CString::new(message).expect("message contains a nul byte");
+    LAST_ERROR.with(|error| *error.borrow_mut() = Some(message));
+}
+
+fn fail(error: Error) -> FmStatus {
+    let status = match error {
+        Error::Launch(_) => FmStatus::Launch,
+        Error::Connection(_) => FmStatus::Connection,
+        Error::ScreenDied(_) => FmStatus::ScreenDied,
+        Error::TimedOut(_) => FmStatus::TimedOut,
+        Error::Interrupted => FmStatus::Interrupted,
+        Error::Protocol(_) => FmStatus::Protocol,
+        Error::AssetLoad { .. } => FmStatus::AssetLoad,
+        Error::Disposed(_) => FmStatus::Disposed,
+        Error::Io(_) => FmStatus::Io,
+        Error::Shader(_) => FmStatus::Shader,
+    };
+    set_error(error);
+
+    status
+}
+
+fn status(result: libfm_client::Result<()>) -> FmStatus {
+    match result {
+        Ok(()) => FmStatus::Ok,
+        Err(e) => fail(e),
+    }
+}
+
+fn invalid(message: &str) -> FmStatus {
+    set_error(message);
+    FmStatus::InvalidArgument
+}
+
+/// What a function returns when it panics, since unwinding into C is undefined behavior.
+trait Fallback {
+    fn fallback() -> Self;
+}
+
+impl Fallback for FmStatus {
+    fn fallback() -> Self {
+        FmStatus::Panic
+    }
+}
+
+impl<T> Fallback for *mut T {
+    fn fallback() -> Self {
+        std::ptr::null_mut()
+    }
+}
+
+impl<T> Fallback for *const T {
+    fn fallback() -> Self {
+        std::ptr::null()
+    }
+}
+
+impl Fallback for () {
+    fn fallback() -> Self {}
+}
+
+impl Fallback for bool {
+    fn fallback() -> Self {
+        false
+    }
+}
+
+impl Fallback for usize {
+    fn fallback() -> Self {
+        0
+    }
+}
+
+impl Fallback for u64 {
+    fn fallback() -> Self {
+        0
+    }
+}
+
+impl Fallback for f64 {
+    fn fallback() -> Self {
+        0.0
+    }
+}
+
+// The defaults have no way to report a failure, so there's nothing better to do than stop
+impl Fallback for FmScreenConfig {
+    fn fallback() -> Self {
+        std::process::abort()
+    }
+}
+
+impl Fallback for FmWindowConfig {
+    fn fallback() -> Self {
+        std::process::abort()
+    }
+}
+
+/// Runs the body of an `fm_*` function, turning a panic into its fallback and the last error.
+fn guard<T: Fallback>(func: impl FnOnce() -> T) -> T {
+    std::panic::catch_unwind(AssertUnwindSafe(func)).unwrap_or_else(|payload| {
+        let message = payload
+            .downcast_ref::<&str>()
+            .copied()
+            .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+            .unwrap_or("unknown error");
+        set_error(format!("libfm panicked: {message}"));
+        T::fallback()
+    })
+}
+
+unsafe fn handle<'a, T>(ptr: *const T, name: &str) -> Result<&'a T, FmStatus> {
+    ptr.as_ref()
+        .ok_or_else(|| invalid(&format!("{name} must not be NULL")))
+}
+
+unsafe fn string(ptr: *const c_char, name: &str) -> Result<Option<String>, FmStatus> {
+    if ptr.is_null() {
+        return Ok(None);
+    }
+
+    CStr::from_ptr(ptr)
+        .to_str()
+        .map(|s| Some(s.to_string()))
+        .map_err(|_| invalid(&format!("{name} is not valid UTF-8")))
+}
+
+unsafe fn call<T>(
+    ptr: *const T,
+    name: &str,
+    func: impl FnOnce(&T) -> libfm_client::Result<()>,
+) -> FmStatus {
+    match handle(ptr, name) {
+        Ok(handle) => status(func(handle)),
+        Err(status) => status,
+    }
+}
+
+/// Describes the last error on this thread, or returns `NULL` if there hasn't been one.
+///
+/// The string is valid until the next failing call on this thread.
+#[no_mangle]
+pub extern "C" fn fm_last_error() -> *const c_char {
+    guard(|| {
+        LAST_ERROR.with(|error| {
+            error
+                .borrow()
+                .as_ref()
+                .map_or(std::ptr::null(), |e| e.as_ptr())
+        })
+    })
+}
+
+/// The configuration `fm_screen_new` uses when given `NULL`.
+#[no_mangle]
+pub extern "C" fn fm_screen_config_default() -> FmScreenConfig {
+    guard(|| {
+        let config = ScreenConfig::default();
+        FmScreenConfig {
+            mode: FmMode::Process,
+            screen_path: std::ptr::null(),
+            socket_addr: std::ptr::null(),
+            fps: config.frames.target_fps.unwrap_or(0),
+            present_mode: FmPresentMode::Fifo,
+            fixed_timestep: config.frames.fixed_timestep,
+            batch: config.batch,
+            timeout: 0.0,
+            connect_timeout: config.connect_timeout.as_secs_f64(),
+            record: std::ptr::null(),
+            encoding: match config.encoding {
+                Encoding::Bincode => FmEncoding::Bincode,
+                Encoding::Json => FmEncoding::Json,
+            },
+        }
+    })
+}
+
+/// Starts a screen, returning `NULL` if it fails to start.
+#[no_mangle]
+pub unsafe extern "C" fn fm_screen_new(config: *const FmScreenConfig) -> *mut FmScreen {
+    guard(|| {
+        let config = config
+            .as_ref()
+            .copied()
+            .unwrap_or_else(|| fm_screen_config_default());
+        let (Ok(screen_path), Ok(socket_addr), Ok(record)) = (
+            string(config.screen_path, "screen_path"),
+            string(config.socket_addr, "socket_addr"),
+            string(config.record, "record"),
+        ) else {
+            return std::ptr::null_mut();
+        };
+
+        let config = ScreenConfig {
+            mode: match config.mode {
+                FmMode::Process => Mode::Process,
+                FmMode::InProcess => Mode::InProcess,
+                FmMode::Connect => Mode::Connect,
+            },
+            screen_path: screen_path.map(Into::into),
+            socket_addr,
+            frames: FrameConfig {
+                target_fps: (config.fps > 0).then_some(config.fps),
+                present_mode: match config.present_mode {
+                    FmPresentMode::Fifo => PresentMode::Fifo,
+                    FmPresentMode::Mailbox => PresentMode::Mailbox,
+                    FmPresentMode::Immediate => PresentMode::Immediate,
+                },
+                fixed_timestep: config.fixed_timestep,
+            },
+            batch: config.batch,
+            timeout: (config.timeout > 0.0).then(|| Duration::from_secs_f64(config.timeout)),
+            connect_timeout: Duration::from_secs_f64(config.connect_timeout.max(0.0)),
+            record: record.map(Into::into),
+            encoding: match config.encoding {
+                FmEncoding::Bincode => Encoding::Bincode,
+                FmEncoding::Json => Encoding::Json,
+            },
+        };
+
+        match libfm_client::Screen::new(config) {
+            Ok(screen) => Box::into_raw(Box::new(FmScreen(screen))),
+            Err(e) => {
+                fail(e);
+                std::ptr::null_mut()
+            }
+        }
+    })
+}
+
+/// Frees a screen handle. The screen shuts down once its windows and sprites are freed too.
+#[no_mangle]
+pub unsafe extern "C" fn fm_screen_free(screen: *mut FmScreen) {
+    guard(|| {
+        if !screen.is_null() {
+            drop(Box::from_raw(screen));
+        }
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn fm_screen_is_alive(screen: *const FmScreen) -> bool {
+    guard(|| {
+        // Only a blocker that gives up can make these fail, and the default one never does
+        handle(screen, "screen").is_ok_and(|screen| screen.0.is_alive().unwrap_or(false))
+    })
+}
+
+/// Sends everything queued up so far, then waits for the next frame.
+#[no_mangle]
+pub unsafe extern "C" fn fm_screen_update(screen: *const FmScreen) -> FmStatus {
+    guard(|| call(screen, "screen", |screen| screen.0.update()))
+}
+
+/// Sends everything queued up so far without waiting for a frame.
+#[no_mangle]
+pub unsafe extern "C" fn fm_screen_flush(screen: *const FmScreen) -> FmStatus {
+    guard(|| call(screen, "screen", |screen| screen.0.flush()))
+}
+
+/// Flushes the queue and handles whatever the screen has sent, without waiting.
+///
+/// Any events picked up can then be taken with `fm_screen_poll_event`.
+#[no_mangle]
+pub unsafe extern "C" fn fm_screen_process_events(screen: *const FmScreen) -> FmStatus {
+    guard(|| call(screen, "screen", |screen| screen.0.process_events()))
+}
+
+/// Takes the oldest pending event, writing it to `event`. Returns false if there are none.
+#[no_mangle]
+pub unsafe extern "C" fn fm_screen_poll_event(
+    screen: *const FmScreen,
+    event: *mut FmEvent,
+) -> bool {
+    guard(|| {
+        let (Ok(screen), Some(event)) = (handle(screen, "screen"), event.as_mut()) else {
+            return false;
+        };
+
+        match screen.0.poll_event().unwrap_or(None) {
+            Some(Event::CloseRequested(window)) => {
+                *event = FmEvent {
+                    kind: FmEventKind::CloseRequested,
+                    window,
+                };
+                true
+            }
+            None => false,
+        }
+    })
+}
+
+/// The number of the last frame the screen reported.
+#[no_mangle]
+pub unsafe extern "C" fn fm_screen_frame_count(screen: *const FmScreen) -> u64 {
+    guard(|| handle(screen, "screen").map_or(0, |screen| screen.0.frame_count().unwrap_or(0)))
+}
+
+/// Seconds between the last two frames the screen reported.
+#[no_mangle]
+pub unsafe extern "C" fn fm_screen_delta(screen: *const FmScreen) -> f64 {
+    guard(|| handle(screen, "screen").map_or(0.0, |screen| screen.0.delta().unwrap_or(0.0)))
+}
+
+/// The configuration `fm_window_new` uses when given `NULL`.
+#[no_mangle]
+pub extern "C" fn fm_window_config_default() -> FmWindowConfig {
+    guard(|| FmWindowConfig {
+        title: std::ptr::null(),
+        has_position: false,
+        x: 0,
+        y: 0,
+        width: 640,
+        height: 480,
+        visible: true,
+        decorations: true,
+        has_z: false,
+        z: 0,
+    })
+}
+
+/// Opens a window on `screen`, returning `NULL` on failure.
+#[no_mangle]
+pub unsafe extern "C" fn fm_window_new(
+    screen: *const FmScreen,
+    config: *const FmWindowConfig,
+) -> *mut FmWindow {
+    guard(|| {
+        let config = config
+            .as_ref()
+            .copied()
+            .unwrap_or_else(|| fm_window_config_default());
+        let (Ok(screen), Ok(title)) = (handle(screen, "screen"), string(config.title, "title"))
+        else {
+            return std::ptr::null_mut();
+        };
+
+        let config = WindowConfig {
+            title: title.unwrap_or_default(),
+            pos: config.has_position.then_some((config.x, config.y)),
+            visible: config.visible,
+            decorations: config.decorations,
+            size: (config.width, config.height),
+            z: config.has_z.then_some(config.z),
+        };
+
+        match libfm_client::Window::new(&screen.0, config) {
+            Ok(window) => Box::into_raw(Box::new(FmWindow(window))),
+            Err(e) => {
+                fail(e);
+                std::ptr::null_mut()
+            }
+        }
+    })
+}
+
+/// Closes a window, along with its sprites, and frees its handle. The sprites' handles stay
+/// valid, but using them fails with `FM_STATUS_DISPOSED` until they are freed.
+#[no_mangle]
+pub unsafe extern "C" fn fm_window_free(window: *mut FmWindow) {
+    guard(|| {
+        if !window.is_null() {
+            drop(Box::from_raw(window));
+        }
+    })
+}
+
+/// The id events use to refer to this window.
+#[no_mangle]
+pub unsafe extern "C" fn fm_window_id(window: *const FmWindow) -> usize {
+    guard(|| handle(window, "window").map_or(0, |window| window.0.id()))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn fm_window_move(window: *const FmWindow, x: i32, y: i32) -> FmStatus {
+    guard(|| call(window, "window", |window| window.0.reposition(x, y)))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn fm_window_resize(
+    window: *const FmWindow,
+    width: u32,
+    height: u32,
+) -> FmStatus {
+    guard(|| call(window, "window", |window| window.0.resize(width, height)))
+}
+
+/// Writes the window's last known state to `state`. It's kept up to date by
+/// `fm_screen_update` and `fm_screen_process_events`.
+#[no_mangle]
+pub unsafe extern "C" fn fm_window_state(
+    window: *const FmWindow,
+    state: *mut FmWindowState,
+) -> FmStatus {
+    guard(|| {
+        let Some(out) = state.as_mut() else {
+            return invalid("state must not be NULL");
+        };
+
+        call(window, "window", |window| {
+            let state = window.0.state()?;
+            *out = FmWindowState {
+                x: state.x,
+                y: state.y,
+                width: state.width,
+                height: state.height,
+                visible: state.visible,
+                focused: state.focused,
+            };
+            Ok(())
+        })
+    })
+}
+
+/// Creates a sprite on `window`, returning `NULL` on failure.
+#[no_mangle]
+pub unsafe extern "C" fn fm_sprite_new(window: *const FmWindow) -> *mut FmSprite {
+    guard(|| {
+        let Ok(window) = handle(window, "window") else {
+            return std::ptr::null_mut();
+        };
+
+        match libfm_client::Sprite::new(&window.0) {
+            Ok(sprite) => Box::into_raw(Box::new(FmSprite(sprite))),
+            Err(e) => {
+                fail(e);
+                std::ptr::null_mut()
+            }
+        }
+    })
+}
+
+/// Removes a sprite and frees its handle.
+#[no_mangle]
+pub unsafe extern "C" fn fm_sprite_free(sprite: *mut FmSprite) {
+    guard(|| {
+        if !sprite.is_null() {
+            drop(Box::from_raw(sprite));
+        }
+    })
+}
+
+/// Sets the image the sprite draws, loaded by the screen from `path`.
+#[no_mangle]
+pub unsafe extern "C" fn fm_sprite_set_image(
+    sprite: *const FmSprite,
+    path: *const c_char,
+) -> FmStatus {
+    guard(|| {
+        let path = match string(path, "path") {
+            Ok(Some(path)) => path,
+            Ok(None) => return invalid("path must not be NULL"),
+            Err(status) => return status,
+        };
+
+        call(sprite, "sprite", |sprite| sprite.0.set(path))
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn fm_sprite_move(
+    sprite: *const FmSprite,
+    x: i32,
+    y: i32,
+    z: i32,
+) -> FmStatus {
+    guard(|| call(sprite, "sprite", |sprite| sprite.0.reposition(x, y, z)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn panics_become_a_status() {
+        let status = guard(|| -> FmStatus { panic!("out of sprites") });
+        assert_eq!(status, FmStatus::Panic);
+
+        let error = unsafe { CStr::from_ptr(fm_last_error()) };
+        assert_eq!(error.to_str(), Ok("libfm panicked: out of sprites"));
+    }
+
+    #[test]
+    fn checked_in_header_is_up_to_date() {
+        let generated = include_str!(concat!(env!("OUT_DIR"), "/libfm.h"));
+        let checked_in = include_str!("../include/libfm.h");
+        assert!(
+            generated == checked_in,
+            "include/libfm.h is out of date, rebuild with LIBFM_UPDATE_HEADER=1"
+        );
+    }
+}
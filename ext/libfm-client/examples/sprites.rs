@@ -0,0 +1,32 @@
+use libfm_client::{Screen, ScreenConfig, Sprite, Window, WindowConfig};
+
+fn main() -> libfm_client::Result<()> {
+    let screen = Screen::new(ScreenConfig::default())?;
+    let window = Window::new(
+        &screen,
+        WindowConfig {
+            title: "libfm-client".to_string(),
+            pos: None,
+            visible: true,
+            decorations: true,
+            size: (600, 600),
+            z: None,
+        },
+    )?;
+
+    let sprite = Sprite::new(&window)?;
+    sprite.set("./examples/two_83c.png")?;
+
+    for t in 0.. {
+        let t = t as f64 / 30.0;
+        sprite.reposition(
+            (t.sin() * 240.0 + 240.0) as i32,
+            (t.cos() * 240.0 + 240.0) as i32,
+            0,
+        )?;
+
+        screen.update()?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,176 @@
+// Copyright (C) 2023 Lily Lyons
+//
+// This file is part of libfm.
+//
+// libfm is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// libfm is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with libfm.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+use crate::{Error, Result};
+
+fn screen_exe() -> String {
+    format!("libfm-screen{}", std::env::consts::EXE_SUFFIX)
+}
+
+// The directory the library containing this client was loaded from
+#[cfg(unix)]
+fn extension_dir() -> Option<PathBuf> {
+    let mut info: libc::Dl_info = unsafe { std::mem::zeroed() };
+    let found = unsafe { libc::dladdr(extension_dir as *const libc::c_void, &mut info) };
+    if found == 0 || info.dli_fname.is_null() {
+        return None;
+    }
+
+    let path = unsafe { std::ffi::CStr::from_ptr(info.dli_fname) };
+    let path = std::fs::canonicalize(path.to_str().ok()?).ok()?;
+    path.parent().map(Path::to_path_buf)
+}
+
+#[cfg(not(unix))]
+fn extension_dir() -> Option<PathBuf> {
+    None
+}
+
+/// Every place we look for the screen binary, in the order we look.
+fn candidates() -> Vec<PathBuf> {
+    candidates_in(
+        extension_dir().as_deref(),
+        std::env::var_os("LIBFM_SCREEN_PATH"),
+        std::env::var_os("PATH"),
+    )
+}
+
+fn candidates_in(
+    extension_dir: Option<&Path>,
+    screen_path: Option<OsString>,
+    paths: Option<OsString>,
+) -> Vec<PathBuf> {
+    let exe = screen_exe();
+    let mut candidates = vec![];
+
+    // The extension is installed to <gem>/lib/libfm/
+    let root = extension_dir.and_then(Path::parent).and_then(Path::parent);
+    if let Some(dir) = extension_dir {
+        candidates.push(dir.join(&exe));
+    }
+    if let Some(root) = root {
+        candidates.push(root.join("bin").join(&exe));
+        candidates.push(root.join("exe").join(&exe));
+    }
+    if let Some(path) = screen_path {
+        candidates.push(path.into());
+    }
+
+    if let Some(paths) = paths {
+        candidates.extend(std::env::split_paths(&paths).map(|dir| dir.join(&exe)));
+    }
+
+    // Development checkouts, as a last resort so a stale build never shadows an installed one
+    if let Some(root) = root.filter(|_| cfg!(debug_assertions)) {
+        candidates.push(root.join("target").join("debug").join(&exe));
+        candidates.push(root.join("target").join("release").join(&exe));
+    }
+
+    candidates
+}
+
+fn protocol_version(path: &Path) -> Option<u32> {
+    let output = std::process::Command::new(path)
+        .arg("--protocol-version")
+        .stderr(std::process::Stdio::null())
+        .output()
+        .ok()?;
+
+    String::from_utf8(output.stdout).ok()?.trim().parse().ok()
+}
+
+/// Checks that `path` is a screen binary that speaks the same protocol as us.
+pub fn check_screen(path: &Path) -> Result<()> {
+    match protocol_version(path) {
+        Some(screen::PROTOCOL_VERSION) => Ok(()),
+        Some(version) => Err(Error::Launch(format!(
+            "screen {} speaks protocol version {version}, but libfm expects {}",
+            path.display(),
+            screen::PROTOCOL_VERSION
+        ))),
+        None => Err(Error::Launch(format!(
+            "{} is not a working screen binary",
+            path.display()
+        ))),
+    }
+}
+
+/// Looks for a screen binary next to this library, in the gem's `bin` and `exe` directories, at
+/// `LIBFM_SCREEN_PATH` and finally on `PATH`. Debug builds also look in the checkout's `target/`
+/// directories after that.
+pub fn find_screen() -> Result<PathBuf> {
+    let candidates = candidates();
+    let mut rejected = vec![];
+
+    for path in candidates.iter().filter(|path| path.is_file()) {
+        match check_screen(path) {
+            Ok(()) => return Ok(path.clone()),
+            Err(e) => rejected.push(e.to_string()),
+        }
+    }
+
+    let mut message = "could not find a screen binary. Searched:".to_string();
+    for path in candidates {
+        message.push_str(&format!("\n  {}", path.display()));
+    }
+    for reason in rejected {
+        message.push_str(&format!("\nrejected: {reason}"));
+    }
+
+    Err(Error::Launch(message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_gem_comes_first_and_dev_builds_last() {
+        let exe = screen_exe();
+        let root = Path::new("/gems/libfm");
+        let paths = std::env::join_paths(["/usr/local/bin", "/usr/bin"]).unwrap();
+
+        let candidates = candidates_in(
+            Some(&root.join("lib").join("libfm")),
+            Some("/opt/screen".into()),
+            Some(paths),
+        );
+
+        let mut expected = vec![
+            root.join("lib").join("libfm").join(&exe),
+            root.join("bin").join(&exe),
+            root.join("exe").join(&exe),
+            PathBuf::from("/opt/screen"),
+            Path::new("/usr/local/bin").join(&exe),
+            Path::new("/usr/bin").join(&exe),
+        ];
+        if cfg!(debug_assertions) {
+            expected.push(root.join("target").join("debug").join(&exe));
+            expected.push(root.join("target").join("release").join(&exe));
+        }
+        assert_eq!(candidates, expected);
+    }
+
+    #[test]
+    fn missing_locations_are_skipped() {
+        let candidates = candidates_in(None, None, None);
+        assert!(candidates.is_empty());
+    }
+}
@@ -0,0 +1,87 @@
+// Copyright (C) 2023 Lily Lyons
+//
+// This file is part of libfm.
+//
+// libfm is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// libfm is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with libfm.  If not, see <http://www.gnu.org/licenses/>.
+
+use screen::ProtocolError;
+use std::path::PathBuf;
+use std::time::Duration;
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+#[derive(Debug)]
+pub enum Error {
+    /// The screen could not be started, or never connected to us.
+    Launch(String),
+    /// The screen hung up or the connection to it broke.
+    Connection(String),
+    /// The screen exited while we were using it. Holds its exit code, if it has one.
+    ScreenDied(Option<i32>),
+    /// The screen did not respond in time.
+    TimedOut(Duration),
+    /// A [`crate::Blocker`] gave up waiting before the operation finished.
+    Interrupted,
+    /// The screen rejected a message we sent it earlier.
+    Protocol(ProtocolError),
+    /// An image could not be loaded, either because it doesn't exist or the screen couldn't
+    /// decode it.
+    AssetLoad {
+        path: PathBuf,
+        reason: String,
+    },
+    /// A shader failed to compile, or doesn't fit the interface in [`crate::SHADER_PRELUDE`].
+    Shader(String),
+    /// The object was used after being disposed. Holds what kind of object it was.
+    Disposed(&'static str),
+    Io(std::io::Error),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Launch(message) | Error::Connection(message) | Error::Shader(message) => {
+                f.write_str(message)
+            }
+            Error::TimedOut(timeout) => {
+                write!(f, "timed out after {timeout:?} waiting on the screen")
+            }
+            Error::ScreenDied(Some(code)) => write!(f, "the screen exited with code {code}"),
+            Error::ScreenDied(None) => f.write_str("the screen exited unexpectedly"),
+            Error::Interrupted => f.write_str("interrupted while waiting on the screen"),
+            Error::Protocol(e) => e.fmt(f),
+            Error::AssetLoad { path, reason } => {
+                write!(f, "failed to load {}: {reason}", path.display())
+            }
+            Error::Disposed(kind) => write!(f, "disposed {kind}"),
+            Error::Io(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Protocol(e) => Some(e),
+            Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(value: std::io::Error) -> Self {
+        Error::Io(value)
+    }
+}
@@ -0,0 +1,138 @@
+// Copyright (C) 2023 Lily Lyons
+//
+// This file is part of libfm.
+//
+// libfm is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// libfm is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with libfm.  If not, see <http://www.gnu.org/licenses/>.
+
+use screen::Message;
+use std::marker::PhantomData;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use crate::{Error, Result, Screen, Window};
+
+/// A kind of object that lives on a window, like a sprite or a plane.
+pub(crate) trait Kind {
+    /// What the object is called in errors.
+    const NAME: &'static str;
+
+    fn remove(id: usize, window_id: usize) -> Message;
+}
+
+/// What every object on a window keeps track of: its ids, the screen it's on and whether it's
+/// been disposed. The object is removed from its window along with the next message sent after
+/// this is dropped.
+pub(crate) struct Handle<K: Kind> {
+    id: usize,
+    window_id: usize,
+    screen: Screen,
+    disposed: AtomicBool,
+    // Shared with the window, since disposing the window takes its objects with it
+    window_disposed: Arc<AtomicBool>,
+    kind: PhantomData<fn() -> K>,
+}
+
+impl<K: Kind> Drop for Handle<K> {
+    fn drop(&mut self) {
+        if self.disposed.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        if !self.window_disposed.load(Ordering::Acquire) {
+            self.screen.send_later(K::remove(self.id, self.window_id));
+        }
+    }
+}
+
+impl<K: Kind> Handle<K> {
+    /// Creates an object on `window` with the message `create` makes from its id and the
+    /// window's.
+    pub fn new(window: &Window, create: impl FnOnce(usize, usize) -> Message) -> Result<Self> {
+        window.check_disposed()?;
+        let screen = window.screen().clone();
+
+        let mut inner = screen.lock()?;
+        let id = inner.next_id();
+        inner.send(create(id, window.id()))?;
+        drop(inner);
+
+        Ok(Self {
+            id,
+            window_id: window.id(),
+            screen,
+            disposed: AtomicBool::new(false),
+            window_disposed: window.disposed_flag(),
+            kind: PhantomData,
+        })
+    }
+
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    pub fn window_id(&self) -> usize {
+        self.window_id
+    }
+
+    pub fn is_disposed(&self) -> bool {
+        self.disposed.load(Ordering::Acquire) || self.window_disposed.load(Ordering::Acquire)
+    }
+
+    pub fn check_disposed(&self) -> Result<()> {
+        if self.is_disposed() {
+            return Err(Error::Disposed(K::NAME));
+        }
+
+        Ok(())
+    }
+
+    /// Sends the message `message` makes from the object's id and its window's.
+    pub fn send(&self, message: impl FnOnce(usize, usize) -> Message) -> Result<()> {
+        self.screen.send(message(self.id, self.window_id))
+    }
+
+    /// Removes the object from its window. Disposing it again does nothing.
+    pub fn dispose(&self) -> Result<()> {
+        if self.disposed.swap(true, Ordering::AcqRel) {
+            return Ok(());
+        }
+        // The screen already removed it along with the window
+        if self.window_disposed.load(Ordering::Acquire) {
+            return Ok(());
+        }
+
+        // Unless the message is on its way, the object is still there to dispose again
+        let (accepted, result) = match self.screen.lock() {
+            Ok(mut inner) => inner.send_accepted(K::remove(self.id, self.window_id)),
+            Err(e) => (false, Err(e)),
+        };
+        if !accepted {
+            self.disposed.store(false, Ordering::Release);
+        }
+        result
+    }
+}
+
+/// Checks that an asset exists before the screen is asked to load it, returning the path to
+/// send.
+pub(crate) fn asset_path(path: &Path) -> Result<String> {
+    if !path.exists() {
+        return Err(Error::AssetLoad {
+            path: path.to_path_buf(),
+            reason: "file does not exist".to_string(),
+        });
+    }
+
+    Ok(path.to_string_lossy().into_owned())
+}
@@ -0,0 +1,347 @@
+// Copyright (C) 2023 Lily Lyons
+//
+// This file is part of libfm.
+//
+// libfm is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// libfm is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with libfm.  If not, see <http://www.gnu.org/licenses/>.
+
+use futures::prelude::*;
+use interprocess::local_socket;
+use parking_lot::Mutex;
+use screen::wire::{Encoding, Rejection};
+use screen::{Message, ProtocolError, ReturnMessage, PROTOCOL_VERSION};
+
+use std::collections::VecDeque;
+use std::io::BufRead;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::screen::{block_on, Blocker};
+use crate::{discovery, Error, Result};
+
+// How many lines of the screen's stderr we hold on to for error messages
+const STDERR_LINES: usize = 64;
+
+// Both backends are driven through these, so the rest of the client doesn't care which one it has
+pub(crate) type MessageSink = Pin<Box<dyn Sink<Message, Error = Error> + Send>>;
+pub(crate) type ReturnStream = Pin<Box<dyn Stream<Item = ReturnMessage> + Send>>;
+
+pub(crate) enum Backend {
+    Process {
+        child: std::process::Child,
+        socket_file: Option<SocketFile>,
+    },
+    InProcess {
+        sender: futures::channel::mpsc::UnboundedSender<Message>,
+        thread: Option<std::thread::JoinHandle<()>>,
+    },
+    /// A screen server somebody else is running, which cleans up after us when we hang up.
+    Remote,
+}
+
+impl Backend {
+    pub(crate) fn is_alive(&mut self) -> bool {
+        match self {
+            Backend::Process { child, .. } => child.try_wait().is_ok_and(|c| c.is_none()),
+            Backend::InProcess { thread, .. } => thread.as_ref().is_some_and(|t| !t.is_finished()),
+            Backend::Remote => true,
+        }
+    }
+
+    /// Why the screen is gone, if it is. A remote screen never counts as dead, since all we can
+    /// see of it is the connection.
+    pub(crate) fn death(&mut self) -> Option<Error> {
+        match self {
+            Backend::Process { child, .. } => {
+                let status = child.try_wait().ok()??;
+                Some(Error::ScreenDied(status.code()))
+            }
+            Backend::InProcess { thread, .. } => thread
+                .as_ref()
+                .is_some_and(|t| t.is_finished())
+                .then_some(Error::ScreenDied(None)),
+            Backend::Remote => None,
+        }
+    }
+
+    pub(crate) fn shutdown(&mut self) {
+        match self {
+            Backend::Process { child, socket_file } => {
+                let _ = child.kill();
+                let _ = child.wait();
+                // Only clean up the socket once the screen can't be using it anymore
+                drop(socket_file.take());
+            }
+            Backend::InProcess { sender, thread } => {
+                // The renderer might have already shut down on its own
+                let _ = sender.unbounded_send(Message::Shutdown);
+                if let Some(thread) = thread.take() {
+                    let _ = thread.join();
+                }
+            }
+            Backend::Remote => {}
+        }
+    }
+}
+
+type StderrLog = Arc<Mutex<VecDeque<String>>>;
+
+// Forwards the child's stderr to ours while keeping the most recent lines around
+fn capture_stderr(child: &mut std::process::Child) -> (StderrLog, std::thread::JoinHandle<()>) {
+    let stderr = child.stderr.take().expect("child stderr is not piped");
+    let log = StderrLog::default();
+
+    let thread_log = log.clone();
+    let handle = std::thread::spawn(move || {
+        for line in std::io::BufReader::new(stderr).lines() {
+            let Ok(line) = line else { break };
+            eprintln!("{line}");
+
+            let mut log = thread_log.lock();
+            if log.len() == STDERR_LINES {
+                log.pop_front();
+            }
+            log.push_back(line);
+        }
+    });
+
+    (log, handle)
+}
+
+// Removes a path based socket once we're done with it, so it doesn't stick around in /tmp
+pub(crate) struct SocketFile(PathBuf);
+
+impl Drop for SocketFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+// Picks a name that won't collide with other screens, or checks the one the user asked for
+fn socket_name(socket_addr: Option<String>) -> Result<(String, Option<SocketFile>)> {
+    let socket_addr = socket_addr
+        .unwrap_or_else(|| format!("{}-{:08x}", std::process::id(), rand::random::<u32>()));
+
+    match local_socket::NameTypeSupport::query() {
+        local_socket::NameTypeSupport::OnlyPaths => {
+            let path = format!("/tmp/libfm-screen-sock-{socket_addr}.sock");
+
+            if std::path::Path::new(&path).exists() {
+                // If nobody is listening the file was left behind by a screen that didn't
+                // shut down cleanly, and it's safe to get rid of
+                if local_socket::LocalSocketStream::connect(path.as_str()).is_ok() {
+                    return Err(Error::Launch(format!(
+                        "socket {path} is already in use by another screen"
+                    )));
+                }
+                std::fs::remove_file(&path)?;
+            }
+
+            let file = SocketFile(path.clone().into());
+            Ok((path, Some(file)))
+        }
+        local_socket::NameTypeSupport::Both | local_socket::NameTypeSupport::OnlyNamespaced => {
+            Ok((format!("@libfm-screen-sock-{socket_addr}.sock"), None))
+        }
+    }
+}
+
+enum LaunchFailure {
+    Exited(std::process::ExitStatus),
+    TimedOut(Duration),
+    Io(std::io::Error),
+}
+
+// Spawns the screen binary and waits for it to connect to us
+pub(crate) fn launch_process(
+    runtime: &tokio::runtime::Runtime,
+    blocker: &dyn Blocker,
+    screen_path: Option<PathBuf>,
+    socket_addr: Option<String>,
+    connect_timeout: Duration,
+    encoding: Encoding,
+) -> Result<(Backend, MessageSink, ReturnStream)> {
+    let screen_path = match screen_path {
+        Some(path) => {
+            discovery::check_screen(&path)?;
+            path
+        }
+        None => discovery::find_screen()?,
+    };
+
+    let (socket_addr, socket_file) = socket_name(socket_addr)?;
+
+    let listener = local_socket::tokio::LocalSocketListener::bind(socket_addr.clone())?;
+
+    let mut child = std::process::Command::new(&screen_path)
+        .arg(socket_addr)
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .map_err(|e| {
+            Error::Launch(format!(
+                "failed to launch screen {}: {e}",
+                screen_path.display()
+            ))
+        })?;
+    let (stderr, stderr_handle) = capture_stderr(&mut child);
+
+    // Keep an eye on the child while we wait, so a screen that crashes on startup
+    // doesn't leave us waiting forever
+    let accept = async {
+        let accept = listener.accept();
+        let deadline = tokio::time::sleep(connect_timeout);
+        let mut poll = tokio::time::interval(Duration::from_millis(50));
+        tokio::pin!(accept, deadline);
+
+        loop {
+            tokio::select! {
+                socket = &mut accept => break socket.map_err(LaunchFailure::Io),
+                _ = &mut deadline => break Err(LaunchFailure::TimedOut(connect_timeout)),
+                _ = poll.tick() => match child.try_wait() {
+                    Ok(Some(status)) => break Err(LaunchFailure::Exited(status)),
+                    Ok(None) => {}
+                    Err(e) => break Err(LaunchFailure::Io(e)),
+                },
+            }
+        }
+    };
+
+    let socket = match block_on(blocker, runtime, None, accept) {
+        Ok(Ok(socket)) => socket,
+        result => {
+            let _ = child.kill();
+            let _ = child.wait();
+            // The child's stderr closes once it's dead, so this will finish shortly
+            let _ = stderr_handle.join();
+
+            let reason = match result {
+                Ok(Err(LaunchFailure::Exited(status))) => {
+                    format!("screen process exited during startup ({status})")
+                }
+                Ok(Err(LaunchFailure::TimedOut(timeout))) => {
+                    format!("screen process did not connect within {timeout:?}")
+                }
+                Ok(Err(LaunchFailure::Io(e))) => {
+                    format!("failed to connect to screen process: {e}")
+                }
+                Err(e) => return Err(e),
+                Ok(Ok(_)) => unreachable!(),
+            };
+            let stderr = Vec::from(std::mem::take(&mut *stderr.lock())).join("\n");
+
+            return Err(Error::Launch(if stderr.is_empty() {
+                reason
+            } else {
+                format!("{reason}:\n{stderr}")
+            }));
+        }
+    };
+
+    let (writer, reader) = handshake(runtime, blocker, socket, connect_timeout, encoding)?;
+    Ok((Backend::Process { child, socket_file }, writer, reader))
+}
+
+// Connects to a screen that's already running with `libfm-screen --serve`
+pub(crate) fn launch_remote(
+    runtime: &tokio::runtime::Runtime,
+    blocker: &dyn Blocker,
+    socket_addr: Option<String>,
+    connect_timeout: Duration,
+    encoding: Encoding,
+) -> Result<(Backend, MessageSink, ReturnStream)> {
+    let socket_addr = socket_addr
+        .ok_or_else(|| Error::Launch("connecting to a screen needs a socket address".into()))?;
+
+    let socket = block_on(
+        blocker,
+        runtime,
+        Some(connect_timeout),
+        local_socket::tokio::LocalSocketStream::connect(socket_addr.as_str()),
+    )?
+    .map_err(|e| Error::Launch(format!("failed to connect to screen at {socket_addr}: {e}")))?;
+
+    let (writer, reader) = handshake(runtime, blocker, socket, connect_timeout, encoding)?;
+    Ok((Backend::Remote, writer, reader))
+}
+
+// Tells the screen which encoding we're going to use and waits for it to agree, then sets it
+// up on our end
+fn handshake(
+    runtime: &tokio::runtime::Runtime,
+    blocker: &dyn Blocker,
+    socket: local_socket::tokio::LocalSocketStream,
+    timeout: Duration,
+    encoding: Encoding,
+) -> Result<(MessageSink, ReturnStream)> {
+    let (reader, mut writer) = socket.into_split();
+    block_on(
+        blocker,
+        runtime,
+        Some(timeout),
+        writer.write_all(encoding.handshake().as_bytes()),
+    )??;
+
+    // Messages can follow the reply right away, so they have to be read from the same buffer
+    let mut reader = futures::io::BufReader::new(reader);
+    let mut reply = String::new();
+    block_on(
+        blocker,
+        runtime,
+        Some(timeout),
+        reader.read_line(&mut reply),
+    )??;
+    match Encoding::check_handshake_reply(&reply) {
+        Ok(()) => {}
+        Err(Rejection::Version(version)) => {
+            let error = ProtocolError::VersionMismatch(PROTOCOL_VERSION, version);
+            return Err(Error::Protocol(error));
+        }
+        Err(Rejection::Other(reason)) => {
+            return Err(Error::Connection(format!("screen rejected us: {reason}")))
+        }
+    }
+
+    let reader = encoding.reader(reader);
+    let writer = encoding
+        .writer(writer)
+        .sink_map_err(|e| Error::Connection(e.to_string()));
+
+    Ok((Box::pin(writer), reader))
+}
+
+// Runs the renderer on a thread of our own, talking to it over channels
+pub(crate) fn launch_in_process(
+    blocker: &dyn Blocker,
+) -> Result<(Backend, MessageSink, ReturnStream)> {
+    // Setting up the renderer waits on the GPU, which can take a moment
+    let mut renderer = None;
+    blocker.block(&mut || renderer = Some(screen::renderer::spawn_in_process()));
+    let renderer = renderer
+        .expect("blocker did not run the function")
+        .map_err(Error::Launch)?;
+
+    let writer = renderer
+        .sender
+        .clone()
+        .sink_map_err(|e| Error::Connection(e.to_string()));
+    Ok((
+        Backend::InProcess {
+            sender: renderer.sender,
+            thread: Some(renderer.thread),
+        },
+        Box::pin(writer),
+        Box::pin(renderer.receiver),
+    ))
+}
@@ -0,0 +1,54 @@
+// Copyright (C) 2023 Lily Lyons
+//
+// This file is part of libfm.
+//
+// libfm is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// libfm is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with libfm.  If not, see <http://www.gnu.org/licenses/>.
+
+//! A Rust client for the libfm screen.
+//!
+//! This launches (or embeds) the screen renderer and drives it over the [`Message`]
+//! protocol. The Ruby extension is a thin wrapper around it, but it works just as well on its own.
+#![warn(rust_2018_idioms, clippy::all)]
+
+mod discovery;
+mod error;
+mod handle;
+mod launch;
+mod panel;
+mod plane;
+mod screen;
+mod shader;
+mod shape;
+mod sprite;
+mod tilemap;
+mod window;
+
+pub use crate::discovery::{check_screen, find_screen};
+pub use crate::error::{Error, Result};
+pub use crate::panel::Panel;
+pub use crate::plane::Plane;
+pub use crate::screen::{Blocker, DefaultBlocker, Event, Mode, Screen, ScreenConfig, Stats};
+pub use crate::shader::Shader;
+pub use crate::shape::Shape;
+pub use crate::sprite::Sprite;
+pub use crate::tilemap::Tilemap;
+pub use crate::window::{Window, WindowState};
+
+pub use ::screen::wire::Encoding;
+pub use ::screen::{
+    BlendType, Color, EdgeMode, Effect, FrameConfig, Message, PanelConfig, PlaneConfig,
+    PresentMode, ProtocolError, Rect, ReturnMessage, ShaderError, ShapeConfig, ShapeGeometry,
+    TileData, TileInfo, TileUpdate, TilemapConfig, Tone, WindowConfig, SHADER_PRELUDE,
+    SHADER_UNIFORMS,
+};
@@ -0,0 +1,105 @@
+// Copyright (C) 2023 Lily Lyons
+//
+// This file is part of libfm.
+//
+// libfm is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// libfm is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with libfm.  If not, see <http://www.gnu.org/licenses/>.
+
+use parking_lot::Mutex;
+use screen::{Message, PanelConfig};
+use std::path::Path;
+
+use crate::handle::{self, Handle, Kind};
+use crate::{Result, Window};
+
+/// An RGSS style window drawn from a skin, with a cursor and a contents image, for menus and
+/// message boxes. It's disposed when dropped, or when its window is.
+pub struct Panel {
+    handle: Handle<Panel>,
+    config: Mutex<PanelConfig>,
+}
+
+impl Kind for Panel {
+    const NAME: &'static str = "panel";
+
+    fn remove(id: usize, window_id: usize) -> Message {
+        Message::RemovePanel(id, window_id)
+    }
+}
+
+impl Panel {
+    pub fn new(window: &Window) -> Result<Self> {
+        let handle = Handle::new(window, Message::CreatePanel)?;
+
+        Ok(Self {
+            handle,
+            config: Mutex::default(),
+        })
+    }
+
+    pub fn id(&self) -> usize {
+        self.handle.id()
+    }
+
+    pub fn window_id(&self) -> usize {
+        self.handle.window_id()
+    }
+
+    pub fn is_disposed(&self) -> bool {
+        self.handle.is_disposed()
+    }
+
+    /// Sets the skin the panel is drawn from, loaded by the screen from `path`.
+    pub fn set_skin(&self, path: impl AsRef<Path>) -> Result<()> {
+        self.handle.check_disposed()?;
+        let path = handle::asset_path(path.as_ref())?;
+
+        self.handle
+            .send(|id, window_id| Message::SetPanelSkin(id, window_id, path))
+    }
+
+    /// Sets the image drawn inside the panel's padding.
+    pub fn set_contents(&self, path: impl AsRef<Path>) -> Result<()> {
+        self.handle.check_disposed()?;
+        let path = handle::asset_path(path.as_ref())?;
+
+        self.handle
+            .send(|id, window_id| Message::SetPanelContents(id, window_id, Some(path)))
+    }
+
+    pub fn clear_contents(&self) -> Result<()> {
+        self.handle.check_disposed()?;
+        self.handle
+            .send(|id, window_id| Message::SetPanelContents(id, window_id, None))
+    }
+
+    pub fn config(&self) -> Result<PanelConfig> {
+        self.handle.check_disposed()?;
+        Ok(*self.config.lock())
+    }
+
+    /// Changes the panel's config with `func` and sends the result to the screen.
+    pub fn configure(&self, func: impl FnOnce(&mut PanelConfig)) -> Result<()> {
+        self.handle.check_disposed()?;
+        let mut config = self.config.lock();
+        func(&mut config);
+
+        self.handle
+            .send(|id, window_id| Message::ConfigurePanel(id, window_id, *config))
+    }
+
+    /// Removes the panel from its window. Disposing it again does nothing.
+    pub fn dispose(&self) -> Result<()> {
+        self.handle.dispose()
+    }
+}
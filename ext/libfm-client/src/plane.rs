@@ -0,0 +1,90 @@
+// Copyright (C) 2023 Lily Lyons
+//
+// This file is part of libfm.
+//
+// libfm is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// libfm is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with libfm.  If not, see <http://www.gnu.org/licenses/>.
+
+use parking_lot::Mutex;
+use screen::{Message, PlaneConfig};
+use std::path::Path;
+
+use crate::handle::{self, Handle, Kind};
+use crate::{Result, Window};
+
+/// An image tiled across a whole window, for scrolling backgrounds. It's disposed when dropped,
+/// or when its window is.
+pub struct Plane {
+    handle: Handle<Plane>,
+    config: Mutex<PlaneConfig>,
+}
+
+impl Kind for Plane {
+    const NAME: &'static str = "plane";
+
+    fn remove(id: usize, window_id: usize) -> Message {
+        Message::RemovePlane(id, window_id)
+    }
+}
+
+impl Plane {
+    pub fn new(window: &Window) -> Result<Self> {
+        let handle = Handle::new(window, Message::CreatePlane)?;
+
+        Ok(Self {
+            handle,
+            config: Mutex::default(),
+        })
+    }
+
+    pub fn id(&self) -> usize {
+        self.handle.id()
+    }
+
+    pub fn window_id(&self) -> usize {
+        self.handle.window_id()
+    }
+
+    pub fn is_disposed(&self) -> bool {
+        self.handle.is_disposed()
+    }
+
+    /// Sets the image the plane tiles, loaded by the screen from `path`.
+    pub fn set(&self, path: impl AsRef<Path>) -> Result<()> {
+        self.handle.check_disposed()?;
+        let path = handle::asset_path(path.as_ref())?;
+
+        self.handle
+            .send(|id, window_id| Message::SetPlane(id, window_id, path))
+    }
+
+    pub fn config(&self) -> Result<PlaneConfig> {
+        self.handle.check_disposed()?;
+        Ok(*self.config.lock())
+    }
+
+    /// Changes the plane's config with `func` and sends the result to the screen.
+    pub fn configure(&self, func: impl FnOnce(&mut PlaneConfig)) -> Result<()> {
+        self.handle.check_disposed()?;
+        let mut config = self.config.lock();
+        func(&mut config);
+
+        self.handle
+            .send(|id, window_id| Message::ConfigurePlane(id, window_id, *config))
+    }
+
+    /// Removes the plane from its window. Disposing it again does nothing.
+    pub fn dispose(&self) -> Result<()> {
+        self.handle.dispose()
+    }
+}
@@ -0,0 +1,605 @@
+// Copyright (C) 2023 Lily Lyons
+//
+// This file is part of libfm.
+//
+// libfm is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// libfm is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with libfm.  If not, see <http://www.gnu.org/licenses/>.
+
+use futures::prelude::*;
+use parking_lot::Mutex;
+use screen::record::Recorder;
+use screen::wire::Encoding;
+use screen::{FrameConfig, FrameStats, Message, ReturnMessage};
+
+use std::collections::{HashMap, VecDeque};
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::runtime::Runtime;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
+use tokio::sync::MutexGuard;
+
+use crate::launch::{self, Backend, MessageSink};
+use crate::{Error, Result, WindowState};
+
+// How many stats reports from the screen are averaged together
+const STATS_WINDOW: usize = 10;
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How the client waits whenever it has to block on the screen.
+///
+/// Embedders that need to do something while blocked, like releasing an interpreter lock or
+/// waking up for signals, can provide their own with [`Screen::with_blocker`].
+pub trait Blocker: Send + Sync {
+    /// Drives `future` to completion on `runtime`.
+    ///
+    /// Returning early leaves the operation unfinished, and it fails with [`Error::Interrupted`].
+    fn block_on(&self, runtime: &Runtime, future: Pin<&mut dyn Future<Output = ()>>) -> Result<()>;
+
+    /// Calls `func`, which may block the thread for a while.
+    fn block(&self, func: &mut dyn FnMut());
+}
+
+/// Blocks the current thread with nothing special going on.
+pub struct DefaultBlocker;
+
+impl Blocker for DefaultBlocker {
+    fn block_on(&self, runtime: &Runtime, future: Pin<&mut dyn Future<Output = ()>>) -> Result<()> {
+        runtime.block_on(future);
+        Ok(())
+    }
+
+    fn block(&self, func: &mut dyn FnMut()) {
+        func()
+    }
+}
+
+pub(crate) fn block_on<F: Future>(
+    blocker: &dyn Blocker,
+    runtime: &Runtime,
+    timeout: Option<Duration>,
+    future: F,
+) -> Result<F::Output> {
+    let mut output = None;
+    {
+        let future = async {
+            output = Some(match timeout {
+                Some(timeout) => tokio::time::timeout(timeout, future)
+                    .await
+                    .map_err(|_| Error::TimedOut(timeout)),
+                None => Ok(future.await),
+            });
+        };
+        tokio::pin!(future);
+        blocker.block_on(runtime, future)?;
+    }
+
+    output.unwrap_or(Err(Error::Interrupted))
+}
+
+/// Where the renderer runs.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// A separate screen process, talking over a local socket.
+    #[default]
+    Process,
+    /// A thread inside this process. Only supported where winit can run off the main thread.
+    InProcess,
+    /// A screen already running with `libfm-screen --serve`, at [`ScreenConfig::socket_addr`].
+    /// Other clients can be using it at the same time.
+    Connect,
+}
+
+#[derive(Debug, Clone)]
+pub struct ScreenConfig {
+    pub mode: Mode,
+    /// The screen binary to launch. Searched for with [`crate::find_screen`] if not set.
+    pub screen_path: Option<PathBuf>,
+    /// The name of the socket to talk over. A unique one is picked if not set.
+    /// When connecting to a running screen this is its full socket name.
+    pub socket_addr: Option<String>,
+    /// When connecting to a running screen, the settings of whichever client set them first
+    /// are kept, and different ones are rejected with [`screen::ProtocolError::FramesOwned`].
+    pub frames: FrameConfig,
+    /// Queue messages until the next [`Screen::flush`] instead of sending them right away.
+    /// Off by default, since errors from a batch only arrive once the whole batch is sent.
+    pub batch: bool,
+    /// How long to wait on the screen before giving up, when sending or waiting on a reply.
+    ///
+    /// A send that times out (or is interrupted) after the screen took part of the message
+    /// still delivers the rest ahead of the next send. If the screen has stopped reading
+    /// altogether, every send after that times out too.
+    pub timeout: Option<Duration>,
+    /// How long to wait for the screen to connect after launching it.
+    pub connect_timeout: Duration,
+    /// Records everything sent to and received from the screen into this file.
+    /// It can be played back with `libfm-screen --replay`.
+    pub record: Option<PathBuf>,
+    /// How messages are encoded on the socket. Defaults to the `LIBFM_ENCODING` environment
+    /// variable if it's set to a known encoding, otherwise bincode.
+    pub encoding: Encoding,
+}
+
+impl Default for ScreenConfig {
+    fn default() -> Self {
+        Self {
+            mode: Mode::default(),
+            screen_path: None,
+            socket_addr: None,
+            frames: FrameConfig::default(),
+            batch: false,
+            timeout: None,
+            connect_timeout: CONNECT_TIMEOUT,
+            record: None,
+            encoding: std::env::var("LIBFM_ENCODING")
+                .ok()
+                .and_then(|name| Encoding::from_name(&name))
+                .unwrap_or_default(),
+        }
+    }
+}
+
+/// Per frame averages over the last few stats reports from the screen.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Stats {
+    pub fps: f64,
+    pub cpu_time: f64,
+    pub gpu_time: f64,
+    pub draw_calls: f64,
+    pub sprites_drawn: f64,
+    pub messages: f64,
+    pub texture_memory: u64,
+}
+
+/// Something that happened on the screen, picked up while handling its messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    /// The user asked to close the window with this id.
+    CloseRequested(usize),
+}
+
+type SharedRecorder = Arc<Mutex<Recorder>>;
+
+fn record(
+    recorder: &Option<SharedRecorder>,
+    record: impl FnOnce(&mut Recorder) -> std::io::Result<()>,
+) {
+    if let Some(recorder) = recorder {
+        if let Err(e) = record(&mut recorder.lock()) {
+            eprintln!("failed to record message: {e}");
+        }
+    }
+}
+
+pub(crate) struct Inner {
+    backend: Backend,
+    reader_handle: tokio::task::JoinHandle<()>,
+    blocker: Arc<dyn Blocker>,
+
+    writer: MessageSink,
+    runtime: Arc<Runtime>,
+    message_recv: UnboundedReceiver<ReturnMessage>,
+    timeout: Option<Duration>,
+    recorder: Option<SharedRecorder>,
+
+    // Messages waiting to be sent as a single batch on the next flush
+    queue: Vec<Message>,
+    // Removals from objects that were dropped, which go out ahead of the next message
+    dropped: Arc<Mutex<Vec<Message>>>,
+    // Messages queued or handed to the writer so far, even if writing them didn't finish
+    accepted: u64,
+    queue_messages: bool,
+    batch_depth: u32,
+
+    frame: u64,
+    delta: f64,
+    stats: VecDeque<FrameStats>,
+    events: VecDeque<Event>,
+    pub(crate) windows: HashMap<usize, WindowState>,
+    /// Failures the screen reported back, raised by the next [`Screen::process_events`].
+    errors: VecDeque<Error>,
+    next_id: usize,
+}
+
+impl Inner {
+    pub(crate) fn send(&mut self, message: Message) -> Result<()> {
+        let mut dropped = self.take_dropped();
+        if self.queue_messages || self.batch_depth > 0 {
+            self.queue.extend(dropped);
+            self.queue.push(message);
+            self.accepted += 1;
+            return Ok(());
+        }
+
+        if dropped.is_empty() {
+            self.write(message)
+        } else {
+            dropped.push(message);
+            self.write(Message::Batch(dropped))
+        }
+    }
+
+    pub(crate) fn flush(&mut self) -> Result<()> {
+        let dropped = self.take_dropped();
+        self.queue.extend(dropped);
+        if self.queue.is_empty() {
+            return Ok(());
+        }
+
+        let messages = std::mem::take(&mut self.queue);
+        self.write(Message::Batch(messages))
+    }
+
+    /// Sends `message` like [`Inner::send`], returning whether it's on its way even if sending
+    /// failed, since a write that gives up partway still finishes with the next one.
+    pub(crate) fn send_accepted(&mut self, message: Message) -> (bool, Result<()>) {
+        let accepted = self.accepted;
+        let result = self.send(message);
+        (self.accepted > accepted, result)
+    }
+
+    fn take_dropped(&mut self) -> Vec<Message> {
+        let dropped = std::mem::take(&mut *self.dropped.lock());
+        for message in &dropped {
+            if let Message::DeleteWindow(id) = message {
+                self.windows.remove(id);
+            }
+        }
+        dropped
+    }
+
+    fn write(&mut self, message: Message) -> Result<()> {
+        let Self {
+            blocker,
+            runtime,
+            writer,
+            timeout,
+            ..
+        } = self;
+        // Nothing has been written yet, so giving up here leaves the connection as it was
+        let ready = future::poll_fn(|cx| writer.poll_ready_unpin(cx));
+        let ready = block_on(&**blocker, runtime, *timeout, ready)?;
+        ready.map_err(|e| self.connection_error(e))?;
+
+        record(&self.recorder, |r| r.record_sent(&message));
+        let sent = self.writer.start_send_unpin(message);
+        sent.map_err(|e| self.connection_error(e))?;
+        self.accepted += 1;
+
+        // The writer holds on to whatever part of the message it couldn't write yet and finishes
+        // it before anything sent after, so giving up here never cuts a message in two
+        let Self {
+            blocker,
+            runtime,
+            writer,
+            timeout,
+            ..
+        } = self;
+        let flushed = block_on(&**blocker, runtime, *timeout, writer.flush())?;
+        flushed.map_err(|e| self.connection_error(e))
+    }
+
+    // A broken connection usually means the screen died, which is more useful to report
+    fn connection_error(&mut self, error: Error) -> Error {
+        match error {
+            Error::Connection(_) | Error::Io(_) => self.backend.death().unwrap_or(error),
+            error => error,
+        }
+    }
+
+    pub(crate) fn recv(&mut self) -> Result<ReturnMessage> {
+        let Self {
+            blocker,
+            runtime,
+            message_recv,
+            timeout,
+            ..
+        } = self;
+        match block_on(&**blocker, runtime, *timeout, message_recv.recv())? {
+            Some(message) => Ok(message),
+            None => Err(self.connection_error(Error::Connection(
+                "screen closed the connection".to_string(),
+            ))),
+        }
+    }
+
+    pub(crate) fn take_error(&mut self) -> Result<()> {
+        match self.errors.pop_front() {
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
+    }
+
+    /// Ids are never reused, so a stale handle can't affect an object created after it.
+    pub(crate) fn next_id(&mut self) -> usize {
+        self.next_id += 1;
+        self.next_id
+    }
+
+    pub(crate) fn handle_message(&mut self, message: ReturnMessage) {
+        match message {
+            ReturnMessage::Frame(frame, delta) => {
+                self.frame = frame;
+                self.delta = delta;
+            }
+            ReturnMessage::Stats(stats) => {
+                if self.stats.len() == STATS_WINDOW {
+                    self.stats.pop_front();
+                }
+                self.stats.push_back(stats);
+            }
+            ReturnMessage::CloseRequested(id) => self.events.push_back(Event::CloseRequested(id)),
+            ReturnMessage::Moved(id, x, y) => {
+                if let Some(state) = self.windows.get_mut(&id) {
+                    (state.x, state.y) = (x, y);
+                }
+            }
+            ReturnMessage::Resized(id, width, height) => {
+                if let Some(state) = self.windows.get_mut(&id) {
+                    (state.width, state.height) = (width, height);
+                }
+            }
+            ReturnMessage::Focused(id, focused) => {
+                if let Some(state) = self.windows.get_mut(&id) {
+                    state.focused = focused;
+                }
+            }
+            ReturnMessage::Error(error) => self.errors.push_back(Error::Protocol(error)),
+            ReturnMessage::LoadFailed(path, reason) => self.errors.push_back(Error::AssetLoad {
+                path: path.into(),
+                reason,
+            }),
+            message => eprintln!("{message:?}"),
+        }
+    }
+}
+
+impl Drop for Inner {
+    fn drop(&mut self) {
+        self.backend.shutdown();
+        self.reader_handle.abort();
+    }
+}
+
+/// A connection to a screen. Cloning it gives another handle to the same screen.
+#[derive(Clone)]
+pub struct Screen {
+    // An async mutex, so waiting on another thread's call to the screen can be interrupted
+    inner: Arc<tokio::sync::Mutex<Inner>>,
+    // Shared with `Inner`, so dropping an object never has to wait on the screen
+    dropped: Arc<Mutex<Vec<Message>>>,
+    blocker: Arc<dyn Blocker>,
+    runtime: Arc<Runtime>,
+}
+
+impl Screen {
+    /// Starts a screen and waits for it to be ready.
+    pub fn new(config: ScreenConfig) -> Result<Self> {
+        Self::with_blocker(config, Arc::new(DefaultBlocker))
+    }
+
+    /// Like [`Screen::new`], but waits on the screen with `blocker`.
+    pub fn with_blocker(config: ScreenConfig, blocker: Arc<dyn Blocker>) -> Result<Self> {
+        // The reader task needs a worker of its own, since we only drive the runtime while blocking
+        let runtime = Arc::new(
+            tokio::runtime::Builder::new_multi_thread()
+                .worker_threads(1)
+                .enable_io()
+                .enable_time()
+                .build()?,
+        );
+        let _g = runtime.enter();
+
+        let (backend, mut writer, mut reader) = match config.mode {
+            Mode::Process => launch::launch_process(
+                &runtime,
+                &*blocker,
+                config.screen_path,
+                config.socket_addr,
+                config.connect_timeout,
+                config.encoding,
+            )?,
+            Mode::InProcess => launch::launch_in_process(&*blocker)?,
+            Mode::Connect => launch::launch_remote(
+                &runtime,
+                &*blocker,
+                config.socket_addr,
+                config.connect_timeout,
+                config.encoding,
+            )?,
+        };
+
+        let recorder = match config.record {
+            Some(path) => Some(Arc::new(Mutex::new(Recorder::create(path)?))),
+            None => None,
+        };
+
+        let configure = Message::ConfigureFrames(config.frames);
+        record(&recorder, |r| r.record_sent(&configure));
+        block_on(&*blocker, &runtime, config.timeout, writer.send(configure))??;
+        let (message_send, message_recv) = unbounded_channel();
+
+        let dropped = Arc::default();
+        let reader_recorder = recorder.clone();
+        let reader_handle = runtime.spawn(async move {
+            while let Some(message) = reader.next().await {
+                record(&reader_recorder, |r| r.record_received(&message));
+                message_send.send(message).expect("failed to send message");
+            }
+        });
+
+        Ok(Self {
+            inner: Arc::new(tokio::sync::Mutex::new(Inner {
+                backend,
+                reader_handle,
+                blocker: blocker.clone(),
+                writer,
+                runtime: runtime.clone(),
+                message_recv,
+                timeout: config.timeout,
+                recorder,
+                queue: Vec::new(),
+                dropped: Arc::clone(&dropped),
+                accepted: 0,
+                queue_messages: config.batch,
+                batch_depth: 0,
+                frame: 0,
+                delta: 0.0,
+                stats: VecDeque::with_capacity(STATS_WINDOW),
+                events: VecDeque::new(),
+                windows: HashMap::new(),
+                errors: VecDeque::new(),
+                next_id: 0,
+            })),
+            dropped,
+            blocker,
+            runtime,
+        })
+    }
+
+    pub fn is_alive(&self) -> Result<bool> {
+        let mut inner = self.lock()?;
+        // The reader stops once the screen hangs up on us
+        Ok(!inner.reader_handle.is_finished() && inner.backend.is_alive())
+    }
+
+    /// Sends `message`, or queues it if batching is on.
+    pub fn send(&self, message: Message) -> Result<()> {
+        self.lock()?.send(message)
+    }
+
+    /// Sends everything queued up so far as one batch.
+    pub fn flush(&self) -> Result<()> {
+        self.lock()?.flush()
+    }
+
+    /// Flushes the queue and handles whatever the screen has sent us, without waiting.
+    ///
+    /// Messages the screen rejected since the last call are reported here, one at a time.
+    pub fn process_events(&self) -> Result<()> {
+        let mut inner = self.lock()?;
+        inner.flush()?;
+        while let Ok(message) = inner.message_recv.try_recv() {
+            inner.handle_message(message);
+        }
+
+        inner.take_error()
+    }
+
+    /// Takes the oldest event handled so far, if there is one.
+    pub fn poll_event(&self) -> Result<Option<Event>> {
+        Ok(self.lock()?.events.pop_front())
+    }
+
+    /// Like RGSS's Graphics.update, this waits for the next frame boundary the screen reports.
+    pub fn update(&self) -> Result<()> {
+        self.process_events()?;
+
+        let mut inner = self.lock()?;
+        loop {
+            let message = inner.recv()?;
+            let is_frame = matches!(message, ReturnMessage::Frame(..));
+            inner.handle_message(message);
+
+            if is_frame {
+                break inner.take_error();
+            }
+        }
+    }
+
+    /// Everything sent until the matching [`Screen::end_batch`] reaches the screen as one batch.
+    pub fn begin_batch(&self) -> Result<()> {
+        self.lock()?.batch_depth += 1;
+        Ok(())
+    }
+
+    pub fn end_batch(&self) -> Result<()> {
+        let mut inner = self.lock()?;
+        inner.batch_depth = inner.batch_depth.saturating_sub(1);
+        if inner.batch_depth == 0 {
+            inner.flush()?;
+        }
+
+        Ok(())
+    }
+
+    /// Runs `func`, sending everything it sends to the screen as one batch.
+    pub fn batch<R>(&self, func: impl FnOnce() -> R) -> Result<R> {
+        self.begin_batch()?;
+        let result = func();
+        self.end_batch()?;
+
+        Ok(result)
+    }
+
+    pub fn frame_count(&self) -> Result<u64> {
+        Ok(self.lock()?.frame)
+    }
+
+    pub fn delta(&self) -> Result<f64> {
+        Ok(self.lock()?.delta)
+    }
+
+    pub fn stats(&self) -> Result<Stats> {
+        self.process_events()?;
+
+        let inner = self.lock()?;
+        let total = inner
+            .stats
+            .iter()
+            .fold(FrameStats::default(), |total, stats| FrameStats {
+                frames: total.frames + stats.frames,
+                elapsed: total.elapsed + stats.elapsed,
+                cpu_time: total.cpu_time + stats.cpu_time,
+                gpu_time: total.gpu_time + stats.gpu_time,
+                draw_calls: total.draw_calls + stats.draw_calls,
+                sprites_drawn: total.sprites_drawn + stats.sprites_drawn,
+                messages: total.messages + stats.messages,
+                texture_memory: stats.texture_memory,
+            });
+        let frames = total.frames.max(1) as f64;
+
+        Ok(Stats {
+            fps: if total.elapsed > 0.0 {
+                total.frames as f64 / total.elapsed
+            } else {
+                0.0
+            },
+            cpu_time: total.cpu_time / frames,
+            gpu_time: total.gpu_time / frames,
+            draw_calls: total.draw_calls as f64 / frames,
+            sprites_drawn: total.sprites_drawn as f64 / frames,
+            messages: total.messages as f64 / frames,
+            texture_memory: total.texture_memory,
+        })
+    }
+
+    /// Sends `message` along with whatever is sent next, without waiting on the screen.
+    ///
+    /// Objects are removed this way when they're dropped, since that can happen where blocking
+    /// isn't allowed, like while Ruby is collecting garbage.
+    pub(crate) fn send_later(&self, message: Message) {
+        self.dropped.lock().push(message);
+    }
+
+    /// Waits for other threads to be done with the screen. Fails with [`Error::Interrupted`] if
+    /// the blocker gives up first.
+    pub(crate) fn lock(&self) -> Result<MutexGuard<'_, Inner>> {
+        match self.inner.try_lock() {
+            Ok(guard) => Ok(guard),
+            // Another thread may be holding the lock while it waits on the screen
+            Err(_) => block_on(&*self.blocker, &self.runtime, None, self.inner.lock()),
+        }
+    }
+}
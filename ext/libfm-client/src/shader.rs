@@ -0,0 +1,100 @@
+// Copyright (C) 2023 Lily Lyons
+//
+// This file is part of libfm.
+//
+// libfm is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// libfm is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with libfm.  If not, see <http://www.gnu.org/licenses/>.
+
+use parking_lot::Mutex;
+use screen::{Message, ProtocolError, SHADER_UNIFORMS};
+
+use crate::handle::{Handle, Kind};
+use crate::{Error, Result, Window};
+
+/// A fragment shader that sprites or a whole window can be drawn with. The source is compiled
+/// after [`crate::SHADER_PRELUDE`], which documents what it can use.
+///
+/// It's disposed when dropped, or when its window is. Anything drawn with it goes back to being
+/// drawn without a shader.
+pub struct Shader {
+    handle: Handle<Shader>,
+    uniforms: Mutex<[f32; SHADER_UNIFORMS]>,
+}
+
+impl Kind for Shader {
+    const NAME: &'static str = "shader";
+
+    fn remove(id: usize, window_id: usize) -> Message {
+        Message::RemoveShader(id, window_id)
+    }
+}
+
+impl Shader {
+    /// Validates `source` before sending it to the screen, so compile errors are returned here.
+    pub fn new(window: &Window, source: impl Into<String>) -> Result<Self> {
+        let source = source.into();
+        screen::validate_shader(&source).map_err(|e| Error::Shader(e.to_string()))?;
+        let handle = Handle::new(window, |id, window_id| {
+            Message::CreateShader(id, window_id, source)
+        })?;
+
+        Ok(Self {
+            handle,
+            uniforms: Mutex::new([0.0; SHADER_UNIFORMS]),
+        })
+    }
+
+    pub fn id(&self) -> usize {
+        self.handle.id()
+    }
+
+    pub fn window_id(&self) -> usize {
+        self.handle.window_id()
+    }
+
+    pub fn is_disposed(&self) -> bool {
+        self.handle.is_disposed()
+    }
+
+    // Shaders can only be used on the window they were made for. Returns the id to use
+    pub(crate) fn check_window(&self, window_id: usize) -> Result<usize> {
+        self.handle.check_disposed()?;
+        if self.handle.window_id() != window_id {
+            let error = ProtocolError::UnknownShader(self.handle.id(), window_id);
+            return Err(Error::Protocol(error));
+        }
+
+        Ok(self.handle.id())
+    }
+
+    pub fn uniforms(&self) -> Result<[f32; SHADER_UNIFORMS]> {
+        self.handle.check_disposed()?;
+        Ok(*self.uniforms.lock())
+    }
+
+    /// Sets `globals.user` for everything drawn with the shader.
+    pub fn set_uniforms(&self, uniforms: [f32; SHADER_UNIFORMS]) -> Result<()> {
+        self.handle.check_disposed()?;
+        let mut current = self.uniforms.lock();
+        self.handle
+            .send(|id, window_id| Message::SetShaderUniforms(id, window_id, uniforms))?;
+        *current = uniforms;
+
+        Ok(())
+    }
+
+    /// Removes the shader from its window. Disposing it again does nothing.
+    pub fn dispose(&self) -> Result<()> {
+        self.handle.dispose()
+    }
+}
@@ -0,0 +1,87 @@
+// Copyright (C) 2023 Lily Lyons
+//
+// This file is part of libfm.
+//
+// libfm is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// libfm is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with libfm.  If not, see <http://www.gnu.org/licenses/>.
+
+use parking_lot::Mutex;
+use screen::{Message, ShapeConfig, ShapeGeometry};
+
+use crate::handle::{Handle, Kind};
+use crate::{Result, Window};
+
+/// A rect, line, circle or polygon drawn in a solid color, sorted by z with sprites. It's disposed
+/// when dropped, or when its window is.
+pub struct Shape {
+    handle: Handle<Shape>,
+    config: Mutex<ShapeConfig>,
+}
+
+impl Kind for Shape {
+    const NAME: &'static str = "shape";
+
+    fn remove(id: usize, window_id: usize) -> Message {
+        Message::RemoveShape(id, window_id)
+    }
+}
+
+impl Shape {
+    pub fn new(window: &Window) -> Result<Self> {
+        let handle = Handle::new(window, Message::CreateShape)?;
+
+        Ok(Self {
+            handle,
+            config: Mutex::default(),
+        })
+    }
+
+    pub fn id(&self) -> usize {
+        self.handle.id()
+    }
+
+    pub fn window_id(&self) -> usize {
+        self.handle.window_id()
+    }
+
+    pub fn is_disposed(&self) -> bool {
+        self.handle.is_disposed()
+    }
+
+    /// Sets what the shape draws. Nothing is drawn until this is called.
+    pub fn set_geometry(&self, geometry: ShapeGeometry) -> Result<()> {
+        self.handle.check_disposed()?;
+        self.handle
+            .send(|id, window_id| Message::SetShape(id, window_id, geometry))
+    }
+
+    pub fn config(&self) -> Result<ShapeConfig> {
+        self.handle.check_disposed()?;
+        Ok(*self.config.lock())
+    }
+
+    /// Changes the shape's config with `func` and sends the result to the screen.
+    pub fn configure(&self, func: impl FnOnce(&mut ShapeConfig)) -> Result<()> {
+        self.handle.check_disposed()?;
+        let mut config = self.config.lock();
+        func(&mut config);
+
+        self.handle
+            .send(|id, window_id| Message::ConfigureShape(id, window_id, *config))
+    }
+
+    /// Removes the shape from its window. Disposing it again does nothing.
+    pub fn dispose(&self) -> Result<()> {
+        self.handle.dispose()
+    }
+}
@@ -0,0 +1,99 @@
+// Copyright (C) 2023 Lily Lyons
+//
+// This file is part of libfm.
+//
+// libfm is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// libfm is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with libfm.  If not, see <http://www.gnu.org/licenses/>.
+
+use parking_lot::Mutex;
+use screen::Message;
+use std::path::Path;
+
+use crate::handle::{self, Handle, Kind};
+use crate::{Result, Shader, Window};
+
+/// An image drawn on a window. It's disposed when dropped, or when its window is.
+pub struct Sprite {
+    handle: Handle<Sprite>,
+    position: Mutex<(i32, i32, i32)>,
+}
+
+impl Kind for Sprite {
+    const NAME: &'static str = "sprite";
+
+    fn remove(id: usize, window_id: usize) -> Message {
+        Message::RemoveSprite(id, window_id)
+    }
+}
+
+impl Sprite {
+    pub fn new(window: &Window) -> Result<Self> {
+        let handle = Handle::new(window, Message::CreateSprite)?;
+
+        Ok(Self {
+            handle,
+            position: Mutex::new((0, 0, 0)),
+        })
+    }
+
+    pub fn id(&self) -> usize {
+        self.handle.id()
+    }
+
+    pub fn window_id(&self) -> usize {
+        self.handle.window_id()
+    }
+
+    pub fn is_disposed(&self) -> bool {
+        self.handle.is_disposed()
+    }
+
+    /// Sets the image the sprite draws, loaded by the screen from `path`.
+    pub fn set(&self, path: impl AsRef<Path>) -> Result<()> {
+        self.handle.check_disposed()?;
+        let path = handle::asset_path(path.as_ref())?;
+
+        self.handle
+            .send(|id, window_id| Message::SetSprite(id, window_id, path))
+    }
+
+    pub fn reposition(&self, x: i32, y: i32, z: i32) -> Result<()> {
+        self.handle.check_disposed()?;
+        *self.position.lock() = (x, y, z);
+
+        self.handle
+            .send(|id, window_id| Message::RepositionSprite(id, window_id, x, y, z))
+    }
+
+    pub fn position(&self) -> Result<(i32, i32, i32)> {
+        self.handle.check_disposed()?;
+        Ok(*self.position.lock())
+    }
+
+    /// Draws the sprite with `shader`, or the default shader if it's `None`. The shader has to
+    /// be on the same window.
+    pub fn set_shader(&self, shader: Option<&Shader>) -> Result<()> {
+        self.handle.check_disposed()?;
+        let shader_id = shader
+            .map(|shader| shader.check_window(self.handle.window_id()))
+            .transpose()?;
+
+        self.handle
+            .send(|id, window_id| Message::SetSpriteShader(id, window_id, shader_id))
+    }
+
+    /// Removes the sprite from its window. Disposing it again does nothing.
+    pub fn dispose(&self) -> Result<()> {
+        self.handle.dispose()
+    }
+}
@@ -0,0 +1,157 @@
+// Copyright (C) 2023 Lily Lyons
+//
+// This file is part of libfm.
+//
+// libfm is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// libfm is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with libfm.  If not, see <http://www.gnu.org/licenses/>.
+
+use parking_lot::Mutex;
+use screen::{Message, ProtocolError, TileData, TileInfo, TileUpdate, TilemapConfig};
+use std::path::Path;
+
+use crate::handle::{self, Handle, Kind};
+use crate::{Error, Result, Window};
+
+/// A grid of tiles drawn from a tileset image. Tiles with a priority are drawn in between
+/// sprites, so they can cover them. It's disposed when dropped, or when its window is.
+pub struct Tilemap {
+    handle: Handle<Tilemap>,
+    config: Mutex<TilemapConfig>,
+    data: Mutex<TileData>,
+}
+
+impl Kind for Tilemap {
+    const NAME: &'static str = "tilemap";
+
+    fn remove(id: usize, window_id: usize) -> Message {
+        Message::RemoveTilemap(id, window_id)
+    }
+}
+
+impl Tilemap {
+    pub fn new(window: &Window) -> Result<Self> {
+        let handle = Handle::new(window, Message::CreateTilemap)?;
+
+        Ok(Self {
+            handle,
+            config: Mutex::default(),
+            data: Mutex::default(),
+        })
+    }
+
+    pub fn id(&self) -> usize {
+        self.handle.id()
+    }
+
+    pub fn window_id(&self) -> usize {
+        self.handle.window_id()
+    }
+
+    pub fn is_disposed(&self) -> bool {
+        self.handle.is_disposed()
+    }
+
+    fn out_of_bounds(&self) -> Error {
+        Error::Protocol(ProtocolError::TilesOutOfBounds(
+            self.handle.id(),
+            self.handle.window_id(),
+        ))
+    }
+
+    /// Sets the image tiles are cut from, loaded by the screen from `path`.
+    pub fn set_tileset(&self, path: impl AsRef<Path>) -> Result<()> {
+        self.handle.check_disposed()?;
+        let path = handle::asset_path(path.as_ref())?;
+
+        self.handle
+            .send(|id, window_id| Message::SetTileset(id, window_id, path))
+    }
+
+    pub fn config(&self) -> Result<TilemapConfig> {
+        self.handle.check_disposed()?;
+        Ok(*self.config.lock())
+    }
+
+    /// Changes the tilemap's config with `func` and sends the result to the screen.
+    pub fn configure(&self, func: impl FnOnce(&mut TilemapConfig)) -> Result<()> {
+        self.handle.check_disposed()?;
+        let mut config = self.config.lock();
+        func(&mut config);
+
+        self.handle
+            .send(|id, window_id| Message::ConfigureTilemap(id, window_id, *config))
+    }
+
+    /// A copy of every tile on the map.
+    pub fn data(&self) -> Result<TileData> {
+        self.handle.check_disposed()?;
+        Ok(self.data.lock().clone())
+    }
+
+    /// Replaces the whole map. `data.tiles` must hold exactly one tile per position and layer.
+    pub fn set_data(&self, data: TileData) -> Result<()> {
+        self.handle.check_disposed()?;
+        let len = data.width as usize * data.height as usize * data.layers as usize;
+        if data.tiles.len() != len {
+            return Err(self.out_of_bounds());
+        }
+
+        let mut current = self.data.lock();
+        self.handle
+            .send(|id, window_id| Message::SetTileData(id, window_id, data.clone()))?;
+        *current = data;
+        Ok(())
+    }
+
+    /// The tile at `x`, `y` on `layer`, or `None` if that's off the map.
+    pub fn tile(&self, x: u32, y: u32, layer: u32) -> Result<Option<u16>> {
+        self.handle.check_disposed()?;
+        let data = self.data.lock();
+        Ok(data.index(x, y, layer).map(|index| data.tiles[index]))
+    }
+
+    /// Changes individual tiles. Nothing is changed if any of them are off the map.
+    pub fn set_tiles(&self, updates: Vec<TileUpdate>) -> Result<()> {
+        self.handle.check_disposed()?;
+        let mut data = self.data.lock();
+        let indices = updates
+            .iter()
+            .map(|update| data.index(update.x, update.y, update.layer))
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(|| self.out_of_bounds())?;
+
+        let tiles: Vec<_> = updates.iter().map(|update| update.tile).collect();
+        self.handle
+            .send(|id, window_id| Message::SetTiles(id, window_id, updates))?;
+        for (index, tile) in indices.into_iter().zip(tiles) {
+            data.tiles[index] = tile;
+        }
+        Ok(())
+    }
+
+    pub fn set_tile(&self, x: u32, y: u32, layer: u32, tile: u16) -> Result<()> {
+        self.set_tiles(vec![TileUpdate { x, y, layer, tile }])
+    }
+
+    /// Sets the priority and animation of tiles, by tile id.
+    pub fn set_tile_info(&self, info: Vec<(u16, TileInfo)>) -> Result<()> {
+        self.handle.check_disposed()?;
+        self.handle
+            .send(|id, window_id| Message::SetTileInfo(id, window_id, info))
+    }
+
+    /// Removes the tilemap from its window. Disposing it again does nothing.
+    pub fn dispose(&self) -> Result<()> {
+        self.handle.dispose()
+    }
+}
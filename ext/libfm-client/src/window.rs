@@ -0,0 +1,206 @@
+// Copyright (C) 2023 Lily Lyons
+//
+// This file is part of libfm.
+//
+// libfm is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// libfm is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with libfm.  If not, see <http://www.gnu.org/licenses/>.
+
+use parking_lot::Mutex;
+use screen::{Effect, Message, ProtocolError, ReturnMessage, WindowConfig};
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use crate::{Error, Result, Screen, Shader};
+
+/// What we last heard about a window from the screen. Updated whenever the screen's messages
+/// are handled, e.g. by [`Screen::update`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WindowState {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+    pub title: String,
+    pub visible: bool,
+    pub focused: bool,
+}
+
+/// A window on the screen. It's disposed when dropped.
+pub struct Window {
+    id: usize,
+    screen: Screen,
+    // Shared with the window's sprites, since disposing the window takes them with it
+    disposed: Arc<AtomicBool>,
+    effects: Mutex<Vec<Effect>>,
+}
+
+// Drops can happen where we can't wait on the screen, so the window is only closed along with
+// the next message sent
+impl Drop for Window {
+    fn drop(&mut self) {
+        if !self.disposed.swap(true, Ordering::AcqRel) {
+            self.screen.send_later(Message::DeleteWindow(self.id));
+        }
+    }
+}
+
+impl Window {
+    pub fn new(screen: &Screen, config: WindowConfig) -> Result<Self> {
+        let mut inner = screen.lock()?;
+        let id = inner.next_id();
+        let (x, y) = config.pos.unwrap_or_default();
+        let state = WindowState {
+            x,
+            y,
+            width: config.size.0,
+            height: config.size.1,
+            title: config.title.clone(),
+            visible: config.visible,
+            focused: false,
+        };
+        inner.send(Message::CreateWindow(config, id))?;
+        inner.windows.insert(id, state);
+        drop(inner);
+
+        Ok(Self {
+            id,
+            screen: screen.clone(),
+            disposed: Arc::default(),
+            effects: Mutex::default(),
+        })
+    }
+
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    pub fn screen(&self) -> &Screen {
+        &self.screen
+    }
+
+    pub(crate) fn disposed_flag(&self) -> Arc<AtomicBool> {
+        self.disposed.clone()
+    }
+
+    pub fn is_disposed(&self) -> bool {
+        self.disposed.load(Ordering::Acquire)
+    }
+
+    pub(crate) fn check_disposed(&self) -> Result<()> {
+        if self.is_disposed() {
+            return Err(Error::Disposed("window"));
+        }
+
+        Ok(())
+    }
+
+    /// The window's last known state.
+    pub fn state(&self) -> Result<WindowState> {
+        self.check_disposed()?;
+        self.screen
+            .lock()?
+            .windows
+            .get(&self.id)
+            .cloned()
+            .ok_or(Error::Disposed("window"))
+    }
+
+    pub fn reposition(&self, x: i32, y: i32) -> Result<()> {
+        self.check_disposed()?;
+        let mut inner = self.screen.lock()?;
+        if let Some(state) = inner.windows.get_mut(&self.id) {
+            (state.x, state.y) = (x, y);
+        }
+        inner.send(Message::RepositionWindow(x, y, self.id))
+    }
+
+    pub fn resize(&self, width: u32, height: u32) -> Result<()> {
+        self.check_disposed()?;
+        let mut inner = self.screen.lock()?;
+        if let Some(state) = inner.windows.get_mut(&self.id) {
+            (state.width, state.height) = (width, height);
+        }
+        inner.send(Message::ResizeWindow(width, height, self.id))
+    }
+
+    /// The effects last set on the window.
+    pub fn effects(&self) -> Result<Vec<Effect>> {
+        self.check_disposed()?;
+        Ok(self.effects.lock().clone())
+    }
+
+    /// Replaces the effects run over the window's scene. If they're the same kinds of effect in
+    /// the same order as before, the screen eases their parameters over `duration` frames.
+    pub fn set_effects(&self, effects: Vec<Effect>, duration: u32) -> Result<()> {
+        self.check_disposed()?;
+        let mut current = self.effects.lock();
+        self.screen
+            .send(Message::SetEffects(effects.clone(), duration, self.id))?;
+        *current = effects;
+        Ok(())
+    }
+
+    /// Runs `shader` over the whole window after its effects, or stops if it's `None`. The shader
+    /// has to be on this window.
+    pub fn set_shader(&self, shader: Option<&Shader>) -> Result<()> {
+        self.check_disposed()?;
+        let shader_id = shader
+            .map(|shader| shader.check_window(self.id))
+            .transpose()?;
+
+        self.screen
+            .send(Message::SetWindowShader(shader_id, self.id))
+    }
+
+    /// Returns the window's current scene as PNG encoded bytes.
+    pub fn snapshot(&self) -> Result<Vec<u8>> {
+        self.check_disposed()?;
+        let mut inner = self.screen.lock()?;
+        inner.send(Message::Snapshot(self.id))?;
+        inner.flush()?;
+        loop {
+            match inner.recv()? {
+                ReturnMessage::Snapshot(id, png) if id == self.id => break Ok(png),
+                ReturnMessage::Error(
+                    error @ (ProtocolError::UnknownWindow(id)
+                    | ProtocolError::SnapshotUnsupported(id)),
+                ) if id == self.id => break Err(Error::Protocol(error)),
+                message => inner.handle_message(message),
+            }
+        }
+    }
+
+    /// Closes the window along with all of its sprites. Disposing it again does nothing.
+    pub fn dispose(&self) -> Result<()> {
+        if self.disposed.swap(true, Ordering::AcqRel) {
+            return Ok(());
+        }
+
+        // Unless the message is on its way, the window is still there to dispose again
+        let (accepted, result) = match self.screen.lock() {
+            Ok(mut inner) => {
+                let (accepted, result) = inner.send_accepted(Message::DeleteWindow(self.id));
+                if accepted {
+                    inner.windows.remove(&self.id);
+                }
+                (accepted, result)
+            }
+            Err(e) => (false, Err(e)),
+        };
+        if !accepted {
+            self.disposed.store(false, Ordering::Release);
+        }
+        result
+    }
+}
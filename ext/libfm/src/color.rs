@@ -0,0 +1,216 @@
+// Copyright (C) 2023 Lily Lyons
+//
+// This file is part of libfm.
+//
+// libfm is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// libfm is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with libfm.  If not, see <http://www.gnu.org/licenses/>.
+
+use magnus::{function, method, Module, Object, RString};
+use std::cell::Cell;
+
+use crate::error;
+
+// The Marshal format RGSS uses: red, green, blue and alpha as little endian doubles
+const DUMP_LEN: usize = 32;
+
+/// Reads the doubles RGSS marshals colors and tones as.
+pub fn load_doubles(data: RString, kind: &str) -> Result<[f64; 4], magnus::Error> {
+    // Safe since the slice is copied before Ruby can touch the string again
+    let data: [u8; DUMP_LEN] = unsafe { data.as_slice() }.try_into().map_err(|_| {
+        magnus::Error::new(
+            magnus::exception::arg_error(),
+            format!("marshaled {kind} has the wrong size"),
+        )
+    })?;
+
+    Ok(std::array::from_fn(|i| {
+        f64::from_le_bytes(data[i * 8..i * 8 + 8].try_into().unwrap())
+    }))
+}
+
+pub fn dump_doubles(values: [f64; 4]) -> RString {
+    let mut data = Vec::with_capacity(DUMP_LEN);
+    for value in values {
+        data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    RString::from_slice(&data)
+}
+
+/// Components are kept between 0 and 255, like in RGSS.
+#[magnus::wrap(class = "LibFM::Color", free_immediately, size)]
+pub struct Color {
+    red: Cell<f64>,
+    green: Cell<f64>,
+    blue: Cell<f64>,
+    alpha: Cell<f64>,
+}
+
+impl Color {
+    pub fn from_parts(red: f64, green: f64, blue: f64, alpha: f64) -> Self {
+        let color = Self {
+            red: Cell::new(0.0),
+            green: Cell::new(0.0),
+            blue: Cell::new(0.0),
+            alpha: Cell::new(0.0),
+        };
+        color.set_parts(red, green, blue, alpha);
+
+        color
+    }
+
+    pub fn from_screen(color: libfm_client::Color) -> Self {
+        let libfm_client::Color {
+            red,
+            green,
+            blue,
+            alpha,
+        } = color;
+        Self::from_parts(red.into(), green.into(), blue.into(), alpha.into())
+    }
+
+    pub fn to_screen(&self) -> libfm_client::Color {
+        let (red, green, blue, alpha) = self.get();
+        libfm_client::Color {
+            red: red as f32,
+            green: green as f32,
+            blue: blue as f32,
+            alpha: alpha as f32,
+        }
+    }
+
+    /// The color's red, green, blue and alpha.
+    pub fn get(&self) -> (f64, f64, f64, f64) {
+        (
+            self.red.get(),
+            self.green.get(),
+            self.blue.get(),
+            self.alpha.get(),
+        )
+    }
+
+    fn set_parts(&self, red: f64, green: f64, blue: f64, alpha: f64) {
+        self.set_red(red);
+        self.set_green(green);
+        self.set_blue(blue);
+        self.set_alpha(alpha);
+    }
+
+    fn new(args: &[magnus::Value]) -> Result<Self, magnus::Error> {
+        let color = Self::from_parts(0.0, 0.0, 0.0, 0.0);
+        match args.len() {
+            0 => {}
+            3 | 4 => color.set(args)?,
+            given => return Err(error::arity_error(given, "0, 3 or 4")),
+        }
+
+        Ok(color)
+    }
+
+    // Takes either another color or red, green, blue and an optional alpha
+    fn set(&self, args: &[magnus::Value]) -> Result<(), magnus::Error> {
+        let (red, green, blue, alpha) = match *args {
+            [color] => color.try_convert::<&Color>()?.get(),
+            [red, green, blue] => (
+                red.try_convert()?,
+                green.try_convert()?,
+                blue.try_convert()?,
+                255.0,
+            ),
+            [red, green, blue, alpha] => (
+                red.try_convert()?,
+                green.try_convert()?,
+                blue.try_convert()?,
+                alpha.try_convert()?,
+            ),
+            _ => return Err(error::arity_error(args.len(), "1, 3 or 4")),
+        };
+
+        self.set_parts(red, green, blue, alpha);
+        Ok(())
+    }
+
+    fn eq(&self, other: magnus::Value) -> bool {
+        other
+            .try_convert::<&Color>()
+            .is_ok_and(|other| other.get() == self.get())
+    }
+
+    fn to_s(&self) -> String {
+        let (red, green, blue, alpha) = self.get();
+        format!("({red:.6}, {green:.6}, {blue:.6}, {alpha:.6})")
+    }
+
+    fn dump(&self, _level: i32) -> RString {
+        let (red, green, blue, alpha) = self.get();
+        dump_doubles([red, green, blue, alpha])
+    }
+
+    fn load(data: RString) -> Result<Self, magnus::Error> {
+        let [red, green, blue, alpha] = load_doubles(data, "color")?;
+        Ok(Self::from_parts(red, green, blue, alpha))
+    }
+
+    fn get_red(&self) -> f64 {
+        self.red.get()
+    }
+
+    fn set_red(&self, red: f64) {
+        self.red.set(red.clamp(0.0, 255.0))
+    }
+
+    fn get_green(&self) -> f64 {
+        self.green.get()
+    }
+
+    fn set_green(&self, green: f64) {
+        self.green.set(green.clamp(0.0, 255.0))
+    }
+
+    fn get_blue(&self) -> f64 {
+        self.blue.get()
+    }
+
+    fn set_blue(&self, blue: f64) {
+        self.blue.set(blue.clamp(0.0, 255.0))
+    }
+
+    fn get_alpha(&self) -> f64 {
+        self.alpha.get()
+    }
+
+    fn set_alpha(&self, alpha: f64) {
+        self.alpha.set(alpha.clamp(0.0, 255.0))
+    }
+}
+
+pub fn bind(module: &mut impl magnus::Module) -> Result<(), magnus::Error> {
+    let class = module.define_class("Color", Default::default())?;
+    class.define_singleton_method("new", function!(Color::new, -1))?;
+    class.define_singleton_method("_load", function!(Color::load, 1))?;
+    class.define_method("set", method!(Color::set, -1))?;
+    class.define_method("==", method!(Color::eq, 1))?;
+    class.define_method("to_s", method!(Color::to_s, 0))?;
+    class.define_method("_dump", method!(Color::dump, 1))?;
+
+    class.define_method("red", method!(Color::get_red, 0))?;
+    class.define_method("red=", method!(Color::set_red, 1))?;
+    class.define_method("green", method!(Color::get_green, 0))?;
+    class.define_method("green=", method!(Color::set_green, 1))?;
+    class.define_method("blue", method!(Color::get_blue, 0))?;
+    class.define_method("blue=", method!(Color::set_blue, 1))?;
+    class.define_method("alpha", method!(Color::get_alpha, 0))?;
+    class.define_method("alpha=", method!(Color::set_alpha, 1))?;
+
+    Ok(())
+}
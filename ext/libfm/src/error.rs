@@ -0,0 +1,115 @@
+// Copyright (C) 2023 Lily Lyons
+//
+// This file is part of libfm.
+//
+// libfm is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// libfm is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with libfm.  If not, see <http://www.gnu.org/licenses/>.
+
+use magnus::{memoize, Attr, Class, ExceptionClass, Module, Object, RModule, RObject};
+
+use crate::gvl;
+
+fn module() -> RModule {
+    magnus::class::object()
+        .const_get("LibFM")
+        .expect("LibFM is not defined")
+}
+
+macro_rules! exception_class {
+    ($name:ident, $const:literal) => {
+        pub fn $name() -> ExceptionClass {
+            *memoize!(ExceptionClass: module()
+                .const_get($const)
+                .expect(concat!($const, " is not defined")))
+        }
+    };
+}
+
+exception_class!(base_error, "Error");
+exception_class!(screen_launch_error, "ScreenLaunchError");
+exception_class!(screen_died_error, "ScreenDiedError");
+exception_class!(connection_error, "ConnectionError");
+exception_class!(protocol_error, "ProtocolError");
+exception_class!(asset_load_error, "AssetLoadError");
+exception_class!(disposed_error, "DisposedError");
+exception_class!(shader_error, "ShaderError");
+
+// Creates an exception whose extra attributes are set by `init`
+fn exception_with(
+    class: ExceptionClass,
+    message: String,
+    init: impl FnOnce(RObject) -> Result<(), magnus::Error>,
+) -> magnus::Error {
+    let exception = class.new_instance((message,)).and_then(|exception| {
+        let object = RObject::from_value(*exception).expect("exception is not an object");
+        init(object)?;
+        Ok(exception)
+    });
+
+    match exception {
+        Ok(exception) => exception.into(),
+        Err(e) => e,
+    }
+}
+
+/// The ArgumentError Ruby raises for a call with the wrong number of arguments.
+pub fn arity_error(given: usize, expected: &str) -> magnus::Error {
+    magnus::Error::new(
+        magnus::exception::arg_error(),
+        format!("wrong number of arguments (given {given}, expected {expected})"),
+    )
+}
+
+/// Converts a client error into the matching Ruby exception.
+pub fn client_error(error: libfm_client::Error) -> magnus::Error {
+    use libfm_client::Error;
+
+    let message = error.to_string();
+    match error {
+        Error::Launch(_) => magnus::Error::new(screen_launch_error(), message),
+        Error::ScreenDied(code) => exception_with(screen_died_error(), message, |e| {
+            e.ivar_set("@exit_status", code)
+        }),
+        Error::Connection(_) | Error::TimedOut(_) => {
+            magnus::Error::new(connection_error(), message)
+        }
+        Error::Protocol(_) => magnus::Error::new(protocol_error(), message),
+        Error::AssetLoad { path, reason } => exception_with(asset_load_error(), message, |e| {
+            e.ivar_set("@path", path.to_string_lossy().into_owned())?;
+            e.ivar_set("@reason", reason)
+        }),
+        Error::Disposed(_) => magnus::Error::new(disposed_error(), message),
+        Error::Shader(_) => magnus::Error::new(shader_error(), message),
+        Error::Io(_) => magnus::Error::new(magnus::exception::io_error(), message),
+        // Whatever interrupted us should be what gets raised
+        Error::Interrupted => {
+            gvl::take_interrupt().unwrap_or_else(|| magnus::Error::new(base_error(), message))
+        }
+    }
+}
+
+pub fn bind(module: &mut impl magnus::Module) -> Result<(), magnus::Error> {
+    let error = module.define_error("Error", magnus::exception::standard_error())?;
+    module.define_error("ScreenLaunchError", error)?;
+    let screen_died = module.define_error("ScreenDiedError", error)?;
+    screen_died.define_attr("exit_status", Attr::Read)?;
+    module.define_error("ConnectionError", error)?;
+    module.define_error("ProtocolError", error)?;
+    let asset_load = module.define_error("AssetLoadError", error)?;
+    asset_load.define_attr("path", Attr::Read)?;
+    asset_load.define_attr("reason", Attr::Read)?;
+    module.define_error("DisposedError", error)?;
+    module.define_error("ShaderError", error)?;
+
+    Ok(())
+}
@@ -0,0 +1,156 @@
+// Copyright (C) 2023 Lily Lyons
+//
+// This file is part of libfm.
+//
+// libfm is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// libfm is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with libfm.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::cell::RefCell;
+use std::ffi::c_void;
+use std::future::Future;
+use std::pin::Pin;
+
+struct Call<F, R> {
+    func: Option<F>,
+    result: Option<std::thread::Result<R>>,
+}
+
+unsafe extern "C" fn call<F, R>(data: *mut c_void) -> *mut c_void
+where
+    F: FnOnce() -> R,
+{
+    let call = &mut *(data as *mut Call<F, R>);
+    let func = call.func.take().expect("function called twice");
+    // Unwinding into Ruby's stack frames is UB, so we carry the panic back out ourselves
+    call.result = Some(std::panic::catch_unwind(std::panic::AssertUnwindSafe(func)));
+
+    std::ptr::null_mut()
+}
+
+unsafe extern "C" fn unblock<U>(data: *mut c_void)
+where
+    U: Fn(),
+{
+    (*(data as *const U))()
+}
+
+/// Runs `func` without holding the GVL so other Ruby threads can keep running.
+///
+/// `unblock` may be called from another thread when Ruby wants to interrupt this one
+/// (`Thread#raise`, Ctrl-C, ...) and should make `func` return as soon as possible.
+/// `func` must not touch any Ruby objects.
+pub fn without_gvl<F, U, R>(func: F, unblock_func: U) -> R
+where
+    F: FnOnce() -> R,
+    U: Fn() + Sync,
+{
+    let mut data = Call {
+        func: Some(func),
+        result: None,
+    };
+
+    unsafe {
+        rb_sys::rb_thread_call_without_gvl(
+            Some(call::<F, R>),
+            &mut data as *mut Call<F, R> as *mut c_void,
+            Some(unblock::<U>),
+            &unblock_func as *const U as *mut c_void,
+        );
+    }
+
+    match data.result.expect("function was not called") {
+        Ok(result) => result,
+        Err(panic) => std::panic::resume_unwind(panic),
+    }
+}
+
+thread_local! {
+    // What interrupted this thread's last wait on the screen, until it's raised. Exceptions are
+    // boxed so the GC knows about them in the meantime
+    static INTERRUPT: RefCell<Option<Interrupt>> = const { RefCell::new(None) };
+}
+
+enum Interrupt {
+    Exception(magnus::value::BoxValue<magnus::Exception>),
+    Other(magnus::Error),
+}
+
+/// Takes the exception that made the last wait on this thread fail with
+/// [`libfm_client::Error::Interrupted`].
+pub fn take_interrupt() -> Option<magnus::Error> {
+    INTERRUPT.with(|interrupt| match interrupt.borrow_mut().take()? {
+        Interrupt::Exception(exception) => Some(magnus::Error::Exception(*exception.as_ref())),
+        Interrupt::Other(error) => Some(error),
+    })
+}
+
+/// Waits on the screen with the GVL released.
+///
+/// Waits can be interrupted by Ruby, in which case the client fails with
+/// [`libfm_client::Error::Interrupted`] and the exception Ruby raised can be taken with
+/// [`take_interrupt`].
+pub struct GvlBlocker;
+
+impl libfm_client::Blocker for GvlBlocker {
+    fn block_on(
+        &self,
+        runtime: &tokio::runtime::Runtime,
+        mut future: Pin<&mut dyn Future<Output = ()>>,
+    ) -> libfm_client::Result<()> {
+        let interrupt = tokio::sync::Notify::new();
+
+        loop {
+            let finished = without_gvl(
+                || {
+                    runtime.block_on(async {
+                        tokio::select! {
+                            _ = future.as_mut() => true,
+                            _ = interrupt.notified() => false,
+                        }
+                    })
+                },
+                || interrupt.notify_one(),
+            );
+            if finished {
+                return Ok(());
+            }
+
+            // Ruby also unblocks us for things that don't raise, like trap handlers that return
+            // normally, in which case we go back to waiting
+            if let Err(error) = check_ints() {
+                let error = match error {
+                    magnus::Error::Exception(exception) => {
+                        Interrupt::Exception(magnus::value::BoxValue::new(exception))
+                    }
+                    error => Interrupt::Other(error),
+                };
+                INTERRUPT.with(|interrupt| *interrupt.borrow_mut() = Some(error));
+                return Err(libfm_client::Error::Interrupted);
+            }
+        }
+    }
+
+    // Only used for short calls, so there's nothing to interrupt
+    fn block(&self, func: &mut dyn FnMut()) {
+        without_gvl(func, || {})
+    }
+}
+
+/// Raises any interrupts (signals, `Thread#raise`) Ruby queued up for this thread.
+fn check_ints() -> Result<(), magnus::Error> {
+    magnus::rb_sys::protect(|| unsafe {
+        rb_sys::rb_thread_check_ints();
+        rb_sys::Qnil as rb_sys::VALUE
+    })
+    .map(|_| ())
+}
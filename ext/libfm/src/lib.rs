@@ -1,30 +1,18 @@
 #![warn(rust_2018_idioms, clippy::all)]
 
+mod color;
+mod error;
+mod gvl;
+mod plane;
+mod rect;
 mod screen;
+mod shader;
+mod shape;
 mod sprite;
+mod tilemap;
+mod tone;
 mod viewport;
-
-#[macro_export]
-macro_rules! send {
-    ($screen:expr, $msg:expr) => {
-        use crate::convert_rust_error;
-        use futures::prelude::*;
-
-        let message = $msg;
-        let mut m_lock = $screen.lock();
-        let lock = &mut *m_lock;
-
-        lock.runtime
-            .block_on(async { lock.writer.send(message).await.map_err(convert_rust_error) })?;
-
-        drop(lock);
-        drop(m_lock);
-    };
-}
-
-pub fn convert_rust_error(error: impl ToString) -> magnus::Error {
-    magnus::Error::new(magnus::exception::runtime_error(), error.to_string())
-}
+mod window;
 
 #[magnus::init]
 fn init() -> Result<(), magnus::Error> {
@@ -33,9 +21,18 @@ fn init() -> Result<(), magnus::Error> {
     }
 
     let mut module = magnus::define_module("LibFM")?;
+    error::bind(&mut module)?;
+    rect::bind(&mut module)?;
+    color::bind(&mut module)?;
+    tone::bind(&mut module)?;
     viewport::bind(&mut module)?;
     screen::bind(&mut module)?;
     sprite::bind(&mut module)?;
+    plane::bind(&mut module)?;
+    tilemap::bind(&mut module)?;
+    window::bind(&mut module)?;
+    shape::bind(&mut module)?;
+    shader::bind(&mut module)?;
 
     Ok(())
 }
@@ -0,0 +1,182 @@
+// Copyright (C) 2023 Lily Lyons
+//
+// This file is part of libfm.
+//
+// libfm is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// libfm is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with libfm.  If not, see <http://www.gnu.org/licenses/>.
+
+use libfm_client::{BlendType, PlaneConfig};
+use magnus::{function, method, Module, Object};
+
+use crate::{color::Color, error, tone::Tone, viewport::Viewport};
+
+#[magnus::wrap(class = "LibFM::Plane", free_immediately, size)]
+struct Plane(libfm_client::Plane);
+
+impl Plane {
+    fn new(viewport: &Viewport) -> Result<Self, magnus::Error> {
+        libfm_client::Plane::new(&viewport.0)
+            .map(Self)
+            .map_err(error::client_error)
+    }
+
+    fn dispose(&self) -> Result<(), magnus::Error> {
+        self.0.dispose().map_err(error::client_error)
+    }
+
+    fn is_disposed(&self) -> bool {
+        self.0.is_disposed()
+    }
+
+    fn set(&self, filename: String) -> Result<(), magnus::Error> {
+        self.0.set(filename).map_err(error::client_error)
+    }
+
+    fn config(&self) -> Result<PlaneConfig, magnus::Error> {
+        self.0.config().map_err(error::client_error)
+    }
+
+    fn configure(&self, func: impl FnOnce(&mut PlaneConfig)) -> Result<(), magnus::Error> {
+        self.0.configure(func).map_err(error::client_error)
+    }
+
+    fn get_ox(&self) -> Result<i32, magnus::Error> {
+        Ok(self.config()?.ox)
+    }
+
+    fn set_ox(&self, ox: i32) -> Result<(), magnus::Error> {
+        self.configure(|config| config.ox = ox)
+    }
+
+    fn get_oy(&self) -> Result<i32, magnus::Error> {
+        Ok(self.config()?.oy)
+    }
+
+    fn set_oy(&self, oy: i32) -> Result<(), magnus::Error> {
+        self.configure(|config| config.oy = oy)
+    }
+
+    fn get_z(&self) -> Result<i32, magnus::Error> {
+        Ok(self.config()?.z)
+    }
+
+    fn set_z(&self, z: i32) -> Result<(), magnus::Error> {
+        self.configure(|config| config.z = z)
+    }
+
+    fn get_zoom_x(&self) -> Result<f32, magnus::Error> {
+        Ok(self.config()?.zoom_x)
+    }
+
+    fn set_zoom_x(&self, zoom_x: f32) -> Result<(), magnus::Error> {
+        self.configure(|config| config.zoom_x = zoom_x)
+    }
+
+    fn get_zoom_y(&self) -> Result<f32, magnus::Error> {
+        Ok(self.config()?.zoom_y)
+    }
+
+    fn set_zoom_y(&self, zoom_y: f32) -> Result<(), magnus::Error> {
+        self.configure(|config| config.zoom_y = zoom_y)
+    }
+
+    fn get_opacity(&self) -> Result<u8, magnus::Error> {
+        Ok(self.config()?.opacity)
+    }
+
+    // Like RGSS, out of range opacities are clamped instead of raising
+    fn set_opacity(&self, opacity: i32) -> Result<(), magnus::Error> {
+        self.configure(|config| config.opacity = opacity.clamp(0, 255) as u8)
+    }
+
+    fn get_blend_type(&self) -> Result<i32, magnus::Error> {
+        Ok(match self.config()?.blend_type {
+            BlendType::Normal => 0,
+            BlendType::Add => 1,
+            BlendType::Subtract => 2,
+        })
+    }
+
+    fn set_blend_type(&self, blend_type: i32) -> Result<(), magnus::Error> {
+        let blend_type = match blend_type {
+            0 => BlendType::Normal,
+            1 => BlendType::Add,
+            2 => BlendType::Subtract,
+            _ => {
+                return Err(magnus::Error::new(
+                    magnus::exception::arg_error(),
+                    format!("invalid blend type {blend_type}"),
+                ))
+            }
+        };
+        self.configure(|config| config.blend_type = blend_type)
+    }
+
+    // Returns a copy, so changes to it only apply once it's assigned back
+    fn get_color(&self) -> Result<Color, magnus::Error> {
+        Ok(Color::from_screen(self.config()?.color))
+    }
+
+    fn set_color(&self, color: &Color) -> Result<(), magnus::Error> {
+        let color = color.to_screen();
+        self.configure(|config| config.color = color)
+    }
+
+    fn get_tone(&self) -> Result<Tone, magnus::Error> {
+        Ok(Tone::from_screen(self.config()?.tone))
+    }
+
+    fn set_tone(&self, tone: &Tone) -> Result<(), magnus::Error> {
+        let tone = tone.to_screen();
+        self.configure(|config| config.tone = tone)
+    }
+
+    fn get_visible(&self) -> Result<bool, magnus::Error> {
+        Ok(self.config()?.visible)
+    }
+
+    fn set_visible(&self, visible: bool) -> Result<(), magnus::Error> {
+        self.configure(|config| config.visible = visible)
+    }
+}
+
+pub fn bind(module: &mut impl magnus::Module) -> Result<(), magnus::Error> {
+    let class = module.define_class("Plane", Default::default())?;
+    class.define_singleton_method("new", function!(Plane::new, 1))?;
+    class.define_method("dispose", method!(Plane::dispose, 0))?;
+    class.define_method("disposed?", method!(Plane::is_disposed, 0))?;
+    class.define_method("set", method!(Plane::set, 1))?;
+
+    class.define_method("ox", method!(Plane::get_ox, 0))?;
+    class.define_method("ox=", method!(Plane::set_ox, 1))?;
+    class.define_method("oy", method!(Plane::get_oy, 0))?;
+    class.define_method("oy=", method!(Plane::set_oy, 1))?;
+    class.define_method("z", method!(Plane::get_z, 0))?;
+    class.define_method("z=", method!(Plane::set_z, 1))?;
+    class.define_method("zoom_x", method!(Plane::get_zoom_x, 0))?;
+    class.define_method("zoom_x=", method!(Plane::set_zoom_x, 1))?;
+    class.define_method("zoom_y", method!(Plane::get_zoom_y, 0))?;
+    class.define_method("zoom_y=", method!(Plane::set_zoom_y, 1))?;
+    class.define_method("opacity", method!(Plane::get_opacity, 0))?;
+    class.define_method("opacity=", method!(Plane::set_opacity, 1))?;
+    class.define_method("blend_type", method!(Plane::get_blend_type, 0))?;
+    class.define_method("blend_type=", method!(Plane::set_blend_type, 1))?;
+    class.define_method("color", method!(Plane::get_color, 0))?;
+    class.define_method("color=", method!(Plane::set_color, 1))?;
+    class.define_method("tone", method!(Plane::get_tone, 0))?;
+    class.define_method("tone=", method!(Plane::set_tone, 1))?;
+    class.define_method("visible", method!(Plane::get_visible, 0))?;
+    class.define_method("visible=", method!(Plane::set_visible, 1))?;
+
+    Ok(())
+}
@@ -0,0 +1,179 @@
+// Copyright (C) 2023 Lily Lyons
+//
+// This file is part of libfm.
+//
+// libfm is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// libfm is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with libfm.  If not, see <http://www.gnu.org/licenses/>.
+
+use magnus::{function, method, Module, Object, RString};
+use std::cell::Cell;
+
+use crate::error;
+
+// The Marshal format RGSS uses: x, y, width and height as little endian 32 bit integers
+const DUMP_LEN: usize = 16;
+
+#[magnus::wrap(class = "LibFM::Rect", free_immediately, size)]
+pub struct Rect {
+    x: Cell<i32>,
+    y: Cell<i32>,
+    width: Cell<i32>,
+    height: Cell<i32>,
+}
+
+impl Rect {
+    pub fn from_parts(x: i32, y: i32, width: i32, height: i32) -> Self {
+        Self {
+            x: Cell::new(x),
+            y: Cell::new(y),
+            width: Cell::new(width),
+            height: Cell::new(height),
+        }
+    }
+
+    /// The rect's x, y, width and height.
+    pub fn get(&self) -> (i32, i32, i32, i32) {
+        (
+            self.x.get(),
+            self.y.get(),
+            self.width.get(),
+            self.height.get(),
+        )
+    }
+
+    fn new(args: &[magnus::Value]) -> Result<Self, magnus::Error> {
+        let rect = Self::from_parts(0, 0, 0, 0);
+        match args.len() {
+            0 => {}
+            4 => rect.set(args)?,
+            given => return Err(error::arity_error(given, "0 or 4")),
+        }
+
+        Ok(rect)
+    }
+
+    // Takes either another rect or x, y, width and height
+    fn set(&self, args: &[magnus::Value]) -> Result<(), magnus::Error> {
+        let (x, y, width, height) = match *args {
+            [rect] => rect.try_convert::<&Rect>()?.get(),
+            [x, y, width, height] => (
+                x.try_convert()?,
+                y.try_convert()?,
+                width.try_convert()?,
+                height.try_convert()?,
+            ),
+            _ => return Err(error::arity_error(args.len(), "1 or 4")),
+        };
+
+        self.x.set(x);
+        self.y.set(y);
+        self.width.set(width);
+        self.height.set(height);
+        Ok(())
+    }
+
+    fn empty(&self) {
+        self.width.set(0);
+        self.height.set(0);
+        self.x.set(0);
+        self.y.set(0);
+    }
+
+    fn eq(&self, other: magnus::Value) -> bool {
+        other
+            .try_convert::<&Rect>()
+            .is_ok_and(|other| other.get() == self.get())
+    }
+
+    fn to_s(&self) -> String {
+        let (x, y, width, height) = self.get();
+        format!("({x}, {y}, {width}, {height})")
+    }
+
+    fn dump(&self, _level: i32) -> RString {
+        let (x, y, width, height) = self.get();
+        let mut data = Vec::with_capacity(DUMP_LEN);
+        for value in [x, y, width, height] {
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+
+        RString::from_slice(&data)
+    }
+
+    fn load(data: RString) -> Result<Self, magnus::Error> {
+        // Safe since the slice is copied before Ruby can touch the string again
+        let data: [u8; DUMP_LEN] = unsafe { data.as_slice() }.try_into().map_err(|_| {
+            magnus::Error::new(
+                magnus::exception::arg_error(),
+                "marshaled rect has the wrong size",
+            )
+        })?;
+        let value = |i: usize| i32::from_le_bytes(data[i * 4..i * 4 + 4].try_into().unwrap());
+
+        Ok(Self::from_parts(value(0), value(1), value(2), value(3)))
+    }
+
+    fn get_x(&self) -> i32 {
+        self.x.get()
+    }
+
+    fn set_x(&self, x: i32) {
+        self.x.set(x)
+    }
+
+    fn get_y(&self) -> i32 {
+        self.y.get()
+    }
+
+    fn set_y(&self, y: i32) {
+        self.y.set(y)
+    }
+
+    fn get_width(&self) -> i32 {
+        self.width.get()
+    }
+
+    fn set_width(&self, width: i32) {
+        self.width.set(width)
+    }
+
+    fn get_height(&self) -> i32 {
+        self.height.get()
+    }
+
+    fn set_height(&self, height: i32) {
+        self.height.set(height)
+    }
+}
+
+pub fn bind(module: &mut impl magnus::Module) -> Result<(), magnus::Error> {
+    let class = module.define_class("Rect", Default::default())?;
+    class.define_singleton_method("new", function!(Rect::new, -1))?;
+    class.define_singleton_method("_load", function!(Rect::load, 1))?;
+    class.define_method("set", method!(Rect::set, -1))?;
+    class.define_method("empty", method!(Rect::empty, 0))?;
+    class.define_method("==", method!(Rect::eq, 1))?;
+    class.define_method("to_s", method!(Rect::to_s, 0))?;
+    class.define_method("_dump", method!(Rect::dump, 1))?;
+
+    class.define_method("x", method!(Rect::get_x, 0))?;
+    class.define_method("x=", method!(Rect::set_x, 1))?;
+    class.define_method("y", method!(Rect::get_y, 0))?;
+    class.define_method("y=", method!(Rect::set_y, 1))?;
+    class.define_method("width", method!(Rect::get_width, 0))?;
+    class.define_method("width=", method!(Rect::set_width, 1))?;
+    class.define_method("height", method!(Rect::get_height, 0))?;
+    class.define_method("height=", method!(Rect::set_height, 1))?;
+
+    Ok(())
+}
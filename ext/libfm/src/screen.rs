@@ -15,149 +15,175 @@
 // You should have received a copy of the GNU General Public License
 // along with libfm.  If not, see <http://www.gnu.org/licenses/>.
 
+use libfm_client::{Encoding, FrameConfig, Mode, PresentMode, ScreenConfig};
 use magnus::{function, method, Module, Object};
-use parking_lot::{Mutex, MutexGuard};
-use screen::ReturnMessage;
-
-use crate::convert_rust_error;
-use interprocess::local_socket;
-
-use futures::prelude::*;
-use std::sync::{
-    mpsc::{channel, Receiver},
-    Arc,
-};
-
-macro_rules! gaurd_dead {
-    ($child:expr) => {
-        match $child.try_wait() {
-            Ok(Some(c)) => {
-                return Err(magnus::Error::new(
-                    magnus::exception::runtime_error(),
-                    format!("child process is dead with code {:?}", c.code()),
-                ))
-            }
-            Err(e) => {
-                return Err(magnus::Error::new(
-                    magnus::exception::runtime_error(),
-                    e.to_string(),
-                ))
-            }
-            Ok(_) => {}
-        };
-    };
-}
-
-pub(crate) struct Inner {
-    child: std::process::Child,
-    reader_handle: tokio::task::JoinHandle<()>,
-
-    pub writer: async_bincode::futures::AsyncBincodeWriter<
-        local_socket::tokio::OwnedWriteHalf,
-        screen::Message,
-        async_bincode::AsyncDestination,
-    >,
-    pub runtime: tokio::runtime::Runtime,
-    pub message_recv: Receiver<ReturnMessage>,
-}
+use std::sync::Arc;
+use std::time::Duration;
 
-impl Drop for Inner {
-    fn drop(&mut self) {
-        let _ = self.child.kill();
-        self.child.wait().expect("failed to wait on child");
-        self.reader_handle.abort();
-    }
-}
+use crate::{error, gvl};
 
 #[magnus::wrap(class = "LibFM::Screen", free_immediately, size)]
 #[derive(Clone)]
-pub struct Screen {
-    inner: Arc<Mutex<Inner>>,
-}
+pub struct Screen(pub libfm_client::Screen);
 
 impl Screen {
     fn new(args: &[magnus::Value]) -> Result<Self, magnus::Error> {
         let args = magnus::scan_args::scan_args::<(), (), (), (), _, ()>(args)?;
-        let args = magnus::scan_args::get_kwargs::<_, (), _, ()>(
+        let args = magnus::scan_args::get_kwargs::<_, (), _, magnus::RHash>(
             args.keywords,
             &[],
-            &["screen_path", "socket_addr"],
+            &[
+                "mode",
+                "screen_path",
+                "socket_addr",
+                "fps",
+                "vsync",
+                "fixed_timestep",
+                "batch",
+                "timeout",
+                "connect_timeout",
+            ],
+        )?;
+        let (
+            mode,
+            screen_path,
+            socket_addr,
+            fps,
+            vsync,
+            fixed_timestep,
+            batch,
+            timeout,
+            connect_timeout,
+        ): (
+            Option<magnus::Symbol>,
+            Option<String>,
+            Option<String>,
+            Option<u32>,
+            Option<magnus::Value>,
+            Option<bool>,
+            Option<bool>,
+            Option<f64>,
+            Option<f64>,
+        ) = args.optional;
+        // get_kwargs only takes so many at once, so the rest come out of what it left behind
+        let rest = magnus::scan_args::get_kwargs::<_, (), _, ()>(
+            args.splat,
+            &[],
+            &["record", "encoding"],
         )?;
-        let (screen_path, socket_addr): (Option<_>, Option<String>) = args.optional;
-
-        let screen_path = screen_path.unwrap_or_else(|| "target/debug/screen".to_string());
-
-        let socket_addr = socket_addr.unwrap_or_else(|| "abcdef".to_string());
-        let socket_addr = match local_socket::NameTypeSupport::query() {
-            local_socket::NameTypeSupport::OnlyPaths => {
-                format!("/tmp/libfm-screen-sock-{socket_addr}.sock")
-            }
-            local_socket::NameTypeSupport::Both | local_socket::NameTypeSupport::OnlyNamespaced => {
-                format!("@libfm-screen-sock-{socket_addr}.sock")
-            }
+        let (record, encoding): (Option<String>, Option<magnus::Symbol>) = rest.optional;
+
+        let mut config = ScreenConfig {
+            mode: mode.map_or(Ok(Mode::Process), screen_mode)?,
+            screen_path: screen_path.map(Into::into),
+            socket_addr,
+            // fps: 0 disables the frame cap entirely
+            frames: FrameConfig {
+                target_fps: fps.map_or(Some(60), |fps| (fps > 0).then_some(fps)),
+                present_mode: vsync.map_or(Ok(PresentMode::Fifo), present_mode)?,
+                fixed_timestep: fixed_timestep.unwrap_or_default(),
+            },
+            timeout: timeout.map(Duration::from_secs_f64),
+            record: record.map(Into::into),
+            ..Default::default()
         };
+        if let Some(encoding) = encoding {
+            let name = encoding.name()?;
+            config.encoding = Encoding::from_name(&name).ok_or_else(|| {
+                magnus::Error::new(
+                    magnus::exception::arg_error(),
+                    format!("unknown encoding :{name}"),
+                )
+            })?;
+        }
+        if let Some(batch) = batch {
+            config.batch = batch;
+        }
+        if let Some(connect_timeout) = connect_timeout {
+            config.connect_timeout = Duration::from_secs_f64(connect_timeout);
+        }
 
-        let runtime = tokio::runtime::Builder::new_current_thread()
-            .enable_io()
-            .build()
-            .map_err(convert_rust_error)?;
-        let _g = runtime.enter();
-
-        let listener = local_socket::tokio::LocalSocketListener::bind(socket_addr.clone())
-            .map_err(convert_rust_error)?;
-
-        let mut child = std::process::Command::new(screen_path)
-            .arg(socket_addr)
-            .spawn()
-            .map_err(convert_rust_error)?;
-
-        gaurd_dead!(child);
-
-        let socket = runtime
-            .block_on(listener.accept())
-            .map_err(convert_rust_error)?;
-        let (reader, writer) = socket.into_split();
-        let mut reader = async_bincode::futures::AsyncBincodeReader::from(reader);
-        let writer = async_bincode::futures::AsyncBincodeWriter::from(writer).for_async();
-        let (message_send, message_recv) = channel();
-
-        let reader_handle = runtime.spawn(async move {
-            while let Some(Ok(message)) = reader.next().await {
-                message_send.send(message).expect("failed to send message");
-            }
-        });
-
-        Ok(Self {
-            inner: Arc::new(Mutex::new(Inner {
-                child,
-                writer,
-                message_recv,
-                runtime,
-                reader_handle,
-            })),
-        })
+        libfm_client::Screen::with_blocker(config, Arc::new(gvl::GvlBlocker))
+            .map(Self)
+            .map_err(error::client_error)
     }
 
-    fn is_alive(&self) -> bool {
-        self.inner
-            .lock()
-            .child
-            .try_wait()
-            .is_ok_and(|c| c.is_none())
+    fn is_alive(&self) -> Result<bool, magnus::Error> {
+        self.0.is_alive().map_err(error::client_error)
     }
 
     fn process_events(&self) -> Result<(), magnus::Error> {
-        let inner = self.inner.lock();
-        for message in inner.message_recv.try_iter() {
-            eprintln!("{message:?}")
-        }
+        self.0.process_events().map_err(error::client_error)
+    }
 
-        Ok(())
+    fn update(&self) -> Result<(), magnus::Error> {
+        self.0.update().map_err(error::client_error)
     }
 
-    pub(crate) fn lock(&self) -> MutexGuard<'_, Inner> {
-        self.inner.lock()
+    // Everything sent inside the block reaches the screen as one batch when the block returns
+    fn batch(&self) -> Result<magnus::Value, magnus::Error> {
+        self.0.begin_batch().map_err(error::client_error)?;
+        let result = magnus::block::yield_values(());
+        self.0.end_batch().map_err(error::client_error)?;
+
+        result
     }
+
+    fn frame_count(&self) -> Result<u64, magnus::Error> {
+        self.0.frame_count().map_err(error::client_error)
+    }
+
+    fn delta(&self) -> Result<f64, magnus::Error> {
+        self.0.delta().map_err(error::client_error)
+    }
+
+    // Per frame averages over the last few stats reports
+    fn stats(&self) -> Result<magnus::RHash, magnus::Error> {
+        let stats = self.0.stats().map_err(error::client_error)?;
+
+        let hash = magnus::RHash::new();
+        hash.aset(magnus::Symbol::new("fps"), stats.fps)?;
+        hash.aset(magnus::Symbol::new("cpu_time"), stats.cpu_time)?;
+        hash.aset(magnus::Symbol::new("gpu_time"), stats.gpu_time)?;
+        hash.aset(magnus::Symbol::new("draw_calls"), stats.draw_calls)?;
+        hash.aset(magnus::Symbol::new("sprites_drawn"), stats.sprites_drawn)?;
+        hash.aset(magnus::Symbol::new("messages"), stats.messages)?;
+        hash.aset(magnus::Symbol::new("texture_memory"), stats.texture_memory)?;
+
+        Ok(hash)
+    }
+}
+
+fn screen_mode(mode: magnus::Symbol) -> Result<Mode, magnus::Error> {
+    match &*mode.name()? {
+        "process" => Ok(Mode::Process),
+        "in_process" => Ok(Mode::InProcess),
+        "connect" => Ok(Mode::Connect),
+        name => Err(magnus::Error::new(
+            magnus::exception::arg_error(),
+            format!("unknown screen mode :{name}"),
+        )),
+    }
+}
+
+fn present_mode(vsync: magnus::Value) -> Result<PresentMode, magnus::Error> {
+    if let Some(symbol) = magnus::Symbol::from_value(vsync) {
+        return match &*symbol.name()? {
+            "fifo" => Ok(PresentMode::Fifo),
+            "mailbox" => Ok(PresentMode::Mailbox),
+            "immediate" => Ok(PresentMode::Immediate),
+            name => Err(magnus::Error::new(
+                magnus::exception::arg_error(),
+                format!("unknown vsync mode :{name}"),
+            )),
+        };
+    }
+
+    Ok(if vsync.to_bool() {
+        PresentMode::Fifo
+    } else {
+        PresentMode::Immediate
+    })
 }
 
 pub fn bind(module: &mut impl magnus::Module) -> Result<(), magnus::Error> {
@@ -165,7 +191,11 @@ pub fn bind(module: &mut impl magnus::Module) -> Result<(), magnus::Error> {
     class.define_singleton_method("new", function!(Screen::new, -1))?;
     class.define_method("alive?", method!(Screen::is_alive, 0))?;
     class.define_method("process_events", method!(Screen::process_events, 0))?;
-    class.define_alias("update", "process_events")?;
+    class.define_method("update", method!(Screen::update, 0))?;
+    class.define_method("batch", method!(Screen::batch, 0))?;
+    class.define_method("frame_count", method!(Screen::frame_count, 0))?;
+    class.define_method("delta", method!(Screen::delta, 0))?;
+    class.define_method("stats", method!(Screen::stats, 0))?;
 
     Ok(())
 }
@@ -0,0 +1,74 @@
+// Copyright (C) 2023 Lily Lyons
+//
+// This file is part of libfm.
+//
+// libfm is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// libfm is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with libfm.  If not, see <http://www.gnu.org/licenses/>.
+
+use libfm_client::SHADER_UNIFORMS;
+use magnus::{function, method, Module, Object};
+
+use crate::{error, viewport::Viewport};
+
+#[magnus::wrap(class = "LibFM::Shader", free_immediately, size)]
+pub struct Shader(pub libfm_client::Shader);
+
+impl Shader {
+    // Raises a ShaderError if the source doesn't compile
+    fn new(viewport: &Viewport, source: String) -> Result<Self, magnus::Error> {
+        libfm_client::Shader::new(&viewport.0, source)
+            .map(Self)
+            .map_err(error::client_error)
+    }
+
+    fn dispose(&self) -> Result<(), magnus::Error> {
+        self.0.dispose().map_err(error::client_error)
+    }
+
+    fn is_disposed(&self) -> bool {
+        self.0.is_disposed()
+    }
+
+    fn uniforms(&self) -> Result<Vec<f32>, magnus::Error> {
+        let uniforms = self.0.uniforms().map_err(error::client_error)?;
+        Ok(uniforms.to_vec())
+    }
+
+    // Missing uniforms are set to zero
+    fn set_uniforms(&self, values: Vec<f32>) -> Result<(), magnus::Error> {
+        if values.len() > SHADER_UNIFORMS {
+            return Err(magnus::Error::new(
+                magnus::exception::arg_error(),
+                format!("shaders take at most {SHADER_UNIFORMS} uniforms"),
+            ));
+        }
+
+        let mut uniforms = [0.0; SHADER_UNIFORMS];
+        uniforms[..values.len()].copy_from_slice(&values);
+        self.0.set_uniforms(uniforms).map_err(error::client_error)
+    }
+}
+
+pub fn bind(module: &mut impl magnus::Module) -> Result<(), magnus::Error> {
+    let class = module.define_class("Shader", Default::default())?;
+    class.const_set("PRELUDE", libfm_client::SHADER_PRELUDE)?;
+    class.const_set("UNIFORMS", SHADER_UNIFORMS)?;
+    class.define_singleton_method("new", function!(Shader::new, 2))?;
+    class.define_method("dispose", method!(Shader::dispose, 0))?;
+    class.define_method("disposed?", method!(Shader::is_disposed, 0))?;
+
+    class.define_method("uniforms", method!(Shader::uniforms, 0))?;
+    class.define_method("uniforms=", method!(Shader::set_uniforms, 1))?;
+
+    Ok(())
+}
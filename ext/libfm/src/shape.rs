@@ -0,0 +1,159 @@
+// Copyright (C) 2023 Lily Lyons
+//
+// This file is part of libfm.
+//
+// libfm is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// libfm is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with libfm.  If not, see <http://www.gnu.org/licenses/>.
+
+use libfm_client::{ShapeConfig, ShapeGeometry};
+use magnus::{function, method, Module, Object};
+
+use crate::{color::Color, error, rect::Rect, viewport::Viewport};
+
+#[magnus::wrap(class = "LibFM::Shape", free_immediately, size)]
+struct Shape(libfm_client::Shape);
+
+impl Shape {
+    fn new(viewport: &Viewport) -> Result<Self, magnus::Error> {
+        libfm_client::Shape::new(&viewport.0)
+            .map(Self)
+            .map_err(error::client_error)
+    }
+
+    fn dispose(&self) -> Result<(), magnus::Error> {
+        self.0.dispose().map_err(error::client_error)
+    }
+
+    fn is_disposed(&self) -> bool {
+        self.0.is_disposed()
+    }
+
+    fn set_geometry(&self, geometry: ShapeGeometry) -> Result<(), magnus::Error> {
+        self.0.set_geometry(geometry).map_err(error::client_error)
+    }
+
+    // Takes either a rect or x, y, width and height
+    fn set_rect(&self, args: &[magnus::Value]) -> Result<(), magnus::Error> {
+        let (x, y, width, height) = match *args {
+            [rect] => rect.try_convert::<&Rect>()?.get(),
+            [x, y, width, height] => (
+                x.try_convert()?,
+                y.try_convert()?,
+                width.try_convert()?,
+                height.try_convert()?,
+            ),
+            _ => return Err(error::arity_error(args.len(), "1 or 4")),
+        };
+
+        self.set_geometry(ShapeGeometry::Rect(libfm_client::Rect {
+            x,
+            y,
+            width,
+            height,
+        }))
+    }
+
+    fn set_line(&self, x1: f32, y1: f32, x2: f32, y2: f32) -> Result<(), magnus::Error> {
+        self.set_geometry(ShapeGeometry::Line {
+            from: (x1, y1),
+            to: (x2, y2),
+        })
+    }
+
+    fn set_circle(&self, x: f32, y: f32, radius: f32) -> Result<(), magnus::Error> {
+        self.set_geometry(ShapeGeometry::Circle {
+            center: (x, y),
+            radius,
+        })
+    }
+
+    // Takes an array of [x, y] points
+    fn set_polygon(&self, points: Vec<(f32, f32)>) -> Result<(), magnus::Error> {
+        self.set_geometry(ShapeGeometry::Polygon(points))
+    }
+
+    fn config(&self) -> Result<ShapeConfig, magnus::Error> {
+        self.0.config().map_err(error::client_error)
+    }
+
+    fn configure(&self, func: impl FnOnce(&mut ShapeConfig)) -> Result<(), magnus::Error> {
+        self.0.configure(func).map_err(error::client_error)
+    }
+
+    fn get_z(&self) -> Result<i32, magnus::Error> {
+        Ok(self.config()?.z)
+    }
+
+    fn set_z(&self, z: i32) -> Result<(), magnus::Error> {
+        self.configure(|config| config.z = z)
+    }
+
+    // Returns a copy, so changes to it only apply once it's assigned back
+    fn get_color(&self) -> Result<Color, magnus::Error> {
+        Ok(Color::from_screen(self.config()?.color))
+    }
+
+    fn set_color(&self, color: &Color) -> Result<(), magnus::Error> {
+        let color = color.to_screen();
+        self.configure(|config| config.color = color)
+    }
+
+    fn get_filled(&self) -> Result<bool, magnus::Error> {
+        Ok(self.config()?.filled)
+    }
+
+    fn set_filled(&self, filled: bool) -> Result<(), magnus::Error> {
+        self.configure(|config| config.filled = filled)
+    }
+
+    fn get_line_width(&self) -> Result<f32, magnus::Error> {
+        Ok(self.config()?.line_width)
+    }
+
+    fn set_line_width(&self, line_width: f32) -> Result<(), magnus::Error> {
+        self.configure(|config| config.line_width = line_width.max(0.0))
+    }
+
+    fn get_visible(&self) -> Result<bool, magnus::Error> {
+        Ok(self.config()?.visible)
+    }
+
+    fn set_visible(&self, visible: bool) -> Result<(), magnus::Error> {
+        self.configure(|config| config.visible = visible)
+    }
+}
+
+pub fn bind(module: &mut impl magnus::Module) -> Result<(), magnus::Error> {
+    let class = module.define_class("Shape", Default::default())?;
+    class.define_singleton_method("new", function!(Shape::new, 1))?;
+    class.define_method("dispose", method!(Shape::dispose, 0))?;
+    class.define_method("disposed?", method!(Shape::is_disposed, 0))?;
+
+    class.define_method("set_rect", method!(Shape::set_rect, -1))?;
+    class.define_method("set_line", method!(Shape::set_line, 4))?;
+    class.define_method("set_circle", method!(Shape::set_circle, 3))?;
+    class.define_method("set_polygon", method!(Shape::set_polygon, 1))?;
+
+    class.define_method("z", method!(Shape::get_z, 0))?;
+    class.define_method("z=", method!(Shape::set_z, 1))?;
+    class.define_method("color", method!(Shape::get_color, 0))?;
+    class.define_method("color=", method!(Shape::set_color, 1))?;
+    class.define_method("filled", method!(Shape::get_filled, 0))?;
+    class.define_method("filled=", method!(Shape::set_filled, 1))?;
+    class.define_method("line_width", method!(Shape::get_line_width, 0))?;
+    class.define_method("line_width=", method!(Shape::set_line_width, 1))?;
+    class.define_method("visible", method!(Shape::get_visible, 0))?;
+    class.define_method("visible=", method!(Shape::set_visible, 1))?;
+
+    Ok(())
+}
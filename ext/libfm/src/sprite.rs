@@ -15,112 +15,81 @@
 // You should have received a copy of the GNU General Public License
 // along with libfm.  If not, see <http://www.gnu.org/licenses/>.
 
-use crate::{screen::Screen, send, viewport::Viewport};
+use crate::{error, shader::Shader, viewport::Viewport};
 use magnus::{function, method, Module, Object};
-use parking_lot::Mutex;
-use screen::Message;
 
 #[magnus::wrap(class = "LibFM::Sprite", free_immediately, size)]
-struct Sprite {
-    id: usize,
-    viewport_id: usize,
-    screen: Screen,
-    position: Mutex<(i32, i32, i32)>,
-}
-
-impl Drop for Sprite {
-    fn drop(&mut self) {
-        self.close();
-    }
-}
+struct Sprite(libfm_client::Sprite);
 
 impl Sprite {
     pub fn new(viewport: &Viewport) -> Result<Self, magnus::Error> {
-        let screen = viewport.screen.clone();
-
-        let id = rand::random();
-        send!(screen, Message::CreateSprite(id, viewport.id));
+        libfm_client::Sprite::new(&viewport.0)
+            .map(Self)
+            .map_err(error::client_error)
+    }
 
-        Ok(Self {
-            id,
-            screen,
-            viewport_id: viewport.id,
-            position: Mutex::new((0, 0, 0)),
-        })
+    fn dispose(&self) -> Result<(), magnus::Error> {
+        self.0.dispose().map_err(error::client_error)
     }
 
-    fn close(&self) {
-        use futures::prelude::*;
-
-        let lock = &mut *self.screen.lock();
-        lock.runtime.block_on(async {
-            if let Err(e) = lock
-                .writer
-                .send(Message::RemoveSprite(self.id, self.viewport_id))
-                .await
-            {
-                eprintln!("error sending message {e:?}")
-            }
-        });
+    fn is_disposed(&self) -> bool {
+        self.0.is_disposed()
     }
 
     fn set(&self, filename: String) -> Result<(), magnus::Error> {
-        if !std::path::Path::new(&filename).exists() {
-            return Err(magnus::Error::new(
-                magnus::exception::io_error(),
-                format!("File does not exist {filename}"),
-            ));
-        }
-
-        send!(
-            self.screen,
-            Message::SetSprite(self.id, self.viewport_id, filename)
-        );
-
-        Ok(())
+        self.0.set(filename).map_err(error::client_error)
     }
 
-    fn reposition(&self, x: i32, y: i32, z: i32) -> Result<(), magnus::Error> {
-        *self.position.lock() = (x, y, z);
+    // nil goes back to the default shader
+    fn set_shader(&self, shader: Option<&Shader>) -> Result<(), magnus::Error> {
+        self.0
+            .set_shader(shader.map(|shader| &shader.0))
+            .map_err(error::client_error)
+    }
 
-        send!(
-            self.screen,
-            Message::RepositionSprite(self.id, self.viewport_id, x, y, z)
-        );
+    fn reposition(&self, x: i32, y: i32, z: i32) -> Result<(), magnus::Error> {
+        self.0.reposition(x, y, z).map_err(error::client_error)
+    }
 
-        Ok(())
+    fn position(&self) -> Result<(i32, i32, i32), magnus::Error> {
+        self.0.position().map_err(error::client_error)
     }
 
-    fn get_x(&self) -> i32 {
-        self.position.lock().0
+    fn get_x(&self) -> Result<i32, magnus::Error> {
+        Ok(self.position()?.0)
     }
 
     fn set_x(&self, x: i32) -> Result<(), magnus::Error> {
-        self.reposition(x, self.get_y(), self.get_z())
+        let (_, y, z) = self.position()?;
+        self.reposition(x, y, z)
     }
 
-    fn get_y(&self) -> i32 {
-        self.position.lock().1
+    fn get_y(&self) -> Result<i32, magnus::Error> {
+        Ok(self.position()?.1)
     }
 
     fn set_y(&self, y: i32) -> Result<(), magnus::Error> {
-        self.reposition(self.get_x(), y, self.get_z())
+        let (x, _, z) = self.position()?;
+        self.reposition(x, y, z)
     }
 
-    fn get_z(&self) -> i32 {
-        self.position.lock().2
+    fn get_z(&self) -> Result<i32, magnus::Error> {
+        Ok(self.position()?.2)
     }
 
     fn set_z(&self, z: i32) -> Result<(), magnus::Error> {
-        self.reposition(self.get_x(), self.get_y(), z)
+        let (x, y, _) = self.position()?;
+        self.reposition(x, y, z)
     }
 }
 
 pub fn bind(module: &mut impl magnus::Module) -> Result<(), magnus::Error> {
     let class = module.define_class("Sprite", Default::default())?;
     class.define_singleton_method("new", function!(Sprite::new, 1))?;
-    class.define_method("close", method!(Sprite::close, 0))?;
+    class.define_method("dispose", method!(Sprite::dispose, 0))?;
+    class.define_method("disposed?", method!(Sprite::is_disposed, 0))?;
     class.define_method("set", method!(Sprite::set, 1))?;
+    class.define_method("shader=", method!(Sprite::set_shader, 1))?;
     class.define_method("move", method!(Sprite::reposition, 3))?;
 
     class.define_method("x", method!(Sprite::get_x, 0))?;
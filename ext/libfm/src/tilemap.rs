@@ -0,0 +1,219 @@
+// Copyright (C) 2023 Lily Lyons
+//
+// This file is part of libfm.
+//
+// libfm is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// libfm is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with libfm.  If not, see <http://www.gnu.org/licenses/>.
+
+use libfm_client::{TileData, TileInfo, TileUpdate, TilemapConfig};
+use magnus::{function, method, Module, Object};
+
+use crate::{error, viewport::Viewport};
+
+#[magnus::wrap(class = "LibFM::Tilemap", free_immediately, size)]
+struct Tilemap(libfm_client::Tilemap);
+
+impl Tilemap {
+    fn new(viewport: &Viewport) -> Result<Self, magnus::Error> {
+        libfm_client::Tilemap::new(&viewport.0)
+            .map(Self)
+            .map_err(error::client_error)
+    }
+
+    fn dispose(&self) -> Result<(), magnus::Error> {
+        self.0.dispose().map_err(error::client_error)
+    }
+
+    fn is_disposed(&self) -> bool {
+        self.0.is_disposed()
+    }
+
+    fn set_tileset(&self, filename: String) -> Result<(), magnus::Error> {
+        self.0.set_tileset(filename).map_err(error::client_error)
+    }
+
+    fn data(&self) -> Result<TileData, magnus::Error> {
+        self.0.data().map_err(error::client_error)
+    }
+
+    // Like Table#resize, tiles that are still on the map are kept
+    fn resize(&self, width: u32, height: u32, layers: u32) -> Result<(), magnus::Error> {
+        let old = self.data()?;
+        let mut data = TileData::new(width, height, layers);
+        for layer in 0..layers.min(old.layers) {
+            for y in 0..height.min(old.height) {
+                for x in 0..width.min(old.width) {
+                    if let (Some(from), Some(to)) =
+                        (old.index(x, y, layer), data.index(x, y, layer))
+                    {
+                        data.tiles[to] = old.tiles[from];
+                    }
+                }
+            }
+        }
+
+        self.0.set_data(data).map_err(error::client_error)
+    }
+
+    fn width(&self) -> Result<u32, magnus::Error> {
+        Ok(self.data()?.width)
+    }
+
+    fn height(&self) -> Result<u32, magnus::Error> {
+        Ok(self.data()?.height)
+    }
+
+    fn layers(&self) -> Result<u32, magnus::Error> {
+        Ok(self.data()?.layers)
+    }
+
+    // Takes x, y and an optional layer, and returns nil off the map
+    fn get_tile(&self, args: &[magnus::Value]) -> Result<Option<u16>, magnus::Error> {
+        let (x, y, layer) = match *args {
+            [x, y] => (x.try_convert()?, y.try_convert()?, 0),
+            [x, y, layer] => (x.try_convert()?, y.try_convert()?, layer.try_convert()?),
+            _ => return Err(error::arity_error(args.len(), "2 or 3")),
+        };
+
+        self.0.tile(x, y, layer).map_err(error::client_error)
+    }
+
+    // Takes x, y, an optional layer and the tile
+    fn set_tile(&self, args: &[magnus::Value]) -> Result<(), magnus::Error> {
+        let (x, y, layer, tile) = match *args {
+            [x, y, tile] => (x.try_convert()?, y.try_convert()?, 0, tile.try_convert()?),
+            [x, y, layer, tile] => (
+                x.try_convert()?,
+                y.try_convert()?,
+                layer.try_convert()?,
+                tile.try_convert()?,
+            ),
+            _ => return Err(error::arity_error(args.len(), "3 or 4")),
+        };
+
+        self.0
+            .set_tile(x, y, layer, tile)
+            .map_err(error::client_error)
+    }
+
+    // Sends many tiles as one message, given as [x, y, layer, tile] arrays
+    fn set_tiles(&self, tiles: Vec<(u32, u32, u32, u16)>) -> Result<(), magnus::Error> {
+        let updates = tiles
+            .into_iter()
+            .map(|(x, y, layer, tile)| TileUpdate { x, y, layer, tile })
+            .collect();
+
+        self.0.set_tiles(updates).map_err(error::client_error)
+    }
+
+    fn set_tile_info(&self, tile: u16, priority: u8, frames: u8) -> Result<(), magnus::Error> {
+        let frames = frames.max(1);
+        self.0
+            .set_tile_info(vec![(tile, TileInfo { priority, frames })])
+            .map_err(error::client_error)
+    }
+
+    fn config(&self) -> Result<TilemapConfig, magnus::Error> {
+        self.0.config().map_err(error::client_error)
+    }
+
+    fn configure(&self, func: impl FnOnce(&mut TilemapConfig)) -> Result<(), magnus::Error> {
+        self.0.configure(func).map_err(error::client_error)
+    }
+
+    fn get_ox(&self) -> Result<i32, magnus::Error> {
+        Ok(self.config()?.ox)
+    }
+
+    fn set_ox(&self, ox: i32) -> Result<(), magnus::Error> {
+        self.configure(|config| config.ox = ox)
+    }
+
+    fn get_oy(&self) -> Result<i32, magnus::Error> {
+        Ok(self.config()?.oy)
+    }
+
+    fn set_oy(&self, oy: i32) -> Result<(), magnus::Error> {
+        self.configure(|config| config.oy = oy)
+    }
+
+    fn get_z(&self) -> Result<i32, magnus::Error> {
+        Ok(self.config()?.z)
+    }
+
+    fn set_z(&self, z: i32) -> Result<(), magnus::Error> {
+        self.configure(|config| config.z = z)
+    }
+
+    fn get_tile_size(&self) -> Result<u32, magnus::Error> {
+        Ok(self.config()?.tile_size)
+    }
+
+    fn set_tile_size(&self, tile_size: u32) -> Result<(), magnus::Error> {
+        if tile_size == 0 {
+            return Err(magnus::Error::new(
+                magnus::exception::arg_error(),
+                "tile size must be positive",
+            ));
+        }
+        self.configure(|config| config.tile_size = tile_size)
+    }
+
+    fn get_anim_interval(&self) -> Result<u32, magnus::Error> {
+        Ok(self.config()?.anim_interval)
+    }
+
+    fn set_anim_interval(&self, anim_interval: u32) -> Result<(), magnus::Error> {
+        self.configure(|config| config.anim_interval = anim_interval.max(1))
+    }
+
+    fn get_visible(&self) -> Result<bool, magnus::Error> {
+        Ok(self.config()?.visible)
+    }
+
+    fn set_visible(&self, visible: bool) -> Result<(), magnus::Error> {
+        self.configure(|config| config.visible = visible)
+    }
+}
+
+pub fn bind(module: &mut impl magnus::Module) -> Result<(), magnus::Error> {
+    let class = module.define_class("Tilemap", Default::default())?;
+    class.define_singleton_method("new", function!(Tilemap::new, 1))?;
+    class.define_method("dispose", method!(Tilemap::dispose, 0))?;
+    class.define_method("disposed?", method!(Tilemap::is_disposed, 0))?;
+    class.define_method("tileset=", method!(Tilemap::set_tileset, 1))?;
+
+    class.define_method("resize", method!(Tilemap::resize, 3))?;
+    class.define_method("width", method!(Tilemap::width, 0))?;
+    class.define_method("height", method!(Tilemap::height, 0))?;
+    class.define_method("layers", method!(Tilemap::layers, 0))?;
+    class.define_method("[]", method!(Tilemap::get_tile, -1))?;
+    class.define_method("[]=", method!(Tilemap::set_tile, -1))?;
+    class.define_method("set_tiles", method!(Tilemap::set_tiles, 1))?;
+    class.define_method("set_tile_info", method!(Tilemap::set_tile_info, 3))?;
+
+    class.define_method("ox", method!(Tilemap::get_ox, 0))?;
+    class.define_method("ox=", method!(Tilemap::set_ox, 1))?;
+    class.define_method("oy", method!(Tilemap::get_oy, 0))?;
+    class.define_method("oy=", method!(Tilemap::set_oy, 1))?;
+    class.define_method("z", method!(Tilemap::get_z, 0))?;
+    class.define_method("z=", method!(Tilemap::set_z, 1))?;
+    class.define_method("tile_size", method!(Tilemap::get_tile_size, 0))?;
+    class.define_method("tile_size=", method!(Tilemap::set_tile_size, 1))?;
+    class.define_method("anim_interval", method!(Tilemap::get_anim_interval, 0))?;
+    class.define_method("anim_interval=", method!(Tilemap::set_anim_interval, 1))?;
+    class.define_method("visible", method!(Tilemap::get_visible, 0))?;
+    class.define_method("visible=", method!(Tilemap::set_visible, 1))?;
+
+    Ok(())
+}
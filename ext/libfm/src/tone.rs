@@ -0,0 +1,190 @@
+// Copyright (C) 2023 Lily Lyons
+//
+// This file is part of libfm.
+//
+// libfm is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// libfm is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with libfm.  If not, see <http://www.gnu.org/licenses/>.
+
+use magnus::{function, method, Module, Object, RString};
+use std::cell::Cell;
+
+use crate::color::{dump_doubles, load_doubles};
+use crate::error;
+
+/// Red, green and blue are kept between -255 and 255, gray between 0 and 255, like in RGSS.
+#[magnus::wrap(class = "LibFM::Tone", free_immediately, size)]
+pub struct Tone {
+    red: Cell<f64>,
+    green: Cell<f64>,
+    blue: Cell<f64>,
+    gray: Cell<f64>,
+}
+
+impl Tone {
+    pub fn from_parts(red: f64, green: f64, blue: f64, gray: f64) -> Self {
+        let tone = Self {
+            red: Cell::new(0.0),
+            green: Cell::new(0.0),
+            blue: Cell::new(0.0),
+            gray: Cell::new(0.0),
+        };
+        tone.set_parts(red, green, blue, gray);
+
+        tone
+    }
+
+    pub fn from_screen(tone: libfm_client::Tone) -> Self {
+        let libfm_client::Tone {
+            red,
+            green,
+            blue,
+            gray,
+        } = tone;
+        Self::from_parts(red.into(), green.into(), blue.into(), gray.into())
+    }
+
+    pub fn to_screen(&self) -> libfm_client::Tone {
+        let (red, green, blue, gray) = self.get();
+        libfm_client::Tone {
+            red: red as f32,
+            green: green as f32,
+            blue: blue as f32,
+            gray: gray as f32,
+        }
+    }
+
+    /// The tone's red, green, blue and gray.
+    pub fn get(&self) -> (f64, f64, f64, f64) {
+        (
+            self.red.get(),
+            self.green.get(),
+            self.blue.get(),
+            self.gray.get(),
+        )
+    }
+
+    fn set_parts(&self, red: f64, green: f64, blue: f64, gray: f64) {
+        self.set_red(red);
+        self.set_green(green);
+        self.set_blue(blue);
+        self.set_gray(gray);
+    }
+
+    fn new(args: &[magnus::Value]) -> Result<Self, magnus::Error> {
+        let tone = Self::from_parts(0.0, 0.0, 0.0, 0.0);
+        match args.len() {
+            0 => {}
+            3 | 4 => tone.set(args)?,
+            given => return Err(error::arity_error(given, "0, 3 or 4")),
+        }
+
+        Ok(tone)
+    }
+
+    // Takes either another tone or red, green, blue and an optional gray
+    fn set(&self, args: &[magnus::Value]) -> Result<(), magnus::Error> {
+        let (red, green, blue, gray) = match *args {
+            [tone] => tone.try_convert::<&Tone>()?.get(),
+            [red, green, blue] => (
+                red.try_convert()?,
+                green.try_convert()?,
+                blue.try_convert()?,
+                0.0,
+            ),
+            [red, green, blue, gray] => (
+                red.try_convert()?,
+                green.try_convert()?,
+                blue.try_convert()?,
+                gray.try_convert()?,
+            ),
+            _ => return Err(error::arity_error(args.len(), "1, 3 or 4")),
+        };
+
+        self.set_parts(red, green, blue, gray);
+        Ok(())
+    }
+
+    fn eq(&self, other: magnus::Value) -> bool {
+        other
+            .try_convert::<&Tone>()
+            .is_ok_and(|other| other.get() == self.get())
+    }
+
+    fn to_s(&self) -> String {
+        let (red, green, blue, gray) = self.get();
+        format!("({red:.6}, {green:.6}, {blue:.6}, {gray:.6})")
+    }
+
+    fn dump(&self, _level: i32) -> RString {
+        let (red, green, blue, gray) = self.get();
+        dump_doubles([red, green, blue, gray])
+    }
+
+    fn load(data: RString) -> Result<Self, magnus::Error> {
+        let [red, green, blue, gray] = load_doubles(data, "tone")?;
+        Ok(Self::from_parts(red, green, blue, gray))
+    }
+
+    fn get_red(&self) -> f64 {
+        self.red.get()
+    }
+
+    fn set_red(&self, red: f64) {
+        self.red.set(red.clamp(-255.0, 255.0))
+    }
+
+    fn get_green(&self) -> f64 {
+        self.green.get()
+    }
+
+    fn set_green(&self, green: f64) {
+        self.green.set(green.clamp(-255.0, 255.0))
+    }
+
+    fn get_blue(&self) -> f64 {
+        self.blue.get()
+    }
+
+    fn set_blue(&self, blue: f64) {
+        self.blue.set(blue.clamp(-255.0, 255.0))
+    }
+
+    fn get_gray(&self) -> f64 {
+        self.gray.get()
+    }
+
+    fn set_gray(&self, gray: f64) {
+        self.gray.set(gray.clamp(0.0, 255.0))
+    }
+}
+
+pub fn bind(module: &mut impl magnus::Module) -> Result<(), magnus::Error> {
+    let class = module.define_class("Tone", Default::default())?;
+    class.define_singleton_method("new", function!(Tone::new, -1))?;
+    class.define_singleton_method("_load", function!(Tone::load, 1))?;
+    class.define_method("set", method!(Tone::set, -1))?;
+    class.define_method("==", method!(Tone::eq, 1))?;
+    class.define_method("to_s", method!(Tone::to_s, 0))?;
+    class.define_method("_dump", method!(Tone::dump, 1))?;
+
+    class.define_method("red", method!(Tone::get_red, 0))?;
+    class.define_method("red=", method!(Tone::set_red, 1))?;
+    class.define_method("green", method!(Tone::get_green, 0))?;
+    class.define_method("green=", method!(Tone::set_green, 1))?;
+    class.define_method("blue", method!(Tone::get_blue, 0))?;
+    class.define_method("blue=", method!(Tone::set_blue, 1))?;
+    class.define_method("gray", method!(Tone::get_gray, 0))?;
+    class.define_method("gray=", method!(Tone::set_gray, 1))?;
+
+    Ok(())
+}
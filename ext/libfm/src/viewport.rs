@@ -15,22 +15,13 @@
 // You should have received a copy of the GNU General Public License
 // along with libfm.  If not, see <http://www.gnu.org/licenses/>.
 
-use magnus::{function, method, Module, Object};
-use screen::Message;
+use libfm_client::Effect;
+use magnus::{function, method, Module, Object, RArray, Symbol};
 
-use crate::{screen::Screen, send};
+use crate::{error, rect::Rect, screen::Screen, shader::Shader};
 
 #[magnus::wrap(class = "LibFM::Viewport", free_immediately, size)]
-pub struct Viewport {
-    pub id: usize,
-    pub screen: Screen,
-}
-
-impl Drop for Viewport {
-    fn drop(&mut self) {
-        self.close();
-    }
-}
+pub struct Viewport(pub libfm_client::Window);
 
 impl Viewport {
     fn new(args: &[magnus::Value]) -> Result<Self, magnus::Error> {
@@ -40,23 +31,40 @@ impl Viewport {
         let args = magnus::scan_args::get_kwargs::<_, (), _, ()>(
             args.keywords,
             &[],
-            &["position", "z", "title", "visible", "size", "decorations"],
+            &[
+                "position",
+                "z",
+                "title",
+                "visible",
+                "size",
+                "decorations",
+                "rect",
+            ],
         )?;
-        let (pos, z, title, visible, size, decorations): (
-            Option<_>,
+        let (pos, z, title, visible, size, decorations, rect): (
             Option<_>,
             Option<_>,
             Option<_>,
             Option<_>,
+            Option<(u32, u32)>,
             Option<_>,
+            Option<&Rect>,
         ) = args.optional;
+        // A rect sets both the position and the size
+        let (pos, size) = match rect.map(Rect::get) {
+            Some((x, y, width, height)) => (
+                Some((x, y)),
+                Some((width.max(0) as u32, height.max(0) as u32)),
+            ),
+            None => (pos, size),
+        };
 
         let title = title.unwrap_or_else(|| "screen exe".to_string());
         let visible = visible.unwrap_or_default();
         let decorations = decorations.unwrap_or_default();
         let size = size.unwrap_or((640, 480));
 
-        let config = screen::WindowConfig {
+        let config = libfm_client::WindowConfig {
             title,
             pos,
             visible,
@@ -64,46 +72,178 @@ impl Viewport {
             size,
             z,
         };
-        let id = rand::random();
 
-        send!(screen, screen::Message::CreateWindow(config, id));
+        libfm_client::Window::new(&screen.0, config)
+            .map(Self)
+            .map_err(error::client_error)
+    }
 
-        Ok(Viewport {
-            id,
-            screen: screen.clone(),
-        })
+    // The last state the screen reported, refreshed by Screen#update
+    fn state(&self) -> Result<libfm_client::WindowState, magnus::Error> {
+        self.0.state().map_err(error::client_error)
     }
 
-    fn reposition(&self, x: i32, y: i32) -> Result<(), magnus::Error> {
-        send!(self.screen, Message::RepositionWindow(x, y, self.id));
+    fn rect(&self) -> Result<Rect, magnus::Error> {
+        let state = self.state()?;
+        Ok(Rect::from_parts(
+            state.x,
+            state.y,
+            state.width as i32,
+            state.height as i32,
+        ))
+    }
+
+    fn x(&self) -> Result<i32, magnus::Error> {
+        Ok(self.state()?.x)
+    }
+
+    fn y(&self) -> Result<i32, magnus::Error> {
+        Ok(self.state()?.y)
+    }
+
+    fn width(&self) -> Result<u32, magnus::Error> {
+        Ok(self.state()?.width)
+    }
 
-        Ok(())
+    fn height(&self) -> Result<u32, magnus::Error> {
+        Ok(self.state()?.height)
+    }
+
+    fn title(&self) -> Result<String, magnus::Error> {
+        Ok(self.state()?.title)
+    }
+
+    fn is_visible(&self) -> Result<bool, magnus::Error> {
+        Ok(self.state()?.visible)
+    }
+
+    fn is_focused(&self) -> Result<bool, magnus::Error> {
+        Ok(self.state()?.focused)
+    }
+
+    fn reposition(&self, x: i32, y: i32) -> Result<(), magnus::Error> {
+        self.0.reposition(x, y).map_err(error::client_error)
     }
 
     fn resize(&self, x: u32, y: u32) -> Result<(), magnus::Error> {
-        send!(self.screen, Message::ResizeWindow(x, y, self.id));
+        self.0.resize(x, y).map_err(error::client_error)
+    }
 
-        Ok(())
+    // Returns the window's current scene as PNG encoded bytes
+    fn snapshot(&self) -> Result<magnus::RString, magnus::Error> {
+        let png = self.0.snapshot().map_err(error::client_error)?;
+        Ok(magnus::RString::from_slice(&png))
     }
 
-    fn close(&self) {
-        use futures::prelude::*;
+    // Effects are arrays of a name and its parameters, like [:shake, 4, 0.5]
+    fn effects(&self) -> Result<RArray, magnus::Error> {
+        let effects = self.0.effects().map_err(error::client_error)?;
+        let effects = effects
+            .into_iter()
+            .map(effect_to_ruby)
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(RArray::from_vec(effects))
+    }
+
+    fn set_effects(&self, effects: Vec<magnus::Value>, duration: u32) -> Result<(), magnus::Error> {
+        let effects = effects
+            .into_iter()
+            .map(effect_from_ruby)
+            .collect::<Result<_, _>>()?;
+        self.0
+            .set_effects(effects, duration)
+            .map_err(error::client_error)
+    }
+
+    fn assign_effects(&self, effects: Vec<magnus::Value>) -> Result<(), magnus::Error> {
+        self.set_effects(effects, 0)
+    }
+
+    // Runs after the effects. nil stops running it
+    fn set_shader(&self, shader: Option<&Shader>) -> Result<(), magnus::Error> {
+        self.0
+            .set_shader(shader.map(|shader| &shader.0))
+            .map_err(error::client_error)
+    }
+
+    // Also disposes every sprite on the viewport
+    fn dispose(&self) -> Result<(), magnus::Error> {
+        self.0.dispose().map_err(error::client_error)
+    }
+
+    fn is_disposed(&self) -> bool {
+        self.0.is_disposed()
+    }
+}
+
+fn effect_from_ruby(value: magnus::Value) -> Result<Effect, magnus::Error> {
+    let array: RArray = value.try_convert()?;
+    let name: Symbol = array.entry(0)?;
+    let params = (1..array.len() as isize)
+        .map(|index| array.entry::<f32>(index))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(match (&*name.name()?, params.as_slice()) {
+        ("blur", &[radius]) => Effect::Blur { radius },
+        ("crt", &[scanlines, curvature]) => Effect::Crt {
+            scanlines,
+            curvature,
+        },
+        ("chromatic_aberration", &[offset]) => Effect::ChromaticAberration { offset },
+        ("grayscale", &[amount]) => Effect::Grayscale { amount },
+        ("pixelate", &[size]) => Effect::Pixelate { size },
+        ("shake", &[amplitude, speed]) => Effect::Shake { amplitude, speed },
+        _ => {
+            return Err(magnus::Error::new(
+                magnus::exception::arg_error(),
+                format!("invalid effect {}", value.inspect()),
+            ))
+        }
+    })
+}
 
-        let lock = &mut *self.screen.lock();
-        lock.runtime.block_on(async {
-            if let Err(e) = lock.writer.send(Message::DeleteWindow(self.id)).await {
-                eprintln!("error sending message {e:?}")
-            }
-        });
+fn effect_to_ruby(effect: Effect) -> Result<RArray, magnus::Error> {
+    let (name, params) = match effect {
+        Effect::Blur { radius } => ("blur", vec![radius]),
+        Effect::Crt {
+            scanlines,
+            curvature,
+        } => ("crt", vec![scanlines, curvature]),
+        Effect::ChromaticAberration { offset } => ("chromatic_aberration", vec![offset]),
+        Effect::Grayscale { amount } => ("grayscale", vec![amount]),
+        Effect::Pixelate { size } => ("pixelate", vec![size]),
+        Effect::Shake { amplitude, speed } => ("shake", vec![amplitude, speed]),
+    };
+
+    let array = RArray::new();
+    array.push(Symbol::new(name))?;
+    for param in params {
+        array.push(param)?;
     }
+    Ok(array)
 }
 
 pub fn bind(module: &mut impl magnus::Module) -> Result<(), magnus::Error> {
     let class = module.define_class("Viewport", Default::default())?;
     class.define_singleton_method("new", function!(Viewport::new, -1))?;
     class.define_method("move", method!(Viewport::reposition, 2))?;
-    class.define_method("close", method!(Viewport::close, 0))?;
+    class.define_method("dispose", method!(Viewport::dispose, 0))?;
+    class.define_method("disposed?", method!(Viewport::is_disposed, 0))?;
     class.define_method("resize", method!(Viewport::resize, 2))?;
+    class.define_method("snapshot", method!(Viewport::snapshot, 0))?;
+
+    class.define_method("rect", method!(Viewport::rect, 0))?;
+    class.define_method("x", method!(Viewport::x, 0))?;
+    class.define_method("y", method!(Viewport::y, 0))?;
+    class.define_method("width", method!(Viewport::width, 0))?;
+    class.define_method("height", method!(Viewport::height, 0))?;
+    class.define_method("title", method!(Viewport::title, 0))?;
+    class.define_method("visible?", method!(Viewport::is_visible, 0))?;
+    class.define_method("focused?", method!(Viewport::is_focused, 0))?;
+    class.define_method("effects", method!(Viewport::effects, 0))?;
+    class.define_method("effects=", method!(Viewport::assign_effects, 1))?;
+    class.define_method("set_effects", method!(Viewport::set_effects, 2))?;
+    class.define_method("shader=", method!(Viewport::set_shader, 1))?;
 
     Ok(())
 }
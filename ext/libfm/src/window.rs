@@ -0,0 +1,250 @@
+// Copyright (C) 2023 Lily Lyons
+//
+// This file is part of libfm.
+//
+// libfm is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// libfm is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with libfm.  If not, see <http://www.gnu.org/licenses/>.
+
+use libfm_client::{EdgeMode, PanelConfig};
+use magnus::{function, method, Module, Object};
+
+use crate::{error, rect::Rect, viewport::Viewport};
+
+// An RGSS window, not to be confused with the OS windows viewports wrap
+#[magnus::wrap(class = "LibFM::Window", free_immediately, size)]
+struct Window(libfm_client::Panel);
+
+impl Window {
+    fn new(viewport: &Viewport) -> Result<Self, magnus::Error> {
+        libfm_client::Panel::new(&viewport.0)
+            .map(Self)
+            .map_err(error::client_error)
+    }
+
+    fn dispose(&self) -> Result<(), magnus::Error> {
+        self.0.dispose().map_err(error::client_error)
+    }
+
+    fn is_disposed(&self) -> bool {
+        self.0.is_disposed()
+    }
+
+    fn set_windowskin(&self, filename: String) -> Result<(), magnus::Error> {
+        self.0.set_skin(filename).map_err(error::client_error)
+    }
+
+    fn set_contents(&self, filename: Option<String>) -> Result<(), magnus::Error> {
+        match filename {
+            Some(filename) => self.0.set_contents(filename),
+            None => self.0.clear_contents(),
+        }
+        .map_err(error::client_error)
+    }
+
+    fn config(&self) -> Result<PanelConfig, magnus::Error> {
+        self.0.config().map_err(error::client_error)
+    }
+
+    fn configure(&self, func: impl FnOnce(&mut PanelConfig)) -> Result<(), magnus::Error> {
+        self.0.configure(func).map_err(error::client_error)
+    }
+
+    fn move_to(&self, x: i32, y: i32, width: i32, height: i32) -> Result<(), magnus::Error> {
+        self.configure(|config| {
+            config.rect = libfm_client::Rect {
+                x,
+                y,
+                width,
+                height,
+            }
+        })
+    }
+
+    fn get_x(&self) -> Result<i32, magnus::Error> {
+        Ok(self.config()?.rect.x)
+    }
+
+    fn set_x(&self, x: i32) -> Result<(), magnus::Error> {
+        self.configure(|config| config.rect.x = x)
+    }
+
+    fn get_y(&self) -> Result<i32, magnus::Error> {
+        Ok(self.config()?.rect.y)
+    }
+
+    fn set_y(&self, y: i32) -> Result<(), magnus::Error> {
+        self.configure(|config| config.rect.y = y)
+    }
+
+    fn get_width(&self) -> Result<i32, magnus::Error> {
+        Ok(self.config()?.rect.width)
+    }
+
+    fn set_width(&self, width: i32) -> Result<(), magnus::Error> {
+        self.configure(|config| config.rect.width = width)
+    }
+
+    fn get_height(&self) -> Result<i32, magnus::Error> {
+        Ok(self.config()?.rect.height)
+    }
+
+    fn set_height(&self, height: i32) -> Result<(), magnus::Error> {
+        self.configure(|config| config.rect.height = height)
+    }
+
+    fn get_z(&self) -> Result<i32, magnus::Error> {
+        Ok(self.config()?.z)
+    }
+
+    fn set_z(&self, z: i32) -> Result<(), magnus::Error> {
+        self.configure(|config| config.z = z)
+    }
+
+    fn get_ox(&self) -> Result<i32, magnus::Error> {
+        Ok(self.config()?.ox)
+    }
+
+    fn set_ox(&self, ox: i32) -> Result<(), magnus::Error> {
+        self.configure(|config| config.ox = ox)
+    }
+
+    fn get_oy(&self) -> Result<i32, magnus::Error> {
+        Ok(self.config()?.oy)
+    }
+
+    fn set_oy(&self, oy: i32) -> Result<(), magnus::Error> {
+        self.configure(|config| config.oy = oy)
+    }
+
+    fn get_padding(&self) -> Result<i32, magnus::Error> {
+        Ok(self.config()?.padding)
+    }
+
+    fn set_padding(&self, padding: i32) -> Result<(), magnus::Error> {
+        self.configure(|config| config.padding = padding)
+    }
+
+    // RGSS1 naming: stretched edges unless this is false, in which case they're tiled
+    fn get_stretch(&self) -> Result<bool, magnus::Error> {
+        Ok(self.config()?.edges == EdgeMode::Stretch)
+    }
+
+    fn set_stretch(&self, stretch: bool) -> Result<(), magnus::Error> {
+        let edges = if stretch {
+            EdgeMode::Stretch
+        } else {
+            EdgeMode::Tile
+        };
+        self.configure(|config| config.edges = edges)
+    }
+
+    // Returns a copy, so changes to it only apply once it's assigned back
+    fn get_cursor_rect(&self) -> Result<Rect, magnus::Error> {
+        let cursor = self.config()?.cursor;
+        Ok(Rect::from_parts(
+            cursor.x,
+            cursor.y,
+            cursor.width,
+            cursor.height,
+        ))
+    }
+
+    fn set_cursor_rect(&self, rect: &Rect) -> Result<(), magnus::Error> {
+        let (x, y, width, height) = rect.get();
+        self.configure(|config| {
+            config.cursor = libfm_client::Rect {
+                x,
+                y,
+                width,
+                height,
+            }
+        })
+    }
+
+    fn get_opacity(&self) -> Result<u8, magnus::Error> {
+        Ok(self.config()?.opacity)
+    }
+
+    // Like RGSS, out of range opacities are clamped instead of raising
+    fn set_opacity(&self, opacity: i32) -> Result<(), magnus::Error> {
+        self.configure(|config| config.opacity = opacity.clamp(0, 255) as u8)
+    }
+
+    fn get_back_opacity(&self) -> Result<u8, magnus::Error> {
+        Ok(self.config()?.back_opacity)
+    }
+
+    fn set_back_opacity(&self, opacity: i32) -> Result<(), magnus::Error> {
+        self.configure(|config| config.back_opacity = opacity.clamp(0, 255) as u8)
+    }
+
+    fn get_contents_opacity(&self) -> Result<u8, magnus::Error> {
+        Ok(self.config()?.contents_opacity)
+    }
+
+    fn set_contents_opacity(&self, opacity: i32) -> Result<(), magnus::Error> {
+        self.configure(|config| config.contents_opacity = opacity.clamp(0, 255) as u8)
+    }
+
+    fn get_visible(&self) -> Result<bool, magnus::Error> {
+        Ok(self.config()?.visible)
+    }
+
+    fn set_visible(&self, visible: bool) -> Result<(), magnus::Error> {
+        self.configure(|config| config.visible = visible)
+    }
+}
+
+pub fn bind(module: &mut impl magnus::Module) -> Result<(), magnus::Error> {
+    let class = module.define_class("Window", Default::default())?;
+    class.define_singleton_method("new", function!(Window::new, 1))?;
+    class.define_method("dispose", method!(Window::dispose, 0))?;
+    class.define_method("disposed?", method!(Window::is_disposed, 0))?;
+    class.define_method("windowskin=", method!(Window::set_windowskin, 1))?;
+    class.define_method("contents=", method!(Window::set_contents, 1))?;
+    class.define_method("move", method!(Window::move_to, 4))?;
+
+    class.define_method("x", method!(Window::get_x, 0))?;
+    class.define_method("x=", method!(Window::set_x, 1))?;
+    class.define_method("y", method!(Window::get_y, 0))?;
+    class.define_method("y=", method!(Window::set_y, 1))?;
+    class.define_method("width", method!(Window::get_width, 0))?;
+    class.define_method("width=", method!(Window::set_width, 1))?;
+    class.define_method("height", method!(Window::get_height, 0))?;
+    class.define_method("height=", method!(Window::set_height, 1))?;
+    class.define_method("z", method!(Window::get_z, 0))?;
+    class.define_method("z=", method!(Window::set_z, 1))?;
+    class.define_method("ox", method!(Window::get_ox, 0))?;
+    class.define_method("ox=", method!(Window::set_ox, 1))?;
+    class.define_method("oy", method!(Window::get_oy, 0))?;
+    class.define_method("oy=", method!(Window::set_oy, 1))?;
+    class.define_method("padding", method!(Window::get_padding, 0))?;
+    class.define_method("padding=", method!(Window::set_padding, 1))?;
+    class.define_method("stretch", method!(Window::get_stretch, 0))?;
+    class.define_method("stretch=", method!(Window::set_stretch, 1))?;
+    class.define_method("cursor_rect", method!(Window::get_cursor_rect, 0))?;
+    class.define_method("cursor_rect=", method!(Window::set_cursor_rect, 1))?;
+    class.define_method("opacity", method!(Window::get_opacity, 0))?;
+    class.define_method("opacity=", method!(Window::set_opacity, 1))?;
+    class.define_method("back_opacity", method!(Window::get_back_opacity, 0))?;
+    class.define_method("back_opacity=", method!(Window::set_back_opacity, 1))?;
+    class.define_method("contents_opacity", method!(Window::get_contents_opacity, 0))?;
+    class.define_method(
+        "contents_opacity=",
+        method!(Window::set_contents_opacity, 1),
+    )?;
+    class.define_method("visible", method!(Window::get_visible, 0))?;
+    class.define_method("visible=", method!(Window::set_visible, 1))?;
+
+    Ok(())
+}
@@ -0,0 +1,87 @@
+// Copyright (C) 2023 Lily Lyons
+//
+// This file is part of libfm.
+//
+// libfm is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// libfm is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with libfm.  If not, see <http://www.gnu.org/licenses/>.
+
+use crate::{Message, ReturnMessage, WindowConfig};
+use futures::prelude::*;
+use indexmap::IndexMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+
+/// Identifies a connected client. Window and sprite ids are only unique per client.
+pub type ClientId = usize;
+
+/// What the event loop is woken up with.
+#[derive(Debug)]
+pub enum Request {
+    Message(ClientId, Message),
+    /// The client hung up, so everything it created should go away.
+    Disconnected(ClientId),
+    /// A window the main thread built for [`Message::CreateWindow`], left for the event loop task
+    /// to set up in order with the client's other messages.
+    WindowBuilt(ClientId, usize, WindowConfig, winit::window::Window),
+}
+
+/// Where replies to each connected client go.
+#[derive(Clone, Default)]
+pub struct Clients {
+    next_id: Arc<AtomicUsize>,
+    senders: Arc<Mutex<IndexMap<ClientId, UnboundedSender<ReturnMessage>>>>,
+}
+
+impl Clients {
+    /// Registers a client that is sent replies through `writer`.
+    ///
+    /// Replies are written from a task of their own, so a slow client can't hold up the renderer.
+    pub fn connect<W>(&self, mut writer: W) -> ClientId
+    where
+        W: Sink<ReturnMessage> + Unpin + Send + 'static,
+        W::Error: std::fmt::Debug,
+    {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (send, mut recv) = unbounded_channel();
+        self.senders.lock().unwrap().insert(id, send);
+
+        tokio::spawn(async move {
+            while let Some(message) = recv.recv().await {
+                if let Err(e) = writer.send(message).await {
+                    eprintln!("failed to send message to client {id}: {e:?}");
+                    break;
+                }
+            }
+        });
+
+        id
+    }
+
+    pub fn disconnect(&self, client: ClientId) {
+        self.senders.lock().unwrap().remove(&client);
+    }
+
+    pub fn send(&self, client: ClientId, message: ReturnMessage) {
+        if let Some(sender) = self.senders.lock().unwrap().get(&client) {
+            // The client might be gone already, which we'll hear about soon enough
+            let _ = sender.send(message);
+        }
+    }
+
+    pub fn broadcast(&self, message: ReturnMessage) {
+        for sender in self.senders.lock().unwrap().values() {
+            let _ = sender.send(message.clone());
+        }
+    }
+}
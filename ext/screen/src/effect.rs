@@ -0,0 +1,483 @@
+// Copyright (C) 2023 Lily Lyons
+//
+// This file is part of libfm.
+//
+// libfm is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// libfm is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with libfm.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::HashMap;
+
+use crate::Effect;
+
+// Matches `Uniforms` in effect.wgsl: the target size, the time and four parameters
+const UNIFORM_FLOATS: usize = 8;
+// Blurs take this many taps in each direction
+const BLUR_TAPS: f32 = 4.0;
+
+/// A fullscreen pass. Most effects are one pass, but blurs are two.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Pass {
+    Blur,
+    Crt,
+    ChromaticAberration,
+    Grayscale,
+    Pixelate,
+    Shake,
+}
+
+impl Pass {
+    const ALL: [Pass; 6] = [
+        Pass::Blur,
+        Pass::Crt,
+        Pass::ChromaticAberration,
+        Pass::Grayscale,
+        Pass::Pixelate,
+        Pass::Shake,
+    ];
+
+    fn entry_point(self) -> &'static str {
+        match self {
+            Pass::Blur => "fs_blur",
+            Pass::Crt => "fs_crt",
+            Pass::ChromaticAberration => "fs_chromatic_aberration",
+            Pass::Grayscale => "fs_grayscale",
+            Pass::Pixelate => "fs_pixelate",
+            Pass::Shake => "fs_shake",
+        }
+    }
+}
+
+/// The passes an effect runs, with their parameters. Effects that wouldn't change anything
+/// don't run at all.
+fn passes(effect: Effect) -> Vec<(Pass, [f32; 4])> {
+    match effect {
+        Effect::Blur { radius } if radius > 0.0 => {
+            let step = radius / BLUR_TAPS;
+            vec![
+                (Pass::Blur, [step, 0.0, 0.0, 0.0]),
+                (Pass::Blur, [0.0, step, 0.0, 0.0]),
+            ]
+        }
+        Effect::Crt {
+            scanlines,
+            curvature,
+        } if scanlines != 0.0 || curvature != 0.0 => {
+            vec![(Pass::Crt, [scanlines, curvature, 0.0, 0.0])]
+        }
+        Effect::ChromaticAberration { offset } if offset != 0.0 => {
+            vec![(Pass::ChromaticAberration, [offset, 0.0, 0.0, 0.0])]
+        }
+        Effect::Grayscale { amount } if amount > 0.0 => {
+            vec![(Pass::Grayscale, [amount, 0.0, 0.0, 0.0])]
+        }
+        Effect::Pixelate { size } if size > 1.0 => vec![(Pass::Pixelate, [size, 0.0, 0.0, 0.0])],
+        Effect::Shake { amplitude, speed } if amplitude > 0.0 => {
+            vec![(Pass::Shake, [amplitude, speed, 0.0, 0.0])]
+        }
+        _ => Vec::new(),
+    }
+}
+
+/// Eases between two effects of the same kind, `t` of the way from `from` to `to`.
+fn lerp(from: Effect, to: Effect, t: f32) -> Option<Effect> {
+    let mix = |a: f32, b: f32| a + (b - a) * t;
+
+    Some(match (from, to) {
+        (Effect::Blur { radius: a }, Effect::Blur { radius: b }) => {
+            Effect::Blur { radius: mix(a, b) }
+        }
+        (
+            Effect::Crt {
+                scanlines: a,
+                curvature: c,
+            },
+            Effect::Crt {
+                scanlines: b,
+                curvature: d,
+            },
+        ) => Effect::Crt {
+            scanlines: mix(a, b),
+            curvature: mix(c, d),
+        },
+        (Effect::ChromaticAberration { offset: a }, Effect::ChromaticAberration { offset: b }) => {
+            Effect::ChromaticAberration { offset: mix(a, b) }
+        }
+        (Effect::Grayscale { amount: a }, Effect::Grayscale { amount: b }) => {
+            Effect::Grayscale { amount: mix(a, b) }
+        }
+        (Effect::Pixelate { size: a }, Effect::Pixelate { size: b }) => {
+            Effect::Pixelate { size: mix(a, b) }
+        }
+        (
+            Effect::Shake {
+                amplitude: a,
+                speed: c,
+            },
+            Effect::Shake {
+                amplitude: b,
+                speed: d,
+            },
+        ) => Effect::Shake {
+            amplitude: mix(a, b),
+            speed: mix(c, d),
+        },
+        _ => return None,
+    })
+}
+
+/// The pipelines effects are drawn with, one for each surface format and pass.
+pub struct Renderer {
+    shader: wgpu::ShaderModule,
+    texture_layout: wgpu::BindGroupLayout,
+    uniform_layout: wgpu::BindGroupLayout,
+    layout: wgpu::PipelineLayout,
+    sampler: wgpu::Sampler,
+    pipelines: HashMap<(wgpu::TextureFormat, Pass), wgpu::RenderPipeline>,
+}
+
+impl Renderer {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let shader = device.create_shader_module(wgpu::include_wgsl!("effect.wgsl"));
+        let texture_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("effect texture bind group layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+        let uniform_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("effect uniform bind group layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("effect pipeline layout"),
+            bind_group_layouts: &[&texture_layout, &uniform_layout],
+            push_constant_ranges: &[],
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        Self {
+            shader,
+            texture_layout,
+            uniform_layout,
+            layout,
+            sampler,
+            pipelines: HashMap::new(),
+        }
+    }
+
+    /// Builds the pipelines for drawing to `format`, unless they already exist.
+    pub fn prepare(&mut self, device: &wgpu::Device, format: wgpu::TextureFormat) {
+        for pass in Pass::ALL {
+            if self.pipelines.contains_key(&(format, pass)) {
+                continue;
+            }
+
+            let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("effect pipeline"),
+                layout: Some(&self.layout),
+                vertex: wgpu::VertexState {
+                    module: &self.shader,
+                    entry_point: "vs_main",
+                    buffers: &[],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &self.shader,
+                    entry_point: pass.entry_point(),
+                    // Every pass replaces the whole target
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format,
+                        blend: None,
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                }),
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+            });
+            self.pipelines.insert((format, pass), pipeline);
+        }
+    }
+}
+
+// An offscreen texture the scene or a pass is drawn into
+struct Target {
+    texture: wgpu::Texture,
+    view: wgpu::TextureView,
+    bind_group: wgpu::BindGroup,
+}
+
+impl Target {
+    fn new(
+        device: &wgpu::Device,
+        renderer: &Renderer,
+        format: wgpu::TextureFormat,
+        size: (u32, u32),
+    ) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("effect target"),
+            size: wgpu::Extent3d {
+                width: size.0,
+                height: size.1,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("effect texture bind group"),
+            layout: &renderer.texture_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&renderer.sampler),
+                },
+            ],
+        });
+
+        Self {
+            texture,
+            view,
+            bind_group,
+        }
+    }
+}
+
+// The two targets passes ping-pong between
+struct Targets {
+    format: wgpu::TextureFormat,
+    size: (u32, u32),
+    targets: [Target; 2],
+}
+
+/// A window's effects, and the offscreen targets they need.
+#[derive(Default)]
+pub struct Chain {
+    from: Vec<Effect>,
+    to: Vec<Effect>,
+    // When the transition from `from` to `to` started, and how many frames it takes
+    start: u64,
+    duration: u32,
+    targets: Option<Targets>,
+    uniforms: Vec<(wgpu::Buffer, wgpu::BindGroup)>,
+    // What was prepared for the current frame
+    passes: Vec<Pass>,
+}
+
+impl Chain {
+    /// Switches to `effects`, easing into them over `duration` frames from `frame` if they can be.
+    pub fn set(&mut self, effects: Vec<Effect>, duration: u32, frame: u64) {
+        self.from = self.current(frame);
+        self.to = effects;
+        self.start = frame;
+        self.duration = duration;
+    }
+
+    /// The effects as of `frame`, partway through any transition.
+    pub fn current(&self, frame: u64) -> Vec<Effect> {
+        let elapsed = frame.saturating_sub(self.start);
+        if elapsed >= self.duration as u64 || self.from.len() != self.to.len() {
+            return self.to.clone();
+        }
+
+        let t = elapsed as f32 / self.duration as f32;
+        self.from
+            .iter()
+            .zip(&self.to)
+            .map(|(&from, &to)| lerp(from, to, t))
+            .collect::<Option<_>>()
+            .unwrap_or_else(|| self.to.clone())
+    }
+
+    /// Whether the window needs redrawing every frame for the effects to move.
+    pub fn is_animated(&self, frame: u64) -> bool {
+        let transitioning = frame.saturating_sub(self.start) < self.duration as u64;
+        let shaking = self
+            .to
+            .iter()
+            .any(|effect| matches!(effect, Effect::Shake { amplitude, .. } if *amplitude > 0.0));
+        transitioning || shaking
+    }
+
+    pub fn memory(&self) -> u64 {
+        let Some(targets) = &self.targets else {
+            return 0;
+        };
+
+        targets
+            .targets
+            .iter()
+            .map(|target| {
+                let size = target.texture.size();
+                size.width as u64 * size.height as u64 * 4
+            })
+            .sum()
+    }
+
+    /// Works out the passes for `frame` and writes their uniforms, making sure there are
+    /// `size` targets of `format` to run them with.
+    pub fn prepare(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        renderer: &Renderer,
+        format: wgpu::TextureFormat,
+        size: (u32, u32),
+        frame: u64,
+    ) {
+        let passes: Vec<_> = self.current(frame).into_iter().flat_map(passes).collect();
+        self.passes = passes.iter().map(|(pass, _)| *pass).collect();
+        if passes.is_empty() {
+            // Don't hold on to the targets when nothing needs them
+            self.targets = None;
+            return;
+        }
+
+        let stale = self
+            .targets
+            .as_ref()
+            .is_none_or(|targets| targets.format != format || targets.size != size);
+        if stale {
+            self.targets = Some(Targets {
+                format,
+                size,
+                targets: [
+                    Target::new(device, renderer, format, size),
+                    Target::new(device, renderer, format, size),
+                ],
+            });
+        }
+
+        // Every pass is part of the same submit, so each one needs its own uniforms
+        while self.uniforms.len() < passes.len() {
+            let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("effect uniforms"),
+                size: (UNIFORM_FLOATS * std::mem::size_of::<f32>()) as u64,
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            });
+            let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("effect uniform bind group"),
+                layout: &renderer.uniform_layout,
+                entries: &[wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: buffer.as_entire_binding(),
+                }],
+            });
+            self.uniforms.push((buffer, bind_group));
+        }
+
+        // Wrapped so it stays precise enough for the shaders
+        let time = (frame % 1_000_000) as f32;
+        for ((_, params), (buffer, _)) in passes.iter().zip(&self.uniforms) {
+            let floats: [f32; UNIFORM_FLOATS] = [
+                size.0 as f32,
+                size.1 as f32,
+                time,
+                0.0,
+                params[0],
+                params[1],
+                params[2],
+                params[3],
+            ];
+            let bytes: Vec<u8> = floats.iter().flat_map(|f| f.to_ne_bytes()).collect();
+            queue.write_buffer(buffer, 0, &bytes);
+        }
+    }
+
+    /// Where the scene should be drawn, if there are any passes to run over it.
+    pub fn scene_view(&self) -> Option<&wgpu::TextureView> {
+        let targets = self.targets.as_ref()?;
+        (!self.passes.is_empty()).then_some(&targets.targets[0].view)
+    }
+
+    /// Runs the prepared passes over the scene, with the last one drawing to `output`.
+    /// Returns how many passes ran.
+    pub fn run(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        renderer: &Renderer,
+        output: &wgpu::TextureView,
+    ) -> u32 {
+        let Some(targets) = &self.targets else {
+            return 0;
+        };
+
+        for (index, (pass, (_, uniforms))) in self.passes.iter().zip(&self.uniforms).enumerate() {
+            let input = &targets.targets[index % 2];
+            let view = if index + 1 == self.passes.len() {
+                output
+            } else {
+                &targets.targets[(index + 1) % 2].view
+            };
+
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("effect pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: None,
+            });
+            render_pass.set_pipeline(&renderer.pipelines[&(targets.format, *pass)]);
+            render_pass.set_bind_group(0, &input.bind_group, &[]);
+            render_pass.set_bind_group(1, uniforms, &[]);
+            render_pass.draw(0..3, 0..1);
+        }
+
+        self.passes.len() as u32
+    }
+}
@@ -0,0 +1,111 @@
+// Fullscreen passes run over a window's scene. Each effect is its own fragment entry point
+
+struct Uniforms {
+    target_size: vec2<f32>,
+    // In frames
+    time: f32,
+    // What these mean depends on the effect
+    params: vec4<f32>,
+};
+
+@group(0) @binding(0)
+var t_scene: texture_2d<f32>;
+@group(0) @binding(1)
+var s_scene: sampler;
+@group(1) @binding(0)
+var<uniform> effect: Uniforms;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+};
+
+// A single triangle that covers the whole target
+@vertex
+fn vs_main(@builtin(vertex_index) in_vertex_index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((in_vertex_index << 1u) & 2u), f32(in_vertex_index & 2u));
+
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+
+fn scene(uv: vec2<f32>) -> vec4<f32> {
+    return textureSample(t_scene, s_scene, uv);
+}
+
+// One direction of a separable gaussian blur. params.xy is the distance between taps, in pixels
+@fragment
+fn fs_blur(in: VertexOutput) -> @location(0) vec4<f32> {
+    var weights = array<f32, 4>(0.1945946, 0.1216216, 0.054054, 0.016216);
+    let step = effect.params.xy / effect.target_size;
+
+    var color = scene(in.uv) * 0.227027;
+    for (var i = 0; i < 4; i++) {
+        let offset = step * f32(i + 1);
+        color += (scene(in.uv + offset) + scene(in.uv - offset)) * weights[i];
+    }
+    return color;
+}
+
+// params.x is how dark the scanlines are, params.y how curved the screen is
+@fragment
+fn fs_crt(in: VertexOutput) -> @location(0) vec4<f32> {
+    let centered = in.uv * 2.0 - 1.0;
+    let bent = centered * (1.0 + effect.params.y * dot(centered, centered));
+    let uv = bent * 0.5 + 0.5;
+    let color = scene(uv);
+
+    // A period of two pixels, so every other row is dark
+    let row = uv.y * effect.target_size.y;
+    let scanline = 1.0 - effect.params.x * (0.5 + 0.5 * cos(row * 3.14159265));
+    let inside = all(uv >= vec2<f32>(0.0)) && all(uv <= vec2<f32>(1.0));
+    return select(vec4<f32>(0.0, 0.0, 0.0, 1.0), vec4<f32>(color.rgb * scanline, color.a), inside);
+}
+
+// params.x is how far apart the red and blue channels are, in pixels
+@fragment
+fn fs_chromatic_aberration(in: VertexOutput) -> @location(0) vec4<f32> {
+    let offset = vec2<f32>(effect.params.x / effect.target_size.x, 0.0);
+    let center = scene(in.uv);
+    let red = scene(in.uv + offset).r;
+    let blue = scene(in.uv - offset).b;
+    return vec4<f32>(red, center.g, blue, center.a);
+}
+
+// params.x is how gray the scene is
+@fragment
+fn fs_grayscale(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = scene(in.uv);
+    let luma = dot(color.rgb, vec3<f32>(0.299, 0.587, 0.114));
+    return vec4<f32>(mix(color.rgb, vec3<f32>(luma), effect.params.x), color.a);
+}
+
+// params.x is the size of a block, in pixels
+@fragment
+fn fs_pixelate(in: VertexOutput) -> @location(0) vec4<f32> {
+    let size = max(effect.params.x, 1.0);
+    let block = floor(in.uv * effect.target_size / size) * size + size * 0.5;
+    return scene(block / effect.target_size);
+}
+
+fn hash(n: f32) -> f32 {
+    return fract(sin(n) * 43758.5453);
+}
+
+// Smooth noise between -1 and 1
+fn noise(t: f32) -> f32 {
+    let i = floor(t);
+    let f = fract(t);
+    let eased = f * f * (3.0 - 2.0 * f);
+    return mix(hash(i), hash(i + 1.0), eased) * 2.0 - 1.0;
+}
+
+// params.x is how far the scene moves in pixels, params.y how often it changes direction
+@fragment
+fn fs_shake(in: VertexOutput) -> @location(0) vec4<f32> {
+    let t = effect.time * effect.params.y;
+    let offset = vec2<f32>(noise(t), noise(t + 31.7)) * effect.params.x;
+    return scene(in.uv + offset / effect.target_size);
+}
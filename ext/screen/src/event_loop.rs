@@ -15,39 +15,72 @@
 // You should have received a copy of the GNU General Public License
 // along with libfm.  If not, see <http://www.gnu.org/licenses/>.
 
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use crate::{Message, Sprite, State};
-use async_bincode::futures::AsyncBincodeWriter;
-use futures::prelude::*;
-use screen::ReturnMessage;
+use crate::clients::{ClientId, Clients, Request};
+use crate::panel::Panel;
+use crate::renderer::{Plane, Sprite, State, Window};
+use crate::shape::Shape;
+use crate::tilemap::{self, Tilemap};
+use crate::{effect, shader, wgpu_state, FrameStats, Message, ProtocolError, ReturnMessage};
+use indexmap::IndexMap;
 use tokio::sync::{mpsc::UnboundedReceiver, Mutex};
 use winit::event::{Event, WindowEvent};
 
+const STATS_INTERVAL: Duration = Duration::from_millis(500);
+
 pub async fn run(
     state: Arc<Mutex<State>>,
-    mut event_recv: UnboundedReceiver<Event<'static, Message>>,
-    writer: impl AsyncWrite + Unpin,
-) -> ! {
-    let mut writer = AsyncBincodeWriter::from(writer).for_async();
+    mut event_recv: UnboundedReceiver<Event<'static, Request>>,
+    clients: Clients,
+) {
+    let mut stats = FrameStats::default();
+    // Added to by the GPU's callbacks as it finishes frames, in nanoseconds
+    let gpu_nanos = Arc::new(AtomicU64::new(0));
+    let mut last_report = Instant::now();
     loop {
+        // The event loop has exited once the sender is gone
+        let Some(event) = event_recv.recv().await else {
+            return;
+        };
         // Process multiple events at a time in case they have been sent in rapid fire
-        let mut events = vec![event_recv.recv().await.expect("sender is closed")];
+        let mut events = vec![event];
         while let Ok(event) = event_recv.try_recv() {
             events.push(event);
         }
 
         let mut state = state.lock().await;
+        let start = Instant::now();
         let State {
             windows,
             wgpu_state,
+            frames,
+            frames_owner,
         } = &mut *state;
+        // Batches are flattened in place, so they are applied within the same lock as
+        // everything else and can't be interrupted by a redraw
+        let events = events.into_iter().flat_map(|event| match event {
+            Event::UserEvent(Request::Message(client, Message::Batch(messages))) => messages
+                .into_iter()
+                .map(|message| Event::UserEvent(Request::Message(client, message)))
+                .collect(),
+            event => vec![event],
+        });
         for event in events {
+            if let Event::UserEvent(Request::Message(..) | Request::WindowBuilt(..)) = event {
+                stats.messages += 1;
+            }
+
             match event {
-                Event::UserEvent(Message::ResizeWindow(width, height, window_id)) => {
-                    let window = windows
-                        .get_mut(&window_id)
-                        .expect("window event recieved for nonexistent window");
+                Event::UserEvent(Request::Message(
+                    client,
+                    Message::ResizeWindow(width, height, window_id),
+                )) => {
+                    let Some(window) = window_mut(windows, &clients, client, window_id) else {
+                        continue;
+                    };
                     window
                         .window
                         .set_inner_size(winit::dpi::PhysicalSize::new(width, height));
@@ -58,21 +91,97 @@ pub async fn run(
 
                     window.sprites_dirty = true;
                 }
-                Event::UserEvent(Message::RepositionWindow(x, y, window_id)) => {
-                    let window = windows
-                        .get_mut(&window_id)
-                        .expect("window event recieved for nonexistent window");
+                Event::UserEvent(Request::Message(
+                    client,
+                    Message::RepositionWindow(x, y, window_id),
+                )) => {
+                    let Some(window) = window_mut(windows, &clients, client, window_id) else {
+                        continue;
+                    };
                     window
                         .window
                         .set_outer_position(winit::dpi::PhysicalPosition::new(x, y));
                 }
-                Event::UserEvent(Message::DeleteWindow(id)) => {
-                    drop(windows.remove(&id));
+                Event::UserEvent(Request::Message(client, Message::ConfigureFrames(config))) => {
+                    // Every client sends its settings when it connects, which is only a problem
+                    // if they'd change what the owner asked for
+                    if frames_owner.is_some_and(|owner| owner != client) {
+                        if config != frames.config {
+                            let error = ProtocolError::FramesOwned;
+                            clients.send(client, ReturnMessage::Error(error));
+                        }
+                        continue;
+                    }
+
+                    *frames_owner = Some(client);
+                    frames.configure(config);
+                    for window in windows.values_mut() {
+                        wgpu_state.set_present_mode(&mut window.surface, config.present_mode);
+                    }
+                }
+                Event::UserEvent(Request::WindowBuilt(client, id, conf, window)) => {
+                    if windows.contains_key(&(client, id)) {
+                        let error = ProtocolError::DuplicateWindow(id);
+                        clients.send(client, ReturnMessage::Error(error));
+                        continue;
+                    }
+
+                    let surface = wgpu_state.create_surface(&window, frames.config.present_mode);
+                    window.set_visible(conf.visible);
+
+                    // Not every platform sends these on creation, and the client needs somewhere
+                    // to start from. Some can't tell us where the window is at all, like Wayland
+                    if let Ok(pos) = window.outer_position() {
+                        clients.send(client, ReturnMessage::Moved(id, pos.x, pos.y));
+                    }
+                    let size = window.inner_size();
+                    clients.send(client, ReturnMessage::Resized(id, size.width, size.height));
+
+                    windows.insert(
+                        (client, id),
+                        Window {
+                            window,
+                            sprites: IndexMap::new(),
+                            planes: IndexMap::new(),
+                            tilemaps: IndexMap::new(),
+                            panels: IndexMap::new(),
+                            shapes: IndexMap::new(),
+                            effects: effect::Chain::default(),
+                            shaders: IndexMap::new(),
+                            shader: None,
+                            shader_pass: None,
+                            sprites_dirty: false,
+                            surface,
+                        },
+                    );
+                }
+                Event::UserEvent(Request::Message(client, Message::DeleteWindow(id))) => {
+                    let Some(window) = windows.remove(&(client, id)) else {
+                        let error = ProtocolError::UnknownWindow(id);
+                        clients.send(client, ReturnMessage::Error(error));
+                        continue;
+                    };
+                    drop(window);
                 }
-                Event::UserEvent(Message::CreateSprite(sprite_id, window_id)) => {
-                    let window = windows
-                        .get_mut(&window_id)
-                        .expect("window event recieved for nonexistent window");
+                Event::UserEvent(Request::Disconnected(client)) => {
+                    windows.retain(|(owner, _), _| *owner != client);
+                    if *frames_owner == Some(client) {
+                        *frames_owner = None;
+                    }
+                    clients.disconnect(client);
+                }
+                Event::UserEvent(Request::Message(
+                    client,
+                    Message::CreateSprite(sprite_id, window_id),
+                )) => {
+                    let Some(window) = window_mut(windows, &clients, client, window_id) else {
+                        continue;
+                    };
+                    if window.sprites.contains_key(&sprite_id) {
+                        let error = ProtocolError::DuplicateSprite(sprite_id, window_id);
+                        clients.send(client, ReturnMessage::Error(error));
+                        continue;
+                    }
 
                     window.sprites.insert(
                         sprite_id,
@@ -81,115 +190,890 @@ pub async fn run(
                             y: 0,
                             z: 0,
                             image: None,
+                            uniforms: wgpu_state.create_shader_uniforms(),
+                            shader: None,
                         },
                     );
                 }
-                Event::UserEvent(Message::RemoveSprite(sprite_id, window_id)) => {
-                    let window = windows
-                        .get_mut(&window_id)
-                        .expect("window event recieved for nonexistent window");
+                Event::UserEvent(Request::Message(
+                    client,
+                    Message::RemoveSprite(sprite_id, window_id),
+                )) => {
+                    let Some(window) = window_mut(windows, &clients, client, window_id) else {
+                        continue;
+                    };
                     window.sprites_dirty = true;
 
-                    drop(window.sprites.remove(&sprite_id));
+                    if window.sprites.remove(&sprite_id).is_none() {
+                        let error = ProtocolError::UnknownSprite(sprite_id, window_id);
+                        clients.send(client, ReturnMessage::Error(error));
+                    }
                 }
-                Event::UserEvent(Message::SetSprite(sprite_id, window_id, path)) => {
-                    let window = windows
-                        .get_mut(&window_id)
-                        .expect("window event recieved for nonexistent window");
+                Event::UserEvent(Request::Message(
+                    client,
+                    Message::SetSprite(sprite_id, window_id, path),
+                )) => {
+                    let Some(window) = window_mut(windows, &clients, client, window_id) else {
+                        continue;
+                    };
                     window.sprites_dirty = true;
 
-                    let sprite = window
-                        .sprites
-                        .get_mut(&sprite_id)
-                        .expect("sprite event recieved for nonexistent sprite");
-                    sprite.image = Some(wgpu_state.create_texture(path));
+                    let Some(sprite) =
+                        object_mut::<Sprite>(window, &clients, client, sprite_id, window_id)
+                    else {
+                        continue;
+                    };
+                    match wgpu_state.create_sprite_image(&path) {
+                        Ok(image) => sprite.image = Some(image),
+                        Err(e) => {
+                            clients.send(client, ReturnMessage::LoadFailed(path, e.to_string()))
+                        }
+                    }
                 }
-                Event::UserEvent(Message::RepositionSprite(sprite_id, window_id, x, y, z)) => {
-                    let window = windows
-                        .get_mut(&window_id)
-                        .expect("window event recieved for nonexistent window");
+                Event::UserEvent(Request::Message(
+                    client,
+                    Message::RepositionSprite(sprite_id, window_id, x, y, z),
+                )) => {
+                    let Some(window) = window_mut(windows, &clients, client, window_id) else {
+                        continue;
+                    };
                     window.sprites_dirty = true;
 
-                    let sprite = window
-                        .sprites
-                        .get_mut(&sprite_id)
-                        .expect("sprite event recieved for nonexistent sprite");
+                    let Some(sprite) =
+                        object_mut::<Sprite>(window, &clients, client, sprite_id, window_id)
+                    else {
+                        continue;
+                    };
                     sprite.x = x;
                     sprite.y = y;
                     sprite.z = z;
                 }
+                Event::UserEvent(Request::Message(
+                    client,
+                    Message::CreatePlane(plane_id, window_id),
+                )) => {
+                    let Some(window) = window_mut(windows, &clients, client, window_id) else {
+                        continue;
+                    };
+                    if window.planes.contains_key(&plane_id) {
+                        let error = ProtocolError::DuplicatePlane(plane_id, window_id);
+                        clients.send(client, ReturnMessage::Error(error));
+                        continue;
+                    }
+
+                    window.planes.insert(
+                        plane_id,
+                        Plane {
+                            config: Default::default(),
+                            image: None,
+                        },
+                    );
+                }
+                Event::UserEvent(Request::Message(
+                    client,
+                    Message::RemovePlane(plane_id, window_id),
+                )) => {
+                    let Some(window) = window_mut(windows, &clients, client, window_id) else {
+                        continue;
+                    };
+                    window.sprites_dirty = true;
+
+                    if window.planes.remove(&plane_id).is_none() {
+                        let error = ProtocolError::UnknownPlane(plane_id, window_id);
+                        clients.send(client, ReturnMessage::Error(error));
+                    }
+                }
+                Event::UserEvent(Request::Message(
+                    client,
+                    Message::SetPlane(plane_id, window_id, path),
+                )) => {
+                    let Some(window) = window_mut(windows, &clients, client, window_id) else {
+                        continue;
+                    };
+                    window.sprites_dirty = true;
+
+                    let Some(plane) =
+                        object_mut::<Plane>(window, &clients, client, plane_id, window_id)
+                    else {
+                        continue;
+                    };
+                    match wgpu_state.create_plane_image(&path) {
+                        Ok(image) => plane.image = Some(image),
+                        Err(e) => {
+                            clients.send(client, ReturnMessage::LoadFailed(path, e.to_string()))
+                        }
+                    }
+                }
+                Event::UserEvent(Request::Message(
+                    client,
+                    Message::ConfigurePlane(plane_id, window_id, config),
+                )) => {
+                    let Some(window) = window_mut(windows, &clients, client, window_id) else {
+                        continue;
+                    };
+                    window.sprites_dirty = true;
+
+                    let Some(plane) =
+                        object_mut::<Plane>(window, &clients, client, plane_id, window_id)
+                    else {
+                        continue;
+                    };
+                    plane.config = config;
+                }
+                Event::UserEvent(Request::Message(
+                    client,
+                    Message::CreatePanel(panel_id, window_id),
+                )) => {
+                    let Some(window) = window_mut(windows, &clients, client, window_id) else {
+                        continue;
+                    };
+                    if window.panels.contains_key(&panel_id) {
+                        let error = ProtocolError::DuplicatePanel(panel_id, window_id);
+                        clients.send(client, ReturnMessage::Error(error));
+                        continue;
+                    }
+
+                    window.panels.insert(panel_id, wgpu_state.create_panel());
+                }
+                Event::UserEvent(Request::Message(
+                    client,
+                    Message::RemovePanel(panel_id, window_id),
+                )) => {
+                    let Some(window) = window_mut(windows, &clients, client, window_id) else {
+                        continue;
+                    };
+                    window.sprites_dirty = true;
+
+                    if window.panels.remove(&panel_id).is_none() {
+                        let error = ProtocolError::UnknownPanel(panel_id, window_id);
+                        clients.send(client, ReturnMessage::Error(error));
+                    }
+                }
+                Event::UserEvent(Request::Message(
+                    client,
+                    Message::SetPanelSkin(panel_id, window_id, path),
+                )) => {
+                    let Some(window) = window_mut(windows, &clients, client, window_id) else {
+                        continue;
+                    };
+                    window.sprites_dirty = true;
+
+                    let Some(panel) =
+                        object_mut::<Panel>(window, &clients, client, panel_id, window_id)
+                    else {
+                        continue;
+                    };
+                    if let Err(e) = wgpu_state.set_panel_skin(panel, &path) {
+                        clients.send(client, ReturnMessage::LoadFailed(path, e.to_string()))
+                    }
+                }
+                Event::UserEvent(Request::Message(
+                    client,
+                    Message::SetPanelContents(panel_id, window_id, path),
+                )) => {
+                    let Some(window) = window_mut(windows, &clients, client, window_id) else {
+                        continue;
+                    };
+                    window.sprites_dirty = true;
+
+                    let Some(panel) =
+                        object_mut::<Panel>(window, &clients, client, panel_id, window_id)
+                    else {
+                        continue;
+                    };
+                    if let Err(e) = wgpu_state.set_panel_contents(panel, path.as_deref()) {
+                        let path = path.unwrap_or_default();
+                        clients.send(client, ReturnMessage::LoadFailed(path, e.to_string()))
+                    }
+                }
+                Event::UserEvent(Request::Message(
+                    client,
+                    Message::ConfigurePanel(panel_id, window_id, config),
+                )) => {
+                    let Some(window) = window_mut(windows, &clients, client, window_id) else {
+                        continue;
+                    };
+                    window.sprites_dirty = true;
+
+                    let Some(panel) =
+                        object_mut::<Panel>(window, &clients, client, panel_id, window_id)
+                    else {
+                        continue;
+                    };
+                    wgpu_state.configure_panel(panel, config);
+                }
+                Event::UserEvent(Request::Message(
+                    client,
+                    Message::CreateShape(shape_id, window_id),
+                )) => {
+                    let Some(window) = window_mut(windows, &clients, client, window_id) else {
+                        continue;
+                    };
+                    if window.shapes.contains_key(&shape_id) {
+                        let error = ProtocolError::DuplicateShape(shape_id, window_id);
+                        clients.send(client, ReturnMessage::Error(error));
+                        continue;
+                    }
+
+                    window.shapes.insert(shape_id, wgpu_state.create_shape());
+                }
+                Event::UserEvent(Request::Message(
+                    client,
+                    Message::RemoveShape(shape_id, window_id),
+                )) => {
+                    let Some(window) = window_mut(windows, &clients, client, window_id) else {
+                        continue;
+                    };
+                    window.sprites_dirty = true;
+
+                    if window.shapes.remove(&shape_id).is_none() {
+                        let error = ProtocolError::UnknownShape(shape_id, window_id);
+                        clients.send(client, ReturnMessage::Error(error));
+                    }
+                }
+                Event::UserEvent(Request::Message(
+                    client,
+                    Message::SetShape(shape_id, window_id, geometry),
+                )) => {
+                    let Some(window) = window_mut(windows, &clients, client, window_id) else {
+                        continue;
+                    };
+                    window.sprites_dirty = true;
+
+                    let Some(shape) =
+                        object_mut::<Shape>(window, &clients, client, shape_id, window_id)
+                    else {
+                        continue;
+                    };
+                    wgpu_state.set_shape_geometry(shape, geometry);
+                }
+                Event::UserEvent(Request::Message(
+                    client,
+                    Message::ConfigureShape(shape_id, window_id, config),
+                )) => {
+                    let Some(window) = window_mut(windows, &clients, client, window_id) else {
+                        continue;
+                    };
+                    window.sprites_dirty = true;
+
+                    let Some(shape) =
+                        object_mut::<Shape>(window, &clients, client, shape_id, window_id)
+                    else {
+                        continue;
+                    };
+                    wgpu_state.configure_shape(shape, config);
+                }
+                Event::UserEvent(Request::Message(
+                    client,
+                    Message::CreateTilemap(tilemap_id, window_id),
+                )) => {
+                    let Some(window) = window_mut(windows, &clients, client, window_id) else {
+                        continue;
+                    };
+                    if window.tilemaps.contains_key(&tilemap_id) {
+                        let error = ProtocolError::DuplicateTilemap(tilemap_id, window_id);
+                        clients.send(client, ReturnMessage::Error(error));
+                        continue;
+                    }
+
+                    window
+                        .tilemaps
+                        .insert(tilemap_id, wgpu_state.create_tilemap());
+                }
+                Event::UserEvent(Request::Message(
+                    client,
+                    Message::RemoveTilemap(tilemap_id, window_id),
+                )) => {
+                    let Some(window) = window_mut(windows, &clients, client, window_id) else {
+                        continue;
+                    };
+                    window.sprites_dirty = true;
+
+                    if window.tilemaps.remove(&tilemap_id).is_none() {
+                        let error = ProtocolError::UnknownTilemap(tilemap_id, window_id);
+                        clients.send(client, ReturnMessage::Error(error));
+                    }
+                }
+                Event::UserEvent(Request::Message(
+                    client,
+                    Message::SetTileset(tilemap_id, window_id, path),
+                )) => {
+                    let Some(window) = window_mut(windows, &clients, client, window_id) else {
+                        continue;
+                    };
+                    window.sprites_dirty = true;
+
+                    let Some(tilemap) =
+                        object_mut::<Tilemap>(window, &clients, client, tilemap_id, window_id)
+                    else {
+                        continue;
+                    };
+                    if let Err(e) = wgpu_state.set_tileset(tilemap, &path) {
+                        clients.send(client, ReturnMessage::LoadFailed(path, e.to_string()))
+                    }
+                }
+                Event::UserEvent(Request::Message(
+                    client,
+                    Message::ConfigureTilemap(tilemap_id, window_id, config),
+                )) => {
+                    let Some(window) = window_mut(windows, &clients, client, window_id) else {
+                        continue;
+                    };
+                    window.sprites_dirty = true;
+
+                    let Some(tilemap) =
+                        object_mut::<Tilemap>(window, &clients, client, tilemap_id, window_id)
+                    else {
+                        continue;
+                    };
+                    tilemap.config = config;
+                }
+                Event::UserEvent(Request::Message(
+                    client,
+                    Message::SetTileData(tilemap_id, window_id, data),
+                )) => {
+                    let Some(window) = window_mut(windows, &clients, client, window_id) else {
+                        continue;
+                    };
+                    window.sprites_dirty = true;
+
+                    let Some(tilemap) =
+                        object_mut::<Tilemap>(window, &clients, client, tilemap_id, window_id)
+                    else {
+                        continue;
+                    };
+                    if !wgpu_state.set_tile_data(tilemap, data) {
+                        let error = ProtocolError::TilesOutOfBounds(tilemap_id, window_id);
+                        clients.send(client, ReturnMessage::Error(error));
+                    }
+                }
+                Event::UserEvent(Request::Message(
+                    client,
+                    Message::SetTiles(tilemap_id, window_id, updates),
+                )) => {
+                    let Some(window) = window_mut(windows, &clients, client, window_id) else {
+                        continue;
+                    };
+                    window.sprites_dirty = true;
+
+                    let Some(tilemap) =
+                        object_mut::<Tilemap>(window, &clients, client, tilemap_id, window_id)
+                    else {
+                        continue;
+                    };
+                    if !wgpu_state.set_tiles(tilemap, updates) {
+                        let error = ProtocolError::TilesOutOfBounds(tilemap_id, window_id);
+                        clients.send(client, ReturnMessage::Error(error));
+                    }
+                }
+                Event::UserEvent(Request::Message(
+                    client,
+                    Message::SetTileInfo(tilemap_id, window_id, updates),
+                )) => {
+                    let Some(window) = window_mut(windows, &clients, client, window_id) else {
+                        continue;
+                    };
+                    window.sprites_dirty = true;
+
+                    let Some(tilemap) =
+                        object_mut::<Tilemap>(window, &clients, client, tilemap_id, window_id)
+                    else {
+                        continue;
+                    };
+                    wgpu_state.set_tile_info(tilemap, updates);
+                }
 
                 Event::WindowEvent { window_id, event } => {
-                    let (id, window) = windows
-                        .iter_mut()
+                    // The window might have been deleted since the event came in
+                    let Some(&(client, id)) = windows
+                        .iter()
                         .find(|(_, window)| window.window.id() == window_id)
-                        .expect("window event received for nonexistent window");
-                    let message = match event {
-                        WindowEvent::CloseRequested => Some(ReturnMessage::CloseRequested(*id)),
-                        _ => None,
+                        .map(|(key, _)| key)
+                    else {
+                        continue;
                     };
-                    if let Some(message) = message {
-                        writer
-                            .send(message)
-                            .await
-                            .expect("failed to send response message");
+                    match event {
+                        WindowEvent::CloseRequested => {
+                            clients.send(client, ReturnMessage::CloseRequested(id))
+                        }
+                        WindowEvent::Moved(pos) => {
+                            clients.send(client, ReturnMessage::Moved(id, pos.x, pos.y))
+                        }
+                        WindowEvent::Resized(size) => {
+                            // Minimized windows report a size of zero, which a surface can't have
+                            if size.width > 0 && size.height > 0 {
+                                let window = &mut windows[&(client, id)];
+                                wgpu_state.resize_surface(&mut window.surface, size);
+                                window.sprites_dirty = true;
+                            }
+                            let message = ReturnMessage::Resized(id, size.width, size.height);
+                            clients.send(client, message);
+                        }
+                        WindowEvent::Focused(focused) => {
+                            clients.send(client, ReturnMessage::Focused(id, focused))
+                        }
+                        _ => {}
                     }
                 }
 
+                Event::UserEvent(Request::Message(
+                    client,
+                    Message::CreateShader(shader_id, window_id, source),
+                )) => {
+                    let Some(window) = window_mut(windows, &clients, client, window_id) else {
+                        continue;
+                    };
+                    if window.shaders.contains_key(&shader_id) {
+                        let error = ProtocolError::DuplicateShader(shader_id, window_id);
+                        clients.send(client, ReturnMessage::Error(error));
+                        continue;
+                    }
+
+                    match wgpu_state.create_shader(&window.surface, &source) {
+                        Ok(program) => {
+                            window.shaders.insert(shader_id, program);
+                        }
+                        Err(e) => {
+                            eprintln!("shader {shader_id} on window {window_id}: {e}");
+                            let error = ProtocolError::InvalidShader(shader_id, window_id);
+                            clients.send(client, ReturnMessage::Error(error));
+                        }
+                    }
+                }
+                Event::UserEvent(Request::Message(
+                    client,
+                    Message::RemoveShader(shader_id, window_id),
+                )) => {
+                    let Some(window) = window_mut(windows, &clients, client, window_id) else {
+                        continue;
+                    };
+                    window.sprites_dirty = true;
+
+                    if window.shaders.remove(&shader_id).is_none() {
+                        let error = ProtocolError::UnknownShader(shader_id, window_id);
+                        clients.send(client, ReturnMessage::Error(error));
+                        continue;
+                    }
+                    // Whatever was drawn with it goes back to being drawn without a shader
+                    for sprite in window.sprites.values_mut() {
+                        if sprite.shader == Some(shader_id) {
+                            sprite.shader = None;
+                        }
+                    }
+                    if window.shader == Some(shader_id) {
+                        window.shader = None;
+                    }
+                }
+                Event::UserEvent(Request::Message(
+                    client,
+                    Message::SetShaderUniforms(shader_id, window_id, uniforms),
+                )) => {
+                    let Some(window) = window_mut(windows, &clients, client, window_id) else {
+                        continue;
+                    };
+                    window.sprites_dirty = true;
+
+                    let Some(program) = object_mut::<shader::Program>(
+                        window, &clients, client, shader_id, window_id,
+                    ) else {
+                        continue;
+                    };
+                    program.uniforms = uniforms;
+                }
+                Event::UserEvent(Request::Message(
+                    client,
+                    Message::SetSpriteShader(sprite_id, window_id, shader_id),
+                )) => {
+                    let Some(window) = window_mut(windows, &clients, client, window_id) else {
+                        continue;
+                    };
+                    window.sprites_dirty = true;
+
+                    if let Some(shader_id) = shader_id.filter(|id| !window.shaders.contains_key(id))
+                    {
+                        let error = ProtocolError::UnknownShader(shader_id, window_id);
+                        clients.send(client, ReturnMessage::Error(error));
+                        continue;
+                    }
+                    let Some(sprite) =
+                        object_mut::<Sprite>(window, &clients, client, sprite_id, window_id)
+                    else {
+                        continue;
+                    };
+                    sprite.shader = shader_id;
+                }
+                Event::UserEvent(Request::Message(
+                    client,
+                    Message::SetWindowShader(shader_id, window_id),
+                )) => {
+                    let Some(window) = window_mut(windows, &clients, client, window_id) else {
+                        continue;
+                    };
+                    window.sprites_dirty = true;
+
+                    if let Some(shader_id) = shader_id.filter(|id| !window.shaders.contains_key(id))
+                    {
+                        let error = ProtocolError::UnknownShader(shader_id, window_id);
+                        clients.send(client, ReturnMessage::Error(error));
+                        continue;
+                    }
+                    window.shader = shader_id;
+                }
+
+                Event::UserEvent(Request::Message(
+                    client,
+                    Message::SetEffects(effects, duration, window_id),
+                )) => {
+                    let Some(window) = window_mut(windows, &clients, client, window_id) else {
+                        continue;
+                    };
+                    window.sprites_dirty = true;
+                    window.effects.set(effects, duration, frames.frame);
+                }
+
+                Event::UserEvent(Request::Message(client, Message::Snapshot(window_id))) => {
+                    let Some(window) = window_mut(windows, &clients, client, window_id) else {
+                        continue;
+                    };
+                    wgpu_state.prepare_effects(&window.surface, &mut window.effects, frames.frame);
+                    let shaded = window.shader.is_some();
+                    wgpu_state.prepare_shader_pass(
+                        &window.surface,
+                        &mut window.shader_pass,
+                        shaded,
+                    );
+                    let window = &*window;
+
+                    // Snapshots aren't frames, so they're kept out of the stats
+                    let image = wgpu_state.snapshot(&window.surface, |view| {
+                        render(
+                            wgpu_state,
+                            window,
+                            view,
+                            frames.frame,
+                            &mut FrameStats::default(),
+                        )
+                    });
+                    let Some(image) = image else {
+                        let error = ProtocolError::SnapshotUnsupported(window_id);
+                        clients.send(client, ReturnMessage::Error(error));
+                        continue;
+                    };
+                    let mut png = Vec::new();
+                    image::DynamicImage::ImageRgba8(image)
+                        .write_to(
+                            &mut std::io::Cursor::new(&mut png),
+                            image::ImageOutputFormat::Png,
+                        )
+                        .expect("failed to encode snapshot");
+
+                    clients.send(client, ReturnMessage::Snapshot(window_id, png));
+                }
+
                 Event::RedrawRequested(window_id) => {
-                    let (_, window) = windows
+                    // The window might have been deleted since the redraw was requested
+                    let Some((_, window)) = windows
                         .iter_mut()
                         .find(|(_, window)| window.window.id() == window_id)
-                        .expect("window event received for nonexistent window");
+                    else {
+                        continue;
+                    };
                     let output = window.surface.get_current_texture();
+                    wgpu_state.prepare_effects(&window.surface, &mut window.effects, frames.frame);
+                    let shaded = window.shader.is_some();
+                    wgpu_state.prepare_shader_pass(
+                        &window.surface,
+                        &mut window.shader_pass,
+                        shaded,
+                    );
 
                     let view = output
                         .texture
                         .create_view(&wgpu::TextureViewDescriptor::default());
-                    let mut encoder = wgpu_state.create_command_encoder();
-
-                    let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                        color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                            view: &view,
-                            resolve_target: None,
-                            ops: wgpu::Operations {
-                                load: wgpu::LoadOp::Clear(wgpu::Color {
-                                    r: 0.05,
-                                    g: 0.0,
-                                    b: 0.1,
-                                    a: 0.7,
-                                }),
-                                store: true,
-                            },
-                        })],
-                        ..Default::default()
-                    });
+                    let encoder = render(wgpu_state, window, &view, frames.frame, &mut stats);
 
-                    for sprite in window.sprites.values() {
-                        let Some(ref texture) = sprite.image else { continue; };
-                        texture.bind(&mut render_pass);
-                        wgpu_state.sprite_shader.bind(&mut render_pass);
+                    let submitted = Instant::now();
+                    wgpu_state.submit_encoder(encoder);
+                    let gpu_nanos = gpu_nanos.clone();
+                    wgpu_state.on_submitted_work_done(move || {
+                        let elapsed = submitted.elapsed().as_nanos() as u64;
+                        gpu_nanos.fetch_add(elapsed, Ordering::Relaxed);
+                    });
+                    output.present();
+                }
 
-                        render_pass.draw(0..3, 0..1);
+                Event::MainEventsCleared => {
+                    for window in windows.values_mut() {
+                        // Animated tiles need a redraw whenever they move on to their next frame.
+                        // Every tilemap's clock has to advance, so don't stop at the first one.
+                        let mut animating = false;
+                        for tilemap in window.tilemaps.values_mut() {
+                            animating |= tilemap.advance_animation(frames.frame);
+                        }
+                        let animating = animating || window.effects.is_animated(frames.frame);
+                        if window.sprites_dirty || animating {
+                            window
+                                .sprites
+                                .sort_unstable_by(|_, s, _, s2| s.z.cmp(&s2.z));
+                            window.window.request_redraw();
+                            window.sprites_dirty = false;
+                        }
                     }
 
-                    drop(render_pass);
+                    clients.broadcast(ReturnMessage::Frame(frames.frame, frames.delta));
 
-                    wgpu_state.submit_encoder(encoder);
-                    output.present();
+                    stats.frames += 1;
+                    if last_report.elapsed() >= STATS_INTERVAL {
+                        stats.elapsed = last_report.elapsed().as_secs_f64();
+                        // Frames the GPU is still working on count towards the next report
+                        wgpu_state.poll();
+                        let gpu_nanos = gpu_nanos.swap(0, Ordering::Relaxed);
+                        stats.gpu_time = Duration::from_nanos(gpu_nanos).as_secs_f64();
+                        let sprites = windows
+                            .values()
+                            .flat_map(|window| window.sprites.values())
+                            .filter_map(|sprite| sprite.image.as_ref())
+                            .map(|image| image.memory());
+                        let planes = windows
+                            .values()
+                            .flat_map(|window| window.planes.values())
+                            .filter_map(|plane| plane.image.as_ref())
+                            .map(|image| image.memory());
+                        let tilemaps = windows
+                            .values()
+                            .flat_map(|window| window.tilemaps.values())
+                            .map(|tilemap| tilemap.memory());
+                        let panels = windows
+                            .values()
+                            .flat_map(|window| window.panels.values())
+                            .map(|panel| panel.memory());
+                        let effects = windows.values().map(|window| window.effects.memory());
+                        let shader_passes = windows
+                            .values()
+                            .filter_map(|window| window.shader_pass.as_ref())
+                            .map(|pass| pass.memory());
+                        stats.texture_memory = sprites
+                            .chain(planes)
+                            .chain(tilemaps)
+                            .chain(panels)
+                            .chain(effects)
+                            .chain(shader_passes)
+                            .sum();
+
+                        clients.broadcast(ReturnMessage::Stats(std::mem::take(&mut stats)));
+                        last_report = Instant::now();
+                    }
                 }
                 _ => {}
             }
         }
 
-        for window in state.windows.values_mut() {
-            if window.sprites_dirty {
-                window
-                    .sprites
-                    .sort_unstable_by(|_, s, _, s2| s.z.cmp(&s2.z));
-                window.window.request_redraw();
-                window.sprites_dirty = false;
+        stats.cpu_time += start.elapsed().as_secs_f64();
+    }
+}
+
+/// Looks up one of a client's windows, telling the client if it doesn't exist.
+fn window_mut<'a>(
+    windows: &'a mut IndexMap<(ClientId, usize), Window>,
+    clients: &Clients,
+    client: ClientId,
+    id: usize,
+) -> Option<&'a mut Window> {
+    let window = windows.get_mut(&(client, id));
+    if window.is_none() {
+        clients.send(
+            client,
+            ReturnMessage::Error(ProtocolError::UnknownWindow(id)),
+        );
+    }
+    window
+}
+
+/// Something that lives on a window, which clients refer to by id.
+trait Object: Sized {
+    fn all(window: &mut Window) -> &mut IndexMap<usize, Self>;
+
+    fn unknown(id: usize, window_id: usize) -> ProtocolError;
+}
+
+macro_rules! objects {
+    ($($object:ty => $field:ident, $unknown:ident;)*) => {$(
+        impl Object for $object {
+            fn all(window: &mut Window) -> &mut IndexMap<usize, Self> {
+                &mut window.$field
+            }
+
+            fn unknown(id: usize, window_id: usize) -> ProtocolError {
+                ProtocolError::$unknown(id, window_id)
             }
         }
+    )*};
+}
+
+objects! {
+    Sprite => sprites, UnknownSprite;
+    Plane => planes, UnknownPlane;
+    Tilemap => tilemaps, UnknownTilemap;
+    Panel => panels, UnknownPanel;
+    Shape => shapes, UnknownShape;
+    shader::Program => shaders, UnknownShader;
+}
+
+/// Looks up something on a window, telling the client if it doesn't exist.
+fn object_mut<'a, T: Object>(
+    window: &'a mut Window,
+    clients: &Clients,
+    client: ClientId,
+    id: usize,
+    window_id: usize,
+) -> Option<&'a mut T> {
+    let object = T::all(window).get_mut(&id);
+    if object.is_none() {
+        let error = T::unknown(id, window_id);
+        clients.send(client, ReturnMessage::Error(error));
     }
+    object
+}
+
+enum Drawable<'a> {
+    Sprite(&'a Sprite),
+    Plane(&'a Plane),
+    Tiles(&'a tilemap::Tileset, &'a tilemap::Chunk, &'a tilemap::Group),
+    Panel(&'a Panel),
+    Shape(&'a Shape),
+}
+
+fn render(
+    wgpu_state: &wgpu_state::State,
+    window: &Window,
+    view: &wgpu::TextureView,
+    frame: u64,
+    stats: &mut FrameStats,
+) -> wgpu::CommandEncoder {
+    let mut encoder = wgpu_state.create_command_encoder();
+
+    let tilemaps: Vec<_> = window
+        .tilemaps
+        .values()
+        .filter(|tilemap| tilemap.config.visible)
+        .filter_map(|tilemap| Some((tilemap, tilemap.tileset.as_ref()?)))
+        .collect();
+    for (tilemap, _) in &tilemaps {
+        wgpu_state.prepare_tilemap(&window.surface, tilemap, frame);
+    }
+    let target = window.surface.size();
+
+    // With a window shader, everything before it is drawn offscreen and the shader draws it to
+    // `view`. The same goes for effects, which draw to wherever the shader reads from
+    let shader = window
+        .shader
+        .and_then(|id| window.shaders.get(&id))
+        .zip(window.shader_pass.as_ref());
+    let effects_output = shader.map_or(view, |(_, pass)| pass.view());
+    let scene = window.effects.scene_view().unwrap_or(effects_output);
+    let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+        color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+            view: scene,
+            resolve_target: None,
+            ops: wgpu::Operations {
+                load: wgpu::LoadOp::Clear(wgpu::Color {
+                    r: 0.05,
+                    g: 0.0,
+                    b: 0.1,
+                    a: 0.7,
+                }),
+                store: true,
+            },
+        })],
+        ..Default::default()
+    });
+
+    // Sprites are already sorted by z, and a stable sort keeps them that way
+    let mut drawables: Vec<(i32, Drawable<'_>)> = window
+        .sprites
+        .values()
+        .map(|sprite| (sprite.z, Drawable::Sprite(sprite)))
+        .chain(
+            window
+                .planes
+                .values()
+                .filter(|plane| plane.config.visible)
+                .map(|plane| (plane.config.z, Drawable::Plane(plane))),
+        )
+        .chain(tilemaps.iter().flat_map(|&(tilemap, tileset)| {
+            tilemap
+                .chunks
+                .values()
+                .filter(move |chunk| tilemap.is_chunk_visible(chunk, target))
+                .flat_map(move |chunk| {
+                    chunk.groups.iter().map(move |group| {
+                        let z = tilemap.z(group.layer);
+                        (z, Drawable::Tiles(tileset, chunk, group))
+                    })
+                })
+        }))
+        .chain(
+            window
+                .panels
+                .values()
+                .filter(|panel| panel.config.visible)
+                .map(|panel| (panel.config.z, Drawable::Panel(panel))),
+        )
+        .chain(
+            window
+                .shapes
+                .values()
+                .filter(|shape| shape.config.visible)
+                .map(|shape| (shape.config.z, Drawable::Shape(shape))),
+        )
+        .collect();
+    drawables.sort_by_key(|(z, _)| *z);
+
+    for (_, drawable) in drawables {
+        stats.draw_calls += match drawable {
+            Drawable::Sprite(sprite) => {
+                let Some(ref image) = sprite.image else {
+                    continue;
+                };
+                let program = sprite.shader.and_then(|id| window.shaders.get(&id));
+                wgpu_state.draw_sprite(
+                    &mut render_pass,
+                    &window.surface,
+                    program,
+                    &sprite.uniforms,
+                    image,
+                    (sprite.x, sprite.y),
+                    frame,
+                );
+                stats.sprites_drawn += 1;
+                1
+            }
+            Drawable::Plane(plane) => {
+                let Some(ref image) = plane.image else {
+                    continue;
+                };
+                wgpu_state.draw_plane(&mut render_pass, &window.surface, &plane.config, image);
+                1
+            }
+            Drawable::Tiles(tileset, chunk, group) => {
+                wgpu_state.draw_tiles(&mut render_pass, &window.surface, tileset, chunk, group);
+                1
+            }
+            Drawable::Panel(panel) => {
+                wgpu_state.draw_panel(&mut render_pass, &window.surface, panel)
+            }
+            Drawable::Shape(shape) => {
+                wgpu_state.draw_shape(&mut render_pass, &window.surface, shape)
+            }
+        };
+    }
+
+    drop(render_pass);
+    stats.draw_calls += wgpu_state.run_effects(&mut encoder, &window.effects, effects_output);
+    if let Some((program, pass)) = shader {
+        wgpu_state.run_shader(&mut encoder, program, pass, view, frame);
+        stats.draw_calls += 1;
+    }
+
+    encoder
 }
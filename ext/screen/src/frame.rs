@@ -0,0 +1,88 @@
+// Copyright (C) 2023 Lily Lyons
+//
+// This file is part of libfm.
+//
+// libfm is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// libfm is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with libfm.  If not, see <http://www.gnu.org/licenses/>.
+
+use crate::FrameConfig;
+use std::time::{Duration, Instant};
+
+pub struct Scheduler {
+    pub config: FrameConfig,
+    pub frame: u64,
+    pub delta: f64,
+    last_frame: Instant,
+    next_frame: Instant,
+}
+
+impl Scheduler {
+    pub fn new(config: FrameConfig) -> Self {
+        let now = Instant::now();
+        Self {
+            config,
+            frame: 0,
+            delta: 0.0,
+            last_frame: now,
+            next_frame: now,
+        }
+    }
+
+    pub fn configure(&mut self, config: FrameConfig) {
+        self.config = config;
+        self.next_frame = Instant::now();
+    }
+
+    fn interval(&self) -> Option<Duration> {
+        self.config
+            .target_fps
+            .filter(|fps| *fps > 0)
+            .map(|fps| Duration::from_secs_f64(1.0 / fps as f64))
+    }
+
+    /// When the event loop should wake up for the next frame, if frames are capped.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.interval().map(|_| self.next_frame)
+    }
+
+    /// Advances the scheduler if a frame boundary has been reached.
+    pub fn tick(&mut self, now: Instant) -> bool {
+        let Some(interval) = self.interval() else {
+            self.delta = (now - self.last_frame).as_secs_f64();
+            self.last_frame = now;
+            self.frame += 1;
+            return true;
+        };
+
+        if now < self.next_frame {
+            return false;
+        }
+
+        let delta = if self.config.fixed_timestep {
+            // Count every boundary we've passed so the frame counter stays in step with
+            // wall time, even if we woke up late.
+            let steps = ((now - self.next_frame).as_secs_f64() / interval.as_secs_f64()) as u32 + 1;
+            self.next_frame += interval * steps;
+            self.frame += steps as u64;
+            interval * steps
+        } else {
+            self.next_frame = now + interval;
+            self.frame += 1;
+            now - self.last_frame
+        };
+        self.delta = delta.as_secs_f64();
+        self.last_frame = now;
+
+        true
+    }
+}
@@ -15,6 +15,31 @@
 // You should have received a copy of the GNU General Public License
 // along with libfm.  If not, see <http://www.gnu.org/licenses/>.
 
+mod clients;
+mod effect;
+mod event_loop;
+mod frame;
+mod panel;
+mod plane;
+pub mod record;
+pub mod renderer;
+mod shader;
+mod shape;
+mod socket_loop;
+mod tilemap;
+mod wgpu_state;
+pub mod wire;
+
+pub use crate::shader::{validate as validate_shader, ShaderError, PRELUDE as SHADER_PRELUDE};
+
+/// Bumped whenever `Message` or `ReturnMessage` change in an incompatible way.
+/// The screen binary reports it when run with `--protocol-version`. tests/protocol.rs fails
+/// when the wire format changes without it.
+pub const PROTOCOL_VERSION: u32 = 14;
+
+/// How many floats of user uniforms a shader gets.
+pub const SHADER_UNIFORMS: usize = 16;
+
 #[derive(serde::Serialize, serde::Deserialize, Debug)]
 pub struct WindowConfig {
     pub title: String,
@@ -25,6 +50,335 @@ pub struct WindowConfig {
     pub z: Option<i32>,
 }
 
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PresentMode {
+    Fifo,
+    Mailbox,
+    Immediate,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameConfig {
+    /// Frames per second to schedule redraws at. `None` runs as fast as possible.
+    pub target_fps: Option<u32>,
+    pub present_mode: PresentMode,
+    /// Advance frame deadlines by exact multiples of the frame interval instead of
+    /// rescheduling from the time the last frame was processed.
+    pub fixed_timestep: bool,
+}
+
+impl Default for FrameConfig {
+    fn default() -> Self {
+        Self {
+            target_fps: Some(60),
+            present_mode: PresentMode::Fifo,
+            fixed_timestep: false,
+        }
+    }
+}
+
+/// A fullscreen pass run over a window's scene before it's presented, in the order given.
+/// Distances are in window pixels.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum Effect {
+    Blur {
+        radius: f32,
+    },
+    /// Darkens every other row by `scanlines`, between 0 and 1, and bends the scene like an old
+    /// monitor. A `curvature` of 0 keeps it flat.
+    Crt {
+        scanlines: f32,
+        curvature: f32,
+    },
+    /// Shifts the red and blue channels `offset` pixels apart.
+    ChromaticAberration {
+        offset: f32,
+    },
+    /// Between 0, which changes nothing, and 1, which is fully gray.
+    Grayscale {
+        amount: f32,
+    },
+    /// Draws the scene in blocks `size` pixels wide.
+    Pixelate {
+        size: f32,
+    },
+    /// Moves the scene around by up to `amplitude` pixels, picking a new direction `speed`
+    /// times a frame.
+    Shake {
+        amplitude: f32,
+        speed: f32,
+    },
+}
+
+/// An RGSS style color, with every component between 0 and 255.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+pub struct Color {
+    pub red: f32,
+    pub green: f32,
+    pub blue: f32,
+    pub alpha: f32,
+}
+
+/// An RGSS style tone. Red, green and blue are between -255 and 255, gray between 0 and 255.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+pub struct Tone {
+    pub red: f32,
+    pub green: f32,
+    pub blue: f32,
+    pub gray: f32,
+}
+
+#[derive(
+    serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, Default,
+)]
+pub enum BlendType {
+    #[default]
+    Normal,
+    Add,
+    Subtract,
+}
+
+/// How a plane is drawn. Planes tile their image across the whole window.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct PlaneConfig {
+    /// How far the image is scrolled, in window pixels.
+    pub ox: i32,
+    pub oy: i32,
+    pub z: i32,
+    pub zoom_x: f32,
+    pub zoom_y: f32,
+    /// Between 0 and 255.
+    pub opacity: u8,
+    pub blend_type: BlendType,
+    /// Blended over the image by the color's alpha.
+    pub color: Color,
+    pub tone: Tone,
+    pub visible: bool,
+}
+
+impl Default for PlaneConfig {
+    fn default() -> Self {
+        Self {
+            ox: 0,
+            oy: 0,
+            z: 0,
+            zoom_x: 1.0,
+            zoom_y: 1.0,
+            opacity: 255,
+            blend_type: BlendType::Normal,
+            color: Color::default(),
+            tone: Tone::default(),
+            visible: true,
+        }
+    }
+}
+
+/// A rectangle in window pixels.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Rect {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+/// How the edges of a panel's frame fill the space between its corners.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EdgeMode {
+    #[default]
+    Stretch,
+    Tile,
+}
+
+/// How a panel is drawn. Panels are RGSS style windows, drawn from a 128x128 skin laid out
+/// like RPG Maker VX Ace's: a background, a background pattern, a nine-slice frame and a cursor.
+/// Larger skins are scaled to that layout.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PanelConfig {
+    pub rect: Rect,
+    pub z: i32,
+    /// How far the contents are scrolled, in contents pixels.
+    pub ox: i32,
+    pub oy: i32,
+    /// The space between the panel's edges and its contents.
+    pub padding: i32,
+    pub edges: EdgeMode,
+    /// Relative to the top left of the contents. Empty rects hide the cursor.
+    pub cursor: Rect,
+    /// Between 0 and 255. `opacity` applies to the whole skin, `back_opacity` to its background
+    /// on top of that, and `contents_opacity` to the contents alone.
+    pub opacity: u8,
+    pub back_opacity: u8,
+    pub contents_opacity: u8,
+    pub visible: bool,
+}
+
+impl Default for PanelConfig {
+    fn default() -> Self {
+        Self {
+            rect: Rect::default(),
+            z: 0,
+            ox: 0,
+            oy: 0,
+            padding: 12,
+            edges: EdgeMode::Stretch,
+            cursor: Rect::default(),
+            opacity: 255,
+            back_opacity: 192,
+            contents_opacity: 255,
+            visible: true,
+        }
+    }
+}
+
+/// What a shape draws, in window pixels.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq)]
+pub enum ShapeGeometry {
+    Rect(Rect),
+    Line {
+        from: (f32, f32),
+        to: (f32, f32),
+    },
+    Circle {
+        center: (f32, f32),
+        radius: f32,
+    },
+    /// Filled polygons are assumed to be convex.
+    Polygon(Vec<(f32, f32)>),
+}
+
+/// How a shape is drawn.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct ShapeConfig {
+    pub z: i32,
+    pub color: Color,
+    /// Outlined shapes are drawn with lines `line_width` pixels wide. Lines ignore this.
+    pub filled: bool,
+    pub line_width: f32,
+    pub visible: bool,
+}
+
+impl Default for ShapeConfig {
+    fn default() -> Self {
+        Self {
+            z: 0,
+            color: Color {
+                red: 255.0,
+                green: 255.0,
+                blue: 255.0,
+                alpha: 255.0,
+            },
+            filled: true,
+            line_width: 1.0,
+            visible: true,
+        }
+    }
+}
+
+/// How a tilemap is drawn.
+///
+/// Tiles with a priority of 0 are drawn at `z`. Tiles with a higher priority are drawn at
+/// `(row + priority + 1) * tile_size - oy` instead, so they interleave with sprites the way they
+/// do in RGSS.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TilemapConfig {
+    /// How far the map is scrolled, in window pixels.
+    pub ox: i32,
+    pub oy: i32,
+    pub z: i32,
+    /// The width and height of a tile, both on the map and in the tileset.
+    pub tile_size: u32,
+    /// How many frames each frame of a tile animation is shown for.
+    pub anim_interval: u32,
+    pub visible: bool,
+}
+
+impl Default for TilemapConfig {
+    fn default() -> Self {
+        Self {
+            ox: 0,
+            oy: 0,
+            z: 0,
+            tile_size: 32,
+            anim_interval: 16,
+            visible: true,
+        }
+    }
+}
+
+/// Every tile of a map, stored by layer, then row, then column.
+///
+/// Tile 0 is empty, any other tile `n` is cell `n - 1` of the tileset, counting left to right
+/// and then top to bottom.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct TileData {
+    pub width: u32,
+    pub height: u32,
+    pub layers: u32,
+    pub tiles: Vec<u16>,
+}
+
+impl TileData {
+    pub fn new(width: u32, height: u32, layers: u32) -> Self {
+        Self {
+            width,
+            height,
+            layers,
+            tiles: vec![0; (width * height * layers) as usize],
+        }
+    }
+
+    /// Where the tile at `x`, `y` on `layer` is in `tiles`, if it's on the map.
+    pub fn index(&self, x: u32, y: u32, layer: u32) -> Option<usize> {
+        (x < self.width && y < self.height && layer < self.layers)
+            .then(|| ((layer * self.height + y) * self.width + x) as usize)
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TileUpdate {
+    pub x: u32,
+    pub y: u32,
+    pub layer: u32,
+    pub tile: u16,
+}
+
+/// Properties shared by every use of a tile.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TileInfo {
+    pub priority: u8,
+    /// Animated tiles cycle through this many tileset cells, starting at their own.
+    pub frames: u8,
+}
+
+impl Default for TileInfo {
+    fn default() -> Self {
+        Self {
+            priority: 0,
+            frames: 1,
+        }
+    }
+}
+
+/// Renderer instrumentation, summed over the frames since the last report.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, Default)]
+pub struct FrameStats {
+    pub frames: u32,
+    /// Wall time covered by this report, in seconds.
+    pub elapsed: f64,
+    /// Time spent processing events in the screen's event loop, in seconds.
+    pub cpu_time: f64,
+    /// Time from submitting a frame to the GPU until the GPU finished it, in seconds.
+    /// Frames still in flight when a report is sent count towards the next one.
+    pub gpu_time: f64,
+    /// Draw calls recorded for frames, including effects and window shaders.
+    pub draw_calls: u32,
+    pub sprites_drawn: u32,
+    pub messages: u32,
+    /// Bytes of sprite textures alive at the time of the report.
+    pub texture_memory: u64,
+}
+
 #[derive(serde::Deserialize, serde::Serialize, Debug)]
 pub enum Message {
     CreateWindow(WindowConfig, usize),
@@ -35,9 +389,164 @@ pub enum Message {
     RemoveSprite(usize, usize),
     SetSprite(usize, usize, String),
     RepositionSprite(usize, usize, i32, i32, i32),
+    /// Planes are identified by plane and window id, like sprites.
+    CreatePlane(usize, usize),
+    RemovePlane(usize, usize),
+    SetPlane(usize, usize, String),
+    ConfigurePlane(usize, usize, PlaneConfig),
+    /// Tilemaps are identified by tilemap and window id, like sprites.
+    CreateTilemap(usize, usize),
+    RemoveTilemap(usize, usize),
+    SetTileset(usize, usize, String),
+    ConfigureTilemap(usize, usize, TilemapConfig),
+    /// Replaces the whole map.
+    SetTileData(usize, usize, TileData),
+    /// Changes a few tiles of the map.
+    SetTiles(usize, usize, Vec<TileUpdate>),
+    /// Sets the properties of the tiles with these ids.
+    SetTileInfo(usize, usize, Vec<(u16, TileInfo)>),
+    /// Panels are identified by panel and window id, like sprites.
+    CreatePanel(usize, usize),
+    RemovePanel(usize, usize),
+    SetPanelSkin(usize, usize, String),
+    /// Sets or clears the image drawn inside a panel's padding.
+    SetPanelContents(usize, usize, Option<String>),
+    ConfigurePanel(usize, usize, PanelConfig),
+    /// Shapes are identified by shape and window id, like sprites.
+    CreateShape(usize, usize),
+    RemoveShape(usize, usize),
+    SetShape(usize, usize, ShapeGeometry),
+    ConfigureShape(usize, usize, ShapeConfig),
+    ConfigureFrames(FrameConfig),
+    Snapshot(usize),
+    /// Replaces a window's effects. If the new effects are the same kinds in the same order as
+    /// the old ones, their parameters are eased from the old values over this many frames.
+    SetEffects(Vec<Effect>, u32, usize),
+    /// Shaders are identified by shader and window id, like sprites. The source is a fragment
+    /// shader written against [`SHADER_PRELUDE`].
+    CreateShader(usize, usize, String),
+    RemoveShader(usize, usize),
+    SetShaderUniforms(usize, usize, [f32; SHADER_UNIFORMS]),
+    /// Draws a sprite with a shader, or the default one if it's `None`.
+    SetSpriteShader(usize, usize, Option<usize>),
+    /// Runs a shader over the whole window after its effects, or stops if it's `None`.
+    SetWindowShader(Option<usize>, usize),
+    /// Messages that are applied together before the next redraw.
+    Batch(Vec<Message>),
+    /// Asks the screen to close its windows and exit.
+    Shutdown,
 }
 
-#[derive(serde::Deserialize, serde::Serialize, Debug)]
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone)]
 pub enum ReturnMessage {
     CloseRequested(usize),
+    /// A window's outer position changed, including when it was first created.
+    Moved(usize, i32, i32),
+    /// A window's inner size changed, including when it was first created.
+    Resized(usize, u32, u32),
+    Focused(usize, bool),
+    /// Sent at every frame boundary with the frame count and the time since the last frame in seconds.
+    Frame(u64, f64),
+    Stats(FrameStats),
+    /// A PNG encoded capture of a window's scene, in response to `Message::Snapshot`.
+    Snapshot(usize, Vec<u8>),
+    /// A message from the client was rejected.
+    Error(ProtocolError),
+    /// An image could not be loaded. Holds the path and why it failed.
+    LoadFailed(String, String),
+}
+
+/// Why the screen rejected a message. Sprites are identified by sprite and window id.
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProtocolError {
+    DuplicateWindow(usize),
+    DuplicateSprite(usize, usize),
+    UnknownWindow(usize),
+    UnknownSprite(usize, usize),
+    DuplicatePlane(usize, usize),
+    UnknownPlane(usize, usize),
+    DuplicateTilemap(usize, usize),
+    UnknownTilemap(usize, usize),
+    /// Tiles were set outside of a tilemap's map, or the map's size didn't match its tiles.
+    TilesOutOfBounds(usize, usize),
+    DuplicatePanel(usize, usize),
+    UnknownPanel(usize, usize),
+    DuplicateShape(usize, usize),
+    UnknownShape(usize, usize),
+    DuplicateShader(usize, usize),
+    UnknownShader(usize, usize),
+    /// A shader failed to compile or didn't fit the prelude's interface.
+    InvalidShader(usize, usize),
+    /// Frame settings are shared by every client, so only the first client to set them can
+    /// change them until it disconnects.
+    FramesOwned,
+    /// The screen turned down our handshake, since the client speaks the first protocol version
+    /// and the screen speaks the second. Only raised by clients, the screen never sends it.
+    VersionMismatch(u32, u32),
+    /// The window's surface is in a format snapshots can't be read back from.
+    SnapshotUnsupported(usize),
 }
+
+impl std::fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProtocolError::DuplicateWindow(id) => write!(f, "window {id} already exists"),
+            ProtocolError::DuplicateSprite(id, window) => {
+                write!(f, "sprite {id} already exists on window {window}")
+            }
+            ProtocolError::UnknownWindow(id) => write!(f, "window {id} does not exist"),
+            ProtocolError::UnknownSprite(id, window) => {
+                write!(f, "sprite {id} does not exist on window {window}")
+            }
+            ProtocolError::DuplicatePlane(id, window) => {
+                write!(f, "plane {id} already exists on window {window}")
+            }
+            ProtocolError::UnknownPlane(id, window) => {
+                write!(f, "plane {id} does not exist on window {window}")
+            }
+            ProtocolError::DuplicateTilemap(id, window) => {
+                write!(f, "tilemap {id} already exists on window {window}")
+            }
+            ProtocolError::UnknownTilemap(id, window) => {
+                write!(f, "tilemap {id} does not exist on window {window}")
+            }
+            ProtocolError::TilesOutOfBounds(id, window) => {
+                write!(f, "tiles out of bounds for tilemap {id} on window {window}")
+            }
+            ProtocolError::DuplicatePanel(id, window) => {
+                write!(f, "panel {id} already exists on window {window}")
+            }
+            ProtocolError::UnknownPanel(id, window) => {
+                write!(f, "panel {id} does not exist on window {window}")
+            }
+            ProtocolError::DuplicateShape(id, window) => {
+                write!(f, "shape {id} already exists on window {window}")
+            }
+            ProtocolError::UnknownShape(id, window) => {
+                write!(f, "shape {id} does not exist on window {window}")
+            }
+            ProtocolError::DuplicateShader(id, window) => {
+                write!(f, "shader {id} already exists on window {window}")
+            }
+            ProtocolError::UnknownShader(id, window) => {
+                write!(f, "shader {id} does not exist on window {window}")
+            }
+            ProtocolError::InvalidShader(id, window) => {
+                write!(f, "shader {id} on window {window} is invalid")
+            }
+            ProtocolError::FramesOwned => f.write_str("frame settings are owned by another client"),
+            ProtocolError::VersionMismatch(client, screen) => write!(
+                f,
+                "the client speaks protocol version {client}, but the screen speaks {screen}"
+            ),
+            ProtocolError::SnapshotUnsupported(id) => {
+                write!(
+                    f,
+                    "window {id} can't be snapshotted in its surface's format"
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for ProtocolError {}
@@ -1,95 +1,57 @@
-use screen::Message;
-
-use indexmap::IndexMap;
-use std::sync::Arc;
-
-use tokio::sync::{mpsc::unbounded_channel, Mutex};
-use winit::event::Event;
-
-mod event_loop;
-mod socket_loop;
-mod wgpu_state;
-
-pub struct State {
-    windows: IndexMap<usize, Window>,
-    wgpu_state: wgpu_state::State,
-}
-
-struct Window {
-    window: winit::window::Window,
-    surface: wgpu_state::Surface,
-    sprites: IndexMap<usize, Sprite>,
-    sprites_dirty: bool,
-}
-
-struct Sprite {
-    x: i32,
-    y: i32,
-    z: i32,
-    image: Option<wgpu_state::Texture>,
-}
+// Copyright (C) 2023 Lily Lyons
+//
+// This file is part of libfm.
+//
+// libfm is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// libfm is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with libfm.  If not, see <http://www.gnu.org/licenses/>.
+
+const USAGE: &str = "usage: libfm-screen <socket addr>
+       libfm-screen --serve <socket addr>
+       libfm-screen --replay <recording> [--speed <multiplier>] [--exit-on-end]
+       libfm-screen --protocol-version";
 
 fn main() {
-    let event_loop = winit::event_loop::EventLoopBuilder::with_user_event().build();
-    let proxy = event_loop.create_proxy();
-
-    let runtime = tokio::runtime::Builder::new_multi_thread()
-        .worker_threads(2)
-        .enable_all()
-        .build()
-        .expect("failed to build runtime");
-
-    let state = Arc::new(Mutex::new(State {
-        windows: IndexMap::new(),
-        wgpu_state: runtime.block_on(wgpu_state::State::new()),
-    }));
-    let async_state = state.clone();
-    let (event_send, event_recv) = unbounded_channel();
-
-    runtime.spawn(async move {
-        let state = async_state;
-        let socket_addr = std::env::args().nth(1).expect("socket addr not provided");
-        let socket = interprocess::local_socket::tokio::LocalSocketStream::connect(socket_addr)
-            .await
-            .expect("failed to connect to socket");
-
-        let (reader, writer) = socket.into_split();
-
-        tokio::task::spawn(socket_loop::run(proxy, reader));
-        event_loop::run(state, event_recv, writer).await;
-    });
-
-    event_loop.run(move |event, target, c| {
-        c.set_wait_timeout(std::time::Duration::from_millis(16));
-
-        let mut state = state.blocking_lock();
-        if let Event::UserEvent(Message::CreateWindow(ref conf, id)) = event {
-            let mut builder = winit::window::WindowBuilder::new()
-                .with_visible(conf.visible)
-                .with_inner_size(winit::dpi::PhysicalSize::new(conf.size.0, conf.size.1))
-                .with_transparent(true)
-                .with_decorations(conf.decorations)
-                .with_resizable(false)
-                .with_title(&conf.title);
-            if let Some((x, y)) = conf.pos {
-                builder = builder.with_position(winit::dpi::LogicalPosition::new(x, y));
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    match args.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+        ["--protocol-version"] => println!("{}", screen::PROTOCOL_VERSION),
+        ["--serve", socket_addr] => screen::renderer::run_server(socket_addr.to_string()),
+        ["--replay", path, ref options @ ..] => {
+            let mut speed = 1.0;
+            let mut exit_on_end = false;
+            let mut options = options.iter();
+            while let Some(option) = options.next() {
+                match *option {
+                    // `--speed inf` plays the recording back as fast as possible
+                    "--speed" => {
+                        let value = options.next().unwrap_or_else(|| usage());
+                        speed = value.parse().expect("speed is not a number");
+                        assert!(speed > 0.0, "speed must be positive");
+                    }
+                    "--exit-on-end" => exit_on_end = true,
+                    _ => usage(),
+                }
             }
-            let window = builder.build(target).expect("failed to create window");
-            let surface = state.wgpu_state.create_surface(&window);
-
-            state.windows.insert(
-                id,
-                Window {
-                    window,
-                    sprites: IndexMap::new(),
-                    sprites_dirty: false,
-                    surface,
-                },
-            );
+            screen::renderer::replay(path.as_ref(), speed, exit_on_end)
         }
-
-        if let Some(e) = event.to_static() {
-            event_send.send(e).expect("failed to send event");
+        [socket_addr] if !socket_addr.starts_with("--") => {
+            screen::renderer::run(socket_addr.to_string())
         }
-    })
+        _ => usage(),
+    }
+}
+
+fn usage() -> ! {
+    eprintln!("{USAGE}");
+    std::process::exit(2);
 }
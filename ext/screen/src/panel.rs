@@ -0,0 +1,517 @@
+// Copyright (C) 2023 Lily Lyons
+//
+// This file is part of libfm.
+//
+// libfm is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// libfm is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with libfm.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::HashMap;
+
+use wgpu::util::DeviceExt;
+
+use crate::wgpu_state::Texture;
+use crate::{EdgeMode, PanelConfig, Rect};
+
+// Matches `Uniforms` in panel.wgsl, padded out to a multiple of 16 bytes
+const UNIFORM_SIZE: u64 = 16;
+// Matches `VertexInput` in panel.wgsl: a position, texture coordinates and an alpha
+const VERTEX_FLOATS: usize = 5;
+
+// Where each part of the skin is, in the 128x128 layout. Skins of other sizes are scaled to it
+const SKIN_LAYOUT: f32 = 128.0;
+const BACK: [f32; 4] = [0.0, 0.0, 64.0, 64.0];
+const PATTERN: [f32; 4] = [0.0, 64.0, 64.0, 64.0];
+const FRAME: [f32; 4] = [64.0, 0.0, 64.0, 64.0];
+const FRAME_BORDER: f32 = 16.0;
+const CURSOR: [f32; 4] = [64.0, 64.0, 32.0, 32.0];
+const CURSOR_BORDER: f32 = 8.0;
+// The background is inset so it doesn't poke out past the frame's rounded corners
+const BACK_INSET: f32 = 2.0;
+
+/// The pipelines panels are drawn with, one for each surface format.
+pub struct Renderer {
+    shader: wgpu::ShaderModule,
+    uniform_layout: wgpu::BindGroupLayout,
+    texture_layout: wgpu::BindGroupLayout,
+    layout: wgpu::PipelineLayout,
+    pipelines: HashMap<wgpu::TextureFormat, wgpu::RenderPipeline>,
+}
+
+impl Renderer {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let shader = device.create_shader_module(wgpu::include_wgsl!("panel.wgsl"));
+        let uniform_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("panel uniform bind group layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+        let texture_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("panel texture bind group layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("panel pipeline layout"),
+            bind_group_layouts: &[&uniform_layout, &texture_layout],
+            push_constant_ranges: &[],
+        });
+
+        Self {
+            shader,
+            uniform_layout,
+            texture_layout,
+            layout,
+            pipelines: HashMap::new(),
+        }
+    }
+
+    /// Builds the pipeline for drawing to `format`, unless it already exists.
+    pub fn prepare(&mut self, device: &wgpu::Device, format: wgpu::TextureFormat) {
+        if self.pipelines.contains_key(&format) {
+            return;
+        }
+
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("panel pipeline"),
+            layout: Some(&self.layout),
+            vertex: wgpu::VertexState {
+                module: &self.shader,
+                entry_point: "vs_main",
+                buffers: &[wgpu::VertexBufferLayout {
+                    array_stride: (VERTEX_FLOATS * std::mem::size_of::<f32>()) as u64,
+                    step_mode: wgpu::VertexStepMode::Vertex,
+                    attributes: &wgpu::vertex_attr_array![
+                        0 => Float32x2,
+                        1 => Float32x2,
+                        2 => Float32,
+                    ],
+                }],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &self.shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+        self.pipelines.insert(format, pipeline);
+    }
+
+    pub fn create_image(&self, device: &wgpu::Device, texture: Texture) -> Image {
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("panel texture bind group"),
+            layout: &self.texture_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&texture.sampler),
+                },
+            ],
+        });
+
+        Image {
+            texture,
+            bind_group,
+        }
+    }
+
+    /// Draws the skin, then the contents over it. Returns how many of them were drawn.
+    pub fn draw<'pass>(
+        &'pass self,
+        pass: &mut wgpu::RenderPass<'pass>,
+        format: wgpu::TextureFormat,
+        panel: &'pass Panel,
+    ) -> u32 {
+        pass.set_pipeline(&self.pipelines[&format]);
+        pass.set_bind_group(0, &panel.bind_group, &[]);
+
+        let mut draws = 0;
+        for layer in [&panel.skin, &panel.contents] {
+            let Some(Layer {
+                image,
+                vertices: Some((ref buffer, count)),
+            }) = layer
+            else {
+                continue;
+            };
+            pass.set_bind_group(1, &image.bind_group, &[]);
+            pass.set_vertex_buffer(0, buffer.slice(..));
+            pass.draw(0..*count, 0..1);
+            draws += 1;
+        }
+        draws
+    }
+}
+
+/// A skin or contents texture.
+pub struct Image {
+    texture: Texture,
+    bind_group: wgpu::BindGroup,
+}
+
+// An image and the quads it's drawn with, if there are any
+struct Layer {
+    image: Image,
+    vertices: Option<(wgpu::Buffer, u32)>,
+}
+
+pub struct Panel {
+    pub config: PanelConfig,
+    uniforms: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    skin: Option<Layer>,
+    contents: Option<Layer>,
+}
+
+impl Panel {
+    pub fn new(device: &wgpu::Device, renderer: &Renderer) -> Self {
+        let uniforms = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("panel uniforms"),
+            size: UNIFORM_SIZE,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("panel uniform bind group"),
+            layout: &renderer.uniform_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniforms.as_entire_binding(),
+            }],
+        });
+
+        Self {
+            config: PanelConfig::default(),
+            uniforms,
+            bind_group,
+            skin: None,
+            contents: None,
+        }
+    }
+
+    pub fn set_skin(&mut self, device: &wgpu::Device, image: Image) {
+        self.skin = Some(Layer {
+            image,
+            vertices: None,
+        });
+        self.rebuild(device);
+    }
+
+    pub fn set_contents(&mut self, device: &wgpu::Device, image: Option<Image>) {
+        self.contents = image.map(|image| Layer {
+            image,
+            vertices: None,
+        });
+        self.rebuild(device);
+    }
+
+    pub fn configure(&mut self, device: &wgpu::Device, config: PanelConfig) {
+        self.config = config;
+        self.rebuild(device);
+    }
+
+    pub fn memory(&self) -> u64 {
+        [&self.skin, &self.contents]
+            .into_iter()
+            .flatten()
+            .map(|layer| layer.image.texture.memory())
+            .sum()
+    }
+
+    /// Updates the uniforms for drawing to a `target` sized surface.
+    pub fn prepare(&self, queue: &wgpu::Queue, target: (u32, u32)) {
+        let floats = [target.0 as f32, target.1 as f32, 0.0, 0.0];
+        let bytes: Vec<u8> = floats.iter().flat_map(|f| f.to_ne_bytes()).collect();
+        queue.write_buffer(&self.uniforms, 0, &bytes);
+    }
+
+    // The quads only change along with the config or the images, so they're built up front
+    fn rebuild(&mut self, device: &wgpu::Device) {
+        let config = self.config;
+        if let Some(skin) = &mut self.skin {
+            let quads = skin_quads(&config, skin.image.texture.size());
+            skin.vertices = quads.into_buffer(device);
+        }
+        if let Some(contents) = &mut self.contents {
+            let quads = contents_quads(&config, contents.image.texture.size());
+            contents.vertices = quads.into_buffer(device);
+        }
+    }
+}
+
+/// Vertices for a list of textured quads.
+struct Quads {
+    texture_size: (f32, f32),
+    vertices: Vec<f32>,
+}
+
+impl Quads {
+    fn new(texture_size: (u32, u32)) -> Self {
+        Self {
+            texture_size: (texture_size.0 as f32, texture_size.1 as f32),
+            vertices: Vec::new(),
+        }
+    }
+
+    fn into_buffer(self, device: &wgpu::Device) -> Option<(wgpu::Buffer, u32)> {
+        if self.vertices.is_empty() {
+            return None;
+        }
+
+        let bytes: Vec<u8> = self.vertices.iter().flat_map(|f| f.to_ne_bytes()).collect();
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("panel vertices"),
+            contents: &bytes,
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+        Some((buffer, (self.vertices.len() / VERTEX_FLOATS) as u32))
+    }
+
+    /// Stretches `source`, in texture pixels, over `dest`, in target pixels.
+    fn push(&mut self, dest: [f32; 4], source: [f32; 4], alpha: f32) {
+        if dest[2] <= 0.0 || dest[3] <= 0.0 {
+            return;
+        }
+
+        let (width, height) = self.texture_size;
+        let [x, y, w, h] = dest;
+        let [u, v] = [source[0] / width, source[1] / height];
+        let [u2, v2] = [
+            (source[0] + source[2]) / width,
+            (source[1] + source[3]) / height,
+        ];
+
+        for [x, y, u, v] in [
+            [x, y, u, v],
+            [x + w, y, u2, v],
+            [x, y + h, u, v2],
+            [x + w, y, u2, v],
+            [x + w, y + h, u2, v2],
+            [x, y + h, u, v2],
+        ] {
+            self.vertices.extend_from_slice(&[x, y, u, v, alpha]);
+        }
+    }
+
+    /// Repeats `source` across `dest` at its own size, cutting off the last row and column.
+    fn tile(&mut self, dest: [f32; 4], source: [f32; 4], alpha: f32) {
+        if source[2] <= 0.0 || source[3] <= 0.0 {
+            return;
+        }
+
+        let mut y = 0.0;
+        while y < dest[3] {
+            let h = source[3].min(dest[3] - y);
+            let mut x = 0.0;
+            while x < dest[2] {
+                let w = source[2].min(dest[2] - x);
+                let part = [source[0], source[1], w, h];
+                self.push([dest[0] + x, dest[1] + y, w, h], part, alpha);
+                x += w;
+            }
+            y += h;
+        }
+    }
+
+    fn fill(&mut self, mode: EdgeMode, dest: [f32; 4], source: [f32; 4], alpha: f32) {
+        match mode {
+            EdgeMode::Stretch => self.push(dest, source, alpha),
+            EdgeMode::Tile => self.tile(dest, source, alpha),
+        }
+    }
+
+    /// Draws `source` over `dest` with unscaled corners `border` pixels wide, shrinking them to
+    /// fit if `dest` is too small. The center is only drawn if `center` is set.
+    fn nine_slice(
+        &mut self,
+        dest: [f32; 4],
+        source: [f32; 4],
+        border: (f32, f32),
+        edges: EdgeMode,
+        center: bool,
+        alpha: f32,
+    ) {
+        let [x, y, w, h] = dest;
+        let [sx, sy, sw, sh] = source;
+        let (bx, by) = border;
+        let (cx, cy) = (bx.min(w / 2.0), by.min(h / 2.0));
+        let (inner_w, inner_h) = (w - cx * 2.0, h - cy * 2.0);
+        let (source_w, source_h) = (sw - bx * 2.0, sh - by * 2.0);
+        let (right, bottom) = (sx + sw - cx, sy + sh - cy);
+
+        // Corners
+        self.push([x, y, cx, cy], [sx, sy, cx, cy], alpha);
+        self.push([x + w - cx, y, cx, cy], [right, sy, cx, cy], alpha);
+        self.push([x, y + h - cy, cx, cy], [sx, bottom, cx, cy], alpha);
+        self.push(
+            [x + w - cx, y + h - cy, cx, cy],
+            [right, bottom, cx, cy],
+            alpha,
+        );
+
+        // Edges
+        let top = [sx + bx, sy, source_w, cy];
+        self.fill(edges, [x + cx, y, inner_w, cy], top, alpha);
+        let bottom = [sx + bx, bottom, source_w, cy];
+        self.fill(edges, [x + cx, y + h - cy, inner_w, cy], bottom, alpha);
+        let left = [sx, sy + by, cx, source_h];
+        self.fill(edges, [x, y + cy, cx, inner_h], left, alpha);
+        let right = [right, sy + by, cx, source_h];
+        self.fill(edges, [x + w - cx, y + cy, cx, inner_h], right, alpha);
+
+        if center {
+            let middle = [sx + bx, sy + by, source_w, source_h];
+            self.push([x + cx, y + cy, inner_w, inner_h], middle, alpha);
+        }
+    }
+}
+
+fn to_floats(rect: Rect) -> [f32; 4] {
+    [
+        rect.x as f32,
+        rect.y as f32,
+        rect.width as f32,
+        rect.height as f32,
+    ]
+}
+
+// The part of the panel inside its padding, where the contents and cursor go
+fn inner_rect(config: &PanelConfig) -> Rect {
+    let Rect {
+        x,
+        y,
+        width,
+        height,
+    } = config.rect;
+    let padding = config.padding;
+
+    Rect {
+        x: x + padding,
+        y: y + padding,
+        width: width - padding * 2,
+        height: height - padding * 2,
+    }
+}
+
+fn skin_quads(config: &PanelConfig, skin_size: (u32, u32)) -> Quads {
+    let mut quads = Quads::new(skin_size);
+    if config.rect.width <= 0 || config.rect.height <= 0 {
+        return quads;
+    }
+
+    let scale = (
+        skin_size.0 as f32 / SKIN_LAYOUT,
+        skin_size.1 as f32 / SKIN_LAYOUT,
+    );
+    let scaled = |[x, y, w, h]: [f32; 4]| [x * scale.0, y * scale.1, w * scale.0, h * scale.1];
+    let opacity = config.opacity as f32 / 255.0;
+
+    let [x, y, w, h] = to_floats(config.rect);
+    let back = [
+        x + BACK_INSET,
+        y + BACK_INSET,
+        w - BACK_INSET * 2.0,
+        h - BACK_INSET * 2.0,
+    ];
+    let back_alpha = opacity * config.back_opacity as f32 / 255.0;
+    quads.push(back, scaled(BACK), back_alpha);
+    quads.tile(back, scaled(PATTERN), back_alpha);
+
+    let border = (FRAME_BORDER * scale.0, FRAME_BORDER * scale.1);
+    let frame = to_floats(config.rect);
+    quads.nine_slice(frame, scaled(FRAME), border, config.edges, false, opacity);
+
+    let cursor = config.cursor;
+    if cursor.width > 0 && cursor.height > 0 {
+        let inner = inner_rect(config);
+        let dest = [
+            (inner.x + cursor.x - config.ox) as f32,
+            (inner.y + cursor.y - config.oy) as f32,
+            cursor.width as f32,
+            cursor.height as f32,
+        ];
+        let border = (CURSOR_BORDER * scale.0, CURSOR_BORDER * scale.1);
+        // RGSS always stretches the cursor
+        let source = scaled(CURSOR);
+        quads.nine_slice(dest, source, border, EdgeMode::Stretch, true, opacity);
+    }
+
+    quads
+}
+
+// The contents are clipped to the inside of the padding, scrolled by `ox` and `oy`
+fn contents_quads(config: &PanelConfig, contents_size: (u32, u32)) -> Quads {
+    let mut quads = Quads::new(contents_size);
+    let inner = inner_rect(config);
+    let image = Rect {
+        x: inner.x - config.ox,
+        y: inner.y - config.oy,
+        width: contents_size.0 as i32,
+        height: contents_size.1 as i32,
+    };
+
+    let left = inner.x.max(image.x);
+    let top = inner.y.max(image.y);
+    let right = (inner.x + inner.width).min(image.x + image.width);
+    let bottom = (inner.y + inner.height).min(image.y + image.height);
+    if right <= left || bottom <= top {
+        return quads;
+    }
+
+    let (width, height) = ((right - left) as f32, (bottom - top) as f32);
+    let dest = [left as f32, top as f32, width, height];
+    let source = [
+        (left - image.x) as f32,
+        (top - image.y) as f32,
+        width,
+        height,
+    ];
+    quads.push(dest, source, config.contents_opacity as f32 / 255.0);
+
+    quads
+}
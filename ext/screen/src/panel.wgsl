@@ -0,0 +1,41 @@
+// Draws the quads panels are built from, positioned in target pixels
+
+struct Uniforms {
+    target_size: vec2<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> panel: Uniforms;
+@group(1) @binding(0)
+var t_panel: texture_2d<f32>;
+@group(1) @binding(1)
+var s_panel: sampler;
+
+struct VertexInput {
+    @location(0) position: vec2<f32>,
+    @location(1) uv: vec2<f32>,
+    @location(2) alpha: f32,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+    @location(1) alpha: f32,
+};
+
+@vertex
+fn vs_main(in: VertexInput) -> VertexOutput {
+    let position = in.position / panel.target_size;
+
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(position.x * 2.0 - 1.0, 1.0 - position.y * 2.0, 0.0, 1.0);
+    out.uv = in.uv;
+    out.alpha = in.alpha;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let texel = textureSample(t_panel, s_panel, in.uv);
+    return vec4<f32>(texel.rgb, texel.a * in.alpha);
+}
@@ -0,0 +1,238 @@
+// Copyright (C) 2023 Lily Lyons
+//
+// This file is part of libfm.
+//
+// libfm is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// libfm is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with libfm.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::HashMap;
+
+use crate::wgpu_state::Texture;
+use crate::{BlendType, PlaneConfig};
+
+// Matches `Uniforms` in plane.wgsl, padded out to a multiple of 16 bytes
+const UNIFORM_FLOATS: usize = 20;
+
+/// The pipelines planes are drawn with. Each surface format needs its own, one per blend type.
+pub struct Renderer {
+    shader: wgpu::ShaderModule,
+    bind_group_layout: wgpu::BindGroupLayout,
+    layout: wgpu::PipelineLayout,
+    pipelines: HashMap<(wgpu::TextureFormat, BlendType), wgpu::RenderPipeline>,
+}
+
+/// A plane's texture along with the uniforms it's drawn with.
+pub struct Image {
+    texture: Texture,
+    uniforms: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+}
+
+impl Image {
+    pub fn memory(&self) -> u64 {
+        self.texture.memory()
+    }
+}
+
+impl Renderer {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let shader = device.create_shader_module(wgpu::include_wgsl!("plane.wgsl"));
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("plane bind group layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("plane pipeline layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        Self {
+            shader,
+            bind_group_layout,
+            layout,
+            pipelines: HashMap::new(),
+        }
+    }
+
+    /// Builds the pipelines for drawing to `format`, unless they already exist.
+    pub fn prepare(&mut self, device: &wgpu::Device, format: wgpu::TextureFormat) {
+        for blend_type in [BlendType::Normal, BlendType::Add, BlendType::Subtract] {
+            if self.pipelines.contains_key(&(format, blend_type)) {
+                continue;
+            }
+
+            let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("plane pipeline"),
+                layout: Some(&self.layout),
+                vertex: wgpu::VertexState {
+                    module: &self.shader,
+                    entry_point: "vs_main",
+                    buffers: &[],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &self.shader,
+                    entry_point: "fs_main",
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format,
+                        blend: Some(blend_state(blend_type)),
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                }),
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+            });
+            self.pipelines.insert((format, blend_type), pipeline);
+        }
+    }
+
+    pub fn create_image(&self, device: &wgpu::Device, texture: Texture) -> Image {
+        let uniforms = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("plane uniforms"),
+            size: (UNIFORM_FLOATS * std::mem::size_of::<f32>()) as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("plane bind group"),
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&texture.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: uniforms.as_entire_binding(),
+                },
+            ],
+        });
+
+        Image {
+            texture,
+            uniforms,
+            bind_group,
+        }
+    }
+
+    /// Draws `image` tiled across a `target` sized surface of `format`.
+    pub fn draw<'pass>(
+        &'pass self,
+        pass: &mut wgpu::RenderPass<'pass>,
+        queue: &wgpu::Queue,
+        format: wgpu::TextureFormat,
+        target: (u32, u32),
+        config: &PlaneConfig,
+        image: &'pass Image,
+    ) {
+        let pipeline = &self.pipelines[&(format, config.blend_type)];
+        // Queued writes land before the next submit, which is the one this pass is part of
+        queue.write_buffer(
+            &image.uniforms,
+            0,
+            &uniforms(config, target, image.texture.size()),
+        );
+
+        pass.set_pipeline(pipeline);
+        pass.set_bind_group(0, &image.bind_group, &[]);
+        pass.draw(0..3, 0..1);
+    }
+}
+
+fn blend_state(blend_type: BlendType) -> wgpu::BlendState {
+    let operation = match blend_type {
+        BlendType::Normal => return wgpu::BlendState::ALPHA_BLENDING,
+        BlendType::Add => wgpu::BlendOperation::Add,
+        BlendType::Subtract => wgpu::BlendOperation::ReverseSubtract,
+    };
+
+    wgpu::BlendState {
+        color: wgpu::BlendComponent {
+            src_factor: wgpu::BlendFactor::SrcAlpha,
+            dst_factor: wgpu::BlendFactor::One,
+            operation,
+        },
+        alpha: wgpu::BlendComponent::OVER,
+    }
+}
+
+fn uniforms(config: &PlaneConfig, target: (u32, u32), texture: (u32, u32)) -> Vec<u8> {
+    let PlaneConfig {
+        ox,
+        oy,
+        zoom_x,
+        zoom_y,
+        opacity,
+        color,
+        tone,
+        ..
+    } = *config;
+
+    let floats: [f32; UNIFORM_FLOATS] = [
+        ox as f32,
+        oy as f32,
+        zoom_x,
+        zoom_y,
+        target.0 as f32,
+        target.1 as f32,
+        texture.0 as f32,
+        texture.1 as f32,
+        color.red / 255.0,
+        color.green / 255.0,
+        color.blue / 255.0,
+        color.alpha / 255.0,
+        tone.red / 255.0,
+        tone.green / 255.0,
+        tone.blue / 255.0,
+        tone.gray / 255.0,
+        opacity as f32 / 255.0,
+        0.0,
+        0.0,
+        0.0,
+    ];
+
+    floats.iter().flat_map(|f| f.to_ne_bytes()).collect()
+}
@@ -0,0 +1,51 @@
+// Draws a texture repeated across the whole target, for planes
+
+struct Uniforms {
+    // Scroll offset, in target pixels
+    offset: vec2<f32>,
+    zoom: vec2<f32>,
+    target_size: vec2<f32>,
+    texture_size: vec2<f32>,
+    // Components are between 0 and 1, except for the tone's red, green and blue
+    // which are between -1 and 1
+    color: vec4<f32>,
+    tone: vec4<f32>,
+    opacity: f32,
+};
+
+@group(0) @binding(0)
+var t_plane: texture_2d<f32>;
+@group(0) @binding(1)
+var s_plane: sampler;
+@group(0) @binding(2)
+var<uniform> plane: Uniforms;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    // Where in the target this fragment is, in pixels
+    @location(0) pixel: vec2<f32>,
+};
+
+// A single triangle that covers the whole target
+@vertex
+fn vs_main(@builtin(vertex_index) in_vertex_index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((in_vertex_index << 1u) & 2u), f32(in_vertex_index & 2u));
+
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+    out.pixel = uv * plane.target_size;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    // The sampler repeats, so coordinates past the edge wrap around
+    let coords = (in.pixel + plane.offset) / plane.zoom / plane.texture_size;
+    let texel = textureSample(t_plane, s_plane, coords);
+
+    let luma = dot(texel.rgb, vec3<f32>(0.299, 0.587, 0.114));
+    var rgb = mix(texel.rgb, vec3<f32>(luma), plane.tone.w) + plane.tone.rgb;
+    rgb = mix(rgb, plane.color.rgb, plane.color.a);
+
+    return vec4<f32>(clamp(rgb, vec3<f32>(0.0), vec3<f32>(1.0)), texel.a * plane.opacity);
+}
@@ -0,0 +1,48 @@
+// Declarations every shader is compiled with. A shader only has to define its fragment entry
+// point, `fn fs_main(in: VertexOutput) -> @location(0) vec4<f32>`, and can't add bindings of
+// its own.
+
+struct Globals {
+    // Where the sprite or window is drawn, in target pixels: x, y, width and height
+    rect: vec4<f32>,
+    // The size of the target, in pixels
+    resolution: vec2<f32>,
+    // Frames since the screen started
+    time: f32,
+    // Set by the client
+    user: array<vec4<f32>, 4>,
+};
+
+@group(0) @binding(0)
+var<uniform> globals: Globals;
+// The sprite's image, or the window's scene
+@group(1) @binding(0)
+var t_source: texture_2d<f32>;
+@group(1) @binding(1)
+var s_source: sampler;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    // Where in the sprite or window this fragment is, between 0 and 1
+    @location(0) uv: vec2<f32>,
+};
+
+// Two triangles covering `globals.rect`
+@vertex
+fn vs_main(@builtin(vertex_index) in_vertex_index: u32) -> VertexOutput {
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(0.0, 0.0),
+        vec2<f32>(1.0, 0.0),
+        vec2<f32>(0.0, 1.0),
+        vec2<f32>(1.0, 0.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(0.0, 1.0),
+    );
+    let uv = corners[in_vertex_index];
+    let position = (globals.rect.xy + uv * globals.rect.zw) / globals.resolution;
+
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(position.x * 2.0 - 1.0, 1.0 - position.y * 2.0, 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
@@ -0,0 +1,120 @@
+// Copyright (C) 2023 Lily Lyons
+//
+// This file is part of libfm.
+//
+// libfm is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// libfm is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with libfm.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Recordings of the traffic between a client and the screen.
+//!
+//! A recording starts with the [`PROTOCOL_VERSION`](crate::PROTOCOL_VERSION) it was made with,
+//! followed by bincode encoded [`Entry`]s until the end of the file.
+
+use crate::wire::into_io;
+use crate::{Message, ReturnMessage};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::Path;
+use std::time::Instant;
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub enum Recorded {
+    Sent(Message),
+    Received(ReturnMessage),
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct Entry {
+    /// Seconds since the recording started.
+    pub time: f64,
+    pub recorded: Recorded,
+}
+
+// Encodes exactly like `Entry`, so we don't have to take ownership of what we record
+#[derive(serde::Serialize)]
+enum RecordedRef<'a> {
+    Sent(&'a Message),
+    Received(&'a ReturnMessage),
+}
+
+#[derive(serde::Serialize)]
+struct EntryRef<'a> {
+    time: f64,
+    recorded: RecordedRef<'a>,
+}
+
+pub struct Recorder {
+    writer: BufWriter<File>,
+    start: Instant,
+}
+
+impl Recorder {
+    pub fn create(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        bincode::serialize_into(&mut writer, &crate::PROTOCOL_VERSION).map_err(into_io)?;
+
+        Ok(Self {
+            writer,
+            start: Instant::now(),
+        })
+    }
+
+    pub fn record_sent(&mut self, message: &Message) -> std::io::Result<()> {
+        self.record(RecordedRef::Sent(message))
+    }
+
+    pub fn record_received(&mut self, message: &ReturnMessage) -> std::io::Result<()> {
+        self.record(RecordedRef::Received(message))
+    }
+
+    fn record(&mut self, recorded: RecordedRef<'_>) -> std::io::Result<()> {
+        let entry = EntryRef {
+            time: self.start.elapsed().as_secs_f64(),
+            recorded,
+        };
+        bincode::serialize_into(&mut self.writer, &entry).map_err(into_io)?;
+        // Recordings are mostly useful when something went wrong, so don't sit on anything
+        self.writer.flush()
+    }
+}
+
+pub struct Reader {
+    reader: BufReader<File>,
+}
+
+impl Reader {
+    pub fn open(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let version: u32 = bincode::deserialize_from(&mut reader).map_err(into_io)?;
+        if version != crate::PROTOCOL_VERSION {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!(
+                    "recording uses protocol version {version}, but this screen speaks {}",
+                    crate::PROTOCOL_VERSION
+                ),
+            ));
+        }
+
+        Ok(Self { reader })
+    }
+
+    /// Reads the next entry, or returns `None` at the end of the recording.
+    pub fn next_entry(&mut self) -> std::io::Result<Option<Entry>> {
+        match bincode::deserialize_from(&mut self.reader).map_err(into_io) {
+            Ok(entry) => Ok(Some(entry)),
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
@@ -0,0 +1,516 @@
+// Copyright (C) 2023 Lily Lyons
+//
+// This file is part of libfm.
+//
+// libfm is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// libfm is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with libfm.  If not, see <http://www.gnu.org/licenses/>.
+
+use crate::clients::{ClientId, Clients, Request};
+use crate::{
+    effect, event_loop, frame, panel, plane, record, shader, shape, socket_loop, tilemap,
+    wgpu_state, wire,
+};
+use crate::{FrameConfig, Message, ReturnMessage, WindowConfig};
+
+use futures::prelude::*;
+use indexmap::IndexMap;
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+use interprocess::local_socket::tokio::{LocalSocketListener, LocalSocketStream};
+use tokio::sync::{
+    mpsc::{unbounded_channel, UnboundedSender},
+    Mutex,
+};
+use winit::event::Event;
+use winit::event_loop::{
+    ControlFlow, EventLoop, EventLoopBuilder, EventLoopProxy, EventLoopWindowTarget,
+};
+
+pub struct State {
+    pub(crate) windows: IndexMap<(ClientId, usize), Window>,
+    pub(crate) wgpu_state: wgpu_state::State,
+    pub(crate) frames: frame::Scheduler,
+    /// The client whose frame settings are in use, see [`ProtocolError::FramesOwned`].
+    pub(crate) frames_owner: Option<ClientId>,
+}
+
+pub(crate) struct Window {
+    pub(crate) window: winit::window::Window,
+    pub(crate) surface: wgpu_state::Surface,
+    pub(crate) sprites: IndexMap<usize, Sprite>,
+    pub(crate) planes: IndexMap<usize, Plane>,
+    pub(crate) tilemaps: IndexMap<usize, tilemap::Tilemap>,
+    pub(crate) panels: IndexMap<usize, panel::Panel>,
+    pub(crate) shapes: IndexMap<usize, shape::Shape>,
+    pub(crate) effects: effect::Chain,
+    pub(crate) shaders: IndexMap<usize, shader::Program>,
+    /// The shader run over the whole window, and where the scene is drawn for it.
+    pub(crate) shader: Option<usize>,
+    pub(crate) shader_pass: Option<shader::WindowPass>,
+    /// Set whenever something drawn on the window changes, so it gets redrawn.
+    pub(crate) sprites_dirty: bool,
+}
+
+pub(crate) struct Sprite {
+    pub(crate) x: i32,
+    pub(crate) y: i32,
+    pub(crate) z: i32,
+    pub(crate) image: Option<shader::Image>,
+    pub(crate) uniforms: shader::Uniforms,
+    pub(crate) shader: Option<usize>,
+}
+
+pub(crate) struct Plane {
+    pub(crate) config: crate::PlaneConfig,
+    pub(crate) image: Option<plane::Image>,
+}
+
+fn build_runtime() -> tokio::runtime::Runtime {
+    tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(2)
+        .enable_all()
+        .build()
+        .expect("failed to build runtime")
+}
+
+fn build_state(runtime: &tokio::runtime::Runtime) -> Arc<Mutex<State>> {
+    Arc::new(Mutex::new(State {
+        windows: IndexMap::new(),
+        wgpu_state: runtime.block_on(wgpu_state::State::new()),
+        frames: frame::Scheduler::new(FrameConfig::default()),
+        frames_owner: None,
+    }))
+}
+
+/// Connects to the client listening on `socket_addr` and runs the renderer until it hangs up.
+pub fn run(socket_addr: String) -> ! {
+    serve(false, |clients, proxy| async move {
+        let socket = LocalSocketStream::connect(socket_addr)
+            .await
+            .expect("failed to connect to socket");
+        let (reader, writer) = match handshake(socket).await {
+            Ok(streams) => streams,
+            // The client has been told why, so all that's left is to go away
+            Err(e) => {
+                eprintln!("rejected client: {e}");
+                std::process::exit(1);
+            }
+        };
+
+        let client = clients.connect(writer);
+        socket_loop::run(proxy, client, reader).await;
+    })
+}
+
+/// Listens on `socket_addr` and renders for every client that connects, until killed.
+///
+/// Each client has its own windows and sprites, which are cleaned up when it disconnects.
+pub fn run_server(socket_addr: String) -> ! {
+    serve(true, |clients, proxy| async move {
+        let listener = LocalSocketListener::bind(socket_addr).expect("failed to bind socket");
+
+        loop {
+            let socket = match listener.accept().await {
+                Ok(socket) => socket,
+                Err(e) => {
+                    eprintln!("failed to accept client: {e}");
+                    continue;
+                }
+            };
+
+            let clients = clients.clone();
+            let proxy = proxy.clone();
+            tokio::spawn(async move {
+                match handshake(socket).await {
+                    Ok((reader, writer)) => {
+                        let client = clients.connect(writer);
+                        socket_loop::run(proxy, client, reader).await;
+                    }
+                    Err(e) => eprintln!("rejected client: {e}"),
+                }
+            });
+        }
+    })
+}
+
+// Reads the client's handshake and sets up the encoding it asked for
+async fn handshake(
+    socket: LocalSocketStream,
+) -> Result<(wire::Reader<Message>, wire::Writer<ReturnMessage>), String> {
+    let (reader, mut writer) = socket.into_split();
+    let mut reader = futures::io::BufReader::new(reader);
+
+    let mut handshake = String::new();
+    reader
+        .read_line(&mut handshake)
+        .await
+        .map_err(|e| e.to_string())?;
+    let encoding = wire::Encoding::from_handshake(&handshake);
+    // The client is told either way, so it can report why it was turned down
+    let reply = wire::Encoding::handshake_reply(&encoding);
+    writer
+        .write_all(reply.as_bytes())
+        .await
+        .map_err(|e| e.to_string())?;
+    let encoding = encoding?;
+
+    Ok((encoding.reader(reader), encoding.writer(writer)))
+}
+
+/// Plays back what a client sent in a recording, `speed` times faster than it was recorded.
+///
+/// An infinite `speed` plays everything back as fast as it can be read. Closing a window stops
+/// the playback. Otherwise the last frame stays up until then, unless `exit_on_end` is set, in
+/// which case the screen exits as soon as the recording does.
+pub fn replay(path: &std::path::Path, speed: f64, exit_on_end: bool) -> ! {
+    let recording = record::Reader::open(path).expect("failed to open recording");
+
+    serve(false, move |clients, proxy| async move {
+        let start = tokio::time::Instant::now();
+        let playback = Playback {
+            recording,
+            ahead: VecDeque::new(),
+        };
+        let messages = stream::unfold(playback, move |mut playback| async move {
+            let (time, message) = playback.next()?;
+            // Clients remove everything on the way out, which would take the last frame with them
+            if !exit_on_end && is_teardown(&message) && playback.only_teardown_left() {
+                return None;
+            }
+
+            if speed.is_finite() {
+                let time = std::time::Duration::from_secs_f64(time / speed);
+                tokio::time::sleep_until(start + time).await;
+            }
+            Some((message, playback))
+        });
+        let hold_open = if exit_on_end {
+            stream::empty().left_stream()
+        } else {
+            stream::pending().right_stream()
+        };
+
+        let (closed_send, closed_recv) = futures::channel::oneshot::channel();
+        let replies = sink::unfold(Some(closed_send), |mut closed, message| async move {
+            if let ReturnMessage::CloseRequested(_) = message {
+                if let Some(closed) = closed.take() {
+                    let _ = closed.send(());
+                }
+            }
+            Ok::<_, std::convert::Infallible>(closed)
+        });
+
+        let client = clients.connect(Box::pin(replies));
+        let messages = messages.chain(hold_open).take_until(closed_recv);
+        socket_loop::run(proxy, client, Box::pin(messages)).await;
+    })
+}
+
+// What a client sent in a recording, read ahead as far as needed to tell whether anything but
+// teardown is left
+struct Playback {
+    recording: record::Reader,
+    ahead: VecDeque<(f64, Message)>,
+}
+
+impl Playback {
+    fn read(&mut self) -> Option<(f64, Message)> {
+        loop {
+            let entry = match self.recording.next_entry() {
+                Ok(Some(entry)) => entry,
+                Ok(None) => return None,
+                Err(e) => {
+                    eprintln!("failed to read recording: {e}");
+                    return None;
+                }
+            };
+            // What the screen sent back is only in there for reference
+            if let record::Recorded::Sent(message) = entry.recorded {
+                return Some((entry.time, message));
+            }
+        }
+    }
+
+    fn next(&mut self) -> Option<(f64, Message)> {
+        self.ahead.pop_front().or_else(|| self.read())
+    }
+
+    fn only_teardown_left(&mut self) -> bool {
+        if self.ahead.iter().any(|(_, message)| !is_teardown(message)) {
+            return false;
+        }
+        while let Some((time, message)) = self.read() {
+            let teardown = is_teardown(&message);
+            self.ahead.push_back((time, message));
+            if !teardown {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+// What a client sends while cleaning up after itself
+fn is_teardown(message: &Message) -> bool {
+    match message {
+        Message::DeleteWindow(_)
+        | Message::RemoveSprite(..)
+        | Message::RemovePlane(..)
+        | Message::RemoveTilemap(..)
+        | Message::RemovePanel(..)
+        | Message::RemoveShape(..)
+        | Message::RemoveShader(..)
+        | Message::Shutdown => true,
+        Message::Batch(messages) => messages.iter().all(is_teardown),
+        _ => false,
+    }
+}
+
+// Runs the renderer on this thread, with `accept` bringing in clients.
+// Unless this is a server, the renderer exits when the first client disconnects.
+fn serve<F, Fut>(server: bool, accept: F) -> !
+where
+    F: FnOnce(Clients, EventLoopProxy<Request>) -> Fut,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    let event_loop = EventLoopBuilder::with_user_event().build();
+    let proxy = event_loop.create_proxy();
+
+    let runtime = build_runtime();
+    let state = build_state(&runtime);
+    let clients = Clients::default();
+    let (event_send, event_recv) = unbounded_channel();
+
+    runtime.spawn(accept(clients.clone(), proxy));
+    runtime.spawn(event_loop::run(state.clone(), event_recv, clients));
+
+    event_loop.run(handle_event(state, event_send, server))
+}
+
+/// A renderer running on a thread inside the client's process.
+pub struct InProcess {
+    pub sender: futures::channel::mpsc::UnboundedSender<Message>,
+    pub receiver: futures::channel::mpsc::UnboundedReceiver<ReturnMessage>,
+    pub thread: std::thread::JoinHandle<()>,
+}
+
+/// Starts the renderer on a dedicated thread, talking over in-memory channels instead of a socket.
+///
+/// This is only possible on platforms where winit can run an event loop off the main thread.
+#[cfg(any(
+    target_os = "windows",
+    target_os = "linux",
+    target_os = "dragonfly",
+    target_os = "freebsd",
+    target_os = "netbsd",
+    target_os = "openbsd"
+))]
+pub fn spawn_in_process() -> Result<InProcess, String> {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use winit::platform::run_return::EventLoopExtRunReturn;
+
+    // winit only allows one event loop per process, even after the first one has exited
+    static IN_PROCESS_STARTED: AtomicBool = AtomicBool::new(false);
+    if IN_PROCESS_STARTED.swap(true, Ordering::SeqCst) {
+        return Err("only one in-process screen can be started per process".to_string());
+    }
+
+    let (message_send, message_recv) = futures::channel::mpsc::unbounded();
+    let (return_send, return_recv) = futures::channel::mpsc::unbounded();
+    let (startup_send, startup_recv) = std::sync::mpsc::channel();
+
+    let thread = std::thread::Builder::new()
+        .name("libfm-screen".to_string())
+        .spawn(move || {
+            // Setting up wgpu panics if there's no adapter, which we'd like to report instead
+            let setup = std::panic::catch_unwind(|| {
+                let mut builder = EventLoopBuilder::with_user_event();
+                allow_any_thread(&mut builder);
+                let event_loop: EventLoop<Request> = builder.build();
+
+                let runtime = build_runtime();
+                let state = build_state(&runtime);
+                (event_loop, runtime, state)
+            });
+            let (mut event_loop, runtime, state) = match setup {
+                Ok(setup) => setup,
+                Err(panic) => {
+                    let message = panic
+                        .downcast_ref::<&str>()
+                        .map(|s| s.to_string())
+                        .or_else(|| panic.downcast_ref::<String>().cloned())
+                        .unwrap_or_else(|| "renderer panicked during startup".to_string());
+                    let _ = startup_send.send(Err(message));
+                    return;
+                }
+            };
+            let _ = startup_send.send(Ok(()));
+
+            let proxy = event_loop.create_proxy();
+            let clients = Clients::default();
+            let (event_send, event_recv) = unbounded_channel();
+            let accept_clients = clients.clone();
+            runtime.spawn(async move {
+                let client = accept_clients.connect(return_send);
+                socket_loop::run(proxy, client, message_recv).await;
+            });
+            runtime.spawn(event_loop::run(state.clone(), event_recv, clients));
+
+            event_loop.run_return(handle_event(state, event_send, false));
+            // Dropping the runtime here cancels the tasks still waiting on the channels
+        })
+        .map_err(|e| e.to_string())?;
+
+    startup_recv
+        .recv()
+        .map_err(|_| "renderer thread exited during startup".to_string())??;
+
+    Ok(InProcess {
+        sender: message_send,
+        receiver: return_recv,
+        thread,
+    })
+}
+
+#[cfg(not(any(
+    target_os = "windows",
+    target_os = "linux",
+    target_os = "dragonfly",
+    target_os = "freebsd",
+    target_os = "netbsd",
+    target_os = "openbsd"
+)))]
+pub fn spawn_in_process() -> Result<InProcess, String> {
+    Err("the in-process screen is not supported on this platform".to_string())
+}
+
+#[cfg(target_os = "windows")]
+fn allow_any_thread(builder: &mut EventLoopBuilder<Request>) {
+    use winit::platform::windows::EventLoopBuilderExtWindows;
+    builder.with_any_thread(true);
+}
+
+#[cfg(any(
+    target_os = "linux",
+    target_os = "dragonfly",
+    target_os = "freebsd",
+    target_os = "netbsd",
+    target_os = "openbsd"
+))]
+fn allow_any_thread(builder: &mut EventLoopBuilder<Request>) {
+    // This is shared between the X11 and Wayland backends
+    use winit::platform::x11::EventLoopBuilderExtX11;
+    builder.with_any_thread(true);
+}
+
+fn handle_event(
+    state: Arc<Mutex<State>>,
+    event_send: UnboundedSender<Event<'static, Request>>,
+    server: bool,
+) -> impl FnMut(Event<'_, Request>, &EventLoopWindowTarget<Request>, &mut ControlFlow) + 'static {
+    move |event, target, c| {
+        let mut state = state.blocking_lock();
+        // Windows have to be built on the main thread, but the rest is left to the event loop
+        // task so they're set up in order with everything else the client sent
+        let event = match event {
+            Event::UserEvent(Request::Message(client, Message::CreateWindow(conf, id))) => {
+                Event::UserEvent(build_window(target, client, conf, id))
+            }
+            Event::UserEvent(Request::Message(client, Message::Batch(messages)))
+                if messages
+                    .iter()
+                    .any(|message| matches!(message, Message::CreateWindow(..))) =>
+            {
+                // Split the batch around each window, so what came before it is applied first
+                let mut requests = Vec::new();
+                let mut batch = Vec::new();
+                for message in messages {
+                    let Message::CreateWindow(conf, id) = message else {
+                        batch.push(message);
+                        continue;
+                    };
+                    if !batch.is_empty() {
+                        let messages = std::mem::take(&mut batch);
+                        requests.push(Request::Message(client, Message::Batch(messages)));
+                    }
+                    requests.push(build_window(target, client, conf, id));
+                }
+                if !batch.is_empty() {
+                    requests.push(Request::Message(client, Message::Batch(batch)));
+                }
+
+                let last = requests.pop().expect("the batch creates a window");
+                for request in requests {
+                    event_send
+                        .send(Event::UserEvent(request))
+                        .expect("failed to send event");
+                }
+                Event::UserEvent(last)
+            }
+            // A server outlives its clients, so for it this only means the client is done
+            Event::UserEvent(Request::Message(client, Message::Shutdown)) if server => {
+                Event::UserEvent(Request::Disconnected(client))
+            }
+            Event::UserEvent(Request::Message(_, Message::Shutdown)) if !server => {
+                c.set_exit();
+                return;
+            }
+            Event::UserEvent(Request::Disconnected(_)) if !server => {
+                c.set_exit();
+                return;
+            }
+            event => event,
+        };
+
+        // Frame boundaries are forwarded as MainEventsCleared, everything else is forwarded as is
+        let frame_boundary = matches!(event, Event::MainEventsCleared)
+            && state.frames.tick(std::time::Instant::now());
+
+        match state.frames.deadline() {
+            Some(deadline) => c.set_wait_until(deadline),
+            None => c.set_poll(),
+        }
+
+        if matches!(event, Event::MainEventsCleared) && !frame_boundary {
+            return;
+        }
+
+        if let Some(e) = event.to_static() {
+            event_send.send(e).expect("failed to send event");
+        }
+    }
+}
+
+/// Builds the window for a [`Message::CreateWindow`]. It starts hidden, and is shown once the
+/// event loop task has set it up.
+fn build_window(
+    target: &EventLoopWindowTarget<Request>,
+    client: ClientId,
+    conf: WindowConfig,
+    id: usize,
+) -> Request {
+    let mut builder = winit::window::WindowBuilder::new()
+        .with_visible(false)
+        .with_inner_size(winit::dpi::PhysicalSize::new(conf.size.0, conf.size.1))
+        .with_transparent(true)
+        .with_decorations(conf.decorations)
+        .with_resizable(false)
+        .with_title(&conf.title);
+    if let Some((x, y)) = conf.pos {
+        builder = builder.with_position(winit::dpi::LogicalPosition::new(x, y));
+    }
+    let window = builder.build(target).expect("failed to create window");
+    Request::WindowBuilt(client, id, conf, window)
+}
@@ -0,0 +1,511 @@
+// Copyright (C) 2023 Lily Lyons
+//
+// This file is part of libfm.
+//
+// libfm is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// libfm is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with libfm.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::HashMap;
+
+use crate::wgpu_state::Texture;
+use crate::SHADER_UNIFORMS;
+
+/// Declarations every shader is compiled with, documenting what shaders can use.
+pub const PRELUDE: &str = include_str!("prelude.wgsl");
+// What sprites without a shader of their own are drawn with
+const DEFAULT_SHADER: &str = include_str!("shader.wgsl");
+const ENTRY_POINT: &str = "fs_main";
+// The globals, the texture and the sampler
+const PRELUDE_BINDINGS: usize = 3;
+// Matches `Globals` in prelude.wgsl: the rect, the resolution, the time, padding and the
+// user uniforms
+const GLOBAL_FLOATS: usize = 8 + SHADER_UNIFORMS;
+
+/// Why a shader was rejected. Line numbers are counted from the start of the shader's own
+/// source, not the prelude.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShaderError(String);
+
+impl std::fmt::Display for ShaderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for ShaderError {}
+
+fn with_prelude(source: &str) -> String {
+    format!("{PRELUDE}\n{source}")
+}
+
+/// Checks that `source` compiles after the prelude and fits its interface.
+pub fn validate(source: &str) -> Result<(), ShaderError> {
+    let full = with_prelude(source);
+    // The prelude and the newline between it and the source
+    let offset = PRELUDE.lines().count() as u32 + 1;
+    let located = |location: Option<naga::SourceLocation>, message: String| match location {
+        Some(location) if location.line_number > offset => ShaderError(format!(
+            "line {}:{}: {message}",
+            location.line_number - offset,
+            location.line_position
+        )),
+        _ => ShaderError(message),
+    };
+
+    let module = naga::front::wgsl::parse_str(&full)
+        .map_err(|e| located(e.location(&full), e.message().to_string()))?;
+    naga::valid::Validator::new(
+        naga::valid::ValidationFlags::all(),
+        naga::valid::Capabilities::empty(),
+    )
+    .validate(&module)
+    .map_err(|e| located(e.location(&full), describe(e.as_inner())))?;
+
+    check_interface(&module)
+}
+
+// naga's errors only say what went wrong in full along with their sources
+fn describe(error: &dyn std::error::Error) -> String {
+    let mut message = error.to_string();
+    let mut source = error.source();
+    while let Some(error) = source {
+        message = format!("{message}: {error}");
+        source = error.source();
+    }
+    message
+}
+
+// Anything that compiles but doesn't fit the pipelines shaders are drawn with
+fn check_interface(module: &naga::Module) -> Result<(), ShaderError> {
+    let error = |message: &str| Err(ShaderError(message.to_string()));
+
+    let bindings = module
+        .global_variables
+        .iter()
+        .filter(|(_, variable)| variable.binding.is_some())
+        .count();
+    if bindings != PRELUDE_BINDINGS {
+        return error("shaders can't declare bindings of their own");
+    }
+
+    let Some(entry_point) = module
+        .entry_points
+        .iter()
+        .find(|entry_point| entry_point.name == ENTRY_POINT)
+    else {
+        return error("shaders must define fs_main");
+    };
+    if entry_point.stage != naga::ShaderStage::Fragment {
+        return error("fs_main must be a @fragment entry point");
+    }
+
+    let returns_color = entry_point.function.result.as_ref().is_some_and(|result| {
+        matches!(
+            result.binding,
+            Some(naga::Binding::Location { location: 0, .. })
+        ) && matches!(
+            module.types[result.ty].inner,
+            naga::TypeInner::Vector {
+                size: naga::VectorSize::Quad,
+                kind: naga::ScalarKind::Float,
+                ..
+            }
+        )
+    });
+    if !returns_color {
+        return error("fs_main must return a vec4<f32> at @location(0)");
+    }
+
+    // Only what vs_main outputs can be passed in, so at most the uv at location 0
+    let mut locations = entry_point.function.arguments.iter().flat_map(|argument| {
+        match module.types[argument.ty].inner {
+            naga::TypeInner::Struct { ref members, .. } if argument.binding.is_none() => members
+                .iter()
+                .map(|member| member.binding.clone())
+                .collect(),
+            _ => vec![argument.binding.clone()],
+        }
+    });
+    if locations.any(|binding| matches!(binding, Some(naga::Binding::Location { location, .. }) if location != 0))
+    {
+        return error("fs_main can only take the prelude's VertexOutput");
+    }
+
+    Ok(())
+}
+
+/// A compiled shader, ready to draw sprites or whole windows with.
+pub struct Program {
+    // Sprites are blended over the scene, while window passes replace it
+    sprite: wgpu::RenderPipeline,
+    window: wgpu::RenderPipeline,
+    pub uniforms: [f32; SHADER_UNIFORMS],
+}
+
+/// A texture shaders can sample, along with its bind group.
+pub struct Image {
+    texture: Texture,
+    bind_group: wgpu::BindGroup,
+}
+
+impl Image {
+    pub fn size(&self) -> (u32, u32) {
+        self.texture.size()
+    }
+
+    pub fn memory(&self) -> u64 {
+        self.texture.memory()
+    }
+}
+
+/// The globals a sprite or window pass is drawn with.
+pub struct Uniforms {
+    buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+}
+
+impl Uniforms {
+    fn write(
+        &self,
+        queue: &wgpu::Queue,
+        rect: [f32; 4],
+        target: (u32, u32),
+        frame: u64,
+        program: &Program,
+    ) {
+        let mut floats = [0.0; GLOBAL_FLOATS];
+        floats[..4].copy_from_slice(&rect);
+        floats[4] = target.0 as f32;
+        floats[5] = target.1 as f32;
+        // Wrapped so it stays precise enough for the shaders
+        floats[6] = (frame % 1_000_000) as f32;
+        floats[8..].copy_from_slice(&program.uniforms);
+
+        let bytes: Vec<u8> = floats.iter().flat_map(|f| f.to_ne_bytes()).collect();
+        queue.write_buffer(&self.buffer, 0, &bytes);
+    }
+}
+
+/// An offscreen copy of a window's scene, for a shader to run over.
+pub struct WindowPass {
+    size: (u32, u32),
+    texture: wgpu::Texture,
+    view: wgpu::TextureView,
+    bind_group: wgpu::BindGroup,
+    uniforms: Uniforms,
+}
+
+impl WindowPass {
+    pub fn view(&self) -> &wgpu::TextureView {
+        &self.view
+    }
+
+    pub fn memory(&self) -> u64 {
+        let size = self.texture.size();
+        size.width as u64 * size.height as u64 * 4
+    }
+}
+
+/// Compiles shaders and draws with them. Every surface format gets its own default program.
+pub struct Renderer {
+    globals_layout: wgpu::BindGroupLayout,
+    texture_layout: wgpu::BindGroupLayout,
+    layout: wgpu::PipelineLayout,
+    // Window passes sample the scene with this
+    sampler: wgpu::Sampler,
+    defaults: HashMap<wgpu::TextureFormat, Program>,
+}
+
+impl Renderer {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let globals_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("shader globals bind group layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+        let texture_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("shader texture bind group layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("shader pipeline layout"),
+            bind_group_layouts: &[&globals_layout, &texture_layout],
+            push_constant_ranges: &[],
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        Self {
+            globals_layout,
+            texture_layout,
+            layout,
+            sampler,
+            defaults: HashMap::new(),
+        }
+    }
+
+    /// Builds the default program for drawing to `format`, unless it already exists.
+    pub fn prepare(&mut self, device: &wgpu::Device, format: wgpu::TextureFormat) {
+        if self.defaults.contains_key(&format) {
+            return;
+        }
+
+        let program = self
+            .create_program(device, DEFAULT_SHADER, format)
+            .expect("the default shader is invalid");
+        self.defaults.insert(format, program);
+    }
+
+    pub fn default_program(&self, format: wgpu::TextureFormat) -> &Program {
+        &self.defaults[&format]
+    }
+
+    /// Validates and compiles `source` for drawing to `format`.
+    pub fn create_program(
+        &self,
+        device: &wgpu::Device,
+        source: &str,
+        format: wgpu::TextureFormat,
+    ) -> Result<Program, ShaderError> {
+        validate(source)?;
+
+        // Validation should catch everything, but wgpu panics on errors that aren't caught
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("user shader"),
+            source: wgpu::ShaderSource::Wgsl(with_prelude(source).into()),
+        });
+        let pipeline = |blend| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("user shader pipeline"),
+                layout: Some(&self.layout),
+                vertex: wgpu::VertexState {
+                    module: &module,
+                    entry_point: "vs_main",
+                    buffers: &[],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &module,
+                    entry_point: ENTRY_POINT,
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format,
+                        blend,
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                }),
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+            })
+        };
+        let sprite = pipeline(Some(wgpu::BlendState::ALPHA_BLENDING));
+        let window = pipeline(None);
+        if let Some(error) = futures::executor::block_on(device.pop_error_scope()) {
+            return Err(ShaderError(error.to_string()));
+        }
+
+        Ok(Program {
+            sprite,
+            window,
+            uniforms: [0.0; SHADER_UNIFORMS],
+        })
+    }
+
+    pub fn create_image(&self, device: &wgpu::Device, texture: Texture) -> Image {
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("shader texture bind group"),
+            layout: &self.texture_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&texture.sampler),
+                },
+            ],
+        });
+
+        Image {
+            texture,
+            bind_group,
+        }
+    }
+
+    pub fn create_uniforms(&self, device: &wgpu::Device) -> Uniforms {
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("shader globals"),
+            size: (GLOBAL_FLOATS * std::mem::size_of::<f32>()) as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("shader globals bind group"),
+            layout: &self.globals_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+        });
+
+        Uniforms { buffer, bind_group }
+    }
+
+    /// Makes sure `pass` has a `size` target of `format` if `enabled`, or drops it if not.
+    pub fn prepare_pass(
+        &self,
+        device: &wgpu::Device,
+        pass: &mut Option<WindowPass>,
+        format: wgpu::TextureFormat,
+        size: (u32, u32),
+        enabled: bool,
+    ) {
+        if !enabled {
+            *pass = None;
+            return;
+        }
+        if pass.as_ref().is_some_and(|pass| pass.size == size) {
+            return;
+        }
+
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("shader pass target"),
+            size: wgpu::Extent3d {
+                width: size.0,
+                height: size.1,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("shader pass bind group"),
+            layout: &self.texture_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&self.sampler),
+                },
+            ],
+        });
+
+        *pass = Some(WindowPass {
+            size,
+            texture,
+            view,
+            bind_group,
+            uniforms: self.create_uniforms(device),
+        });
+    }
+
+    /// Draws `image` at `position` with `program`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn draw_sprite<'pass>(
+        &'pass self,
+        pass: &mut wgpu::RenderPass<'pass>,
+        queue: &wgpu::Queue,
+        target: (u32, u32),
+        frame: u64,
+        program: &'pass Program,
+        uniforms: &'pass Uniforms,
+        image: &'pass Image,
+        position: (i32, i32),
+    ) {
+        let (width, height) = image.size();
+        let rect = [
+            position.0 as f32,
+            position.1 as f32,
+            width as f32,
+            height as f32,
+        ];
+        uniforms.write(queue, rect, target, frame, program);
+
+        pass.set_pipeline(&program.sprite);
+        pass.set_bind_group(0, &uniforms.bind_group, &[]);
+        pass.set_bind_group(1, &image.bind_group, &[]);
+        pass.draw(0..6, 0..1);
+    }
+
+    /// Runs `program` over the scene drawn into `pass`, drawing the result to `output`.
+    pub fn run_pass(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        queue: &wgpu::Queue,
+        frame: u64,
+        program: &Program,
+        pass: &WindowPass,
+        output: &wgpu::TextureView,
+    ) {
+        let (width, height) = pass.size;
+        let rect = [0.0, 0.0, width as f32, height as f32];
+        pass.uniforms.write(queue, rect, pass.size, frame, program);
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("shader pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: output,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(&program.window);
+        render_pass.set_bind_group(0, &pass.uniforms.bind_group, &[]);
+        render_pass.set_bind_group(1, &pass.bind_group, &[]);
+        render_pass.draw(0..6, 0..1);
+    }
+}
@@ -1,21 +1,7 @@
-// Vertex shader
-struct VertexOutput {
-    @builtin(position) clip_position: vec4<f32>,
-};
+// The shader sprites are drawn with unless they have their own. Like any other shader, it's
+// compiled after prelude.wgsl
 
-@vertex
-fn vs_main(
-    @builtin(vertex_index) in_vertex_index: u32,
-) -> VertexOutput {
-    var out: VertexOutput;
-    let x = f32(1 - i32(in_vertex_index)) * 0.5;
-    let y = f32(i32(in_vertex_index & 1u) * 2 - 1) * 0.5;
-    out.clip_position = vec4<f32>(x, y, 0.0, 1.0);
-    return out;
-}
-
-// Fragment shader
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    return vec4<f32>(0.3, 0.2, 0.1, 1.0);
+    return textureSample(t_source, s_source, in.uv);
 }
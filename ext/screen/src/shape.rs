@@ -0,0 +1,384 @@
+// Copyright (C) 2023 Lily Lyons
+//
+// This file is part of libfm.
+//
+// libfm is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// libfm is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with libfm.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::HashMap;
+use std::f32::consts::TAU;
+
+use wgpu::util::DeviceExt;
+
+use crate::{Rect, ShapeConfig, ShapeGeometry};
+
+// Matches `Uniforms` in shape.wgsl, padded out to a multiple of 16 bytes
+const UNIFORM_SIZE: u64 = 16;
+// Matches `VertexInput` in shape.wgsl: a position and a color
+const VERTEX_FLOATS: usize = 6;
+// Circles get a segment for about every this many pixels of their circumference
+const SEGMENT_LENGTH: f32 = 4.0;
+const MIN_SEGMENTS: usize = 12;
+const MAX_SEGMENTS: usize = 256;
+
+/// The pipelines shapes are drawn with, one for each surface format.
+pub struct Renderer {
+    shader: wgpu::ShaderModule,
+    bind_group_layout: wgpu::BindGroupLayout,
+    layout: wgpu::PipelineLayout,
+    pipelines: HashMap<wgpu::TextureFormat, wgpu::RenderPipeline>,
+}
+
+impl Renderer {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let shader = device.create_shader_module(wgpu::include_wgsl!("shape.wgsl"));
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("shape bind group layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("shape pipeline layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        Self {
+            shader,
+            bind_group_layout,
+            layout,
+            pipelines: HashMap::new(),
+        }
+    }
+
+    /// Builds the pipeline for drawing to `format`, unless it already exists.
+    pub fn prepare(&mut self, device: &wgpu::Device, format: wgpu::TextureFormat) {
+        if self.pipelines.contains_key(&format) {
+            return;
+        }
+
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("shape pipeline"),
+            layout: Some(&self.layout),
+            vertex: wgpu::VertexState {
+                module: &self.shader,
+                entry_point: "vs_main",
+                buffers: &[wgpu::VertexBufferLayout {
+                    array_stride: (VERTEX_FLOATS * std::mem::size_of::<f32>()) as u64,
+                    step_mode: wgpu::VertexStepMode::Vertex,
+                    attributes: &wgpu::vertex_attr_array![0 => Float32x2, 1 => Float32x4],
+                }],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &self.shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+        self.pipelines.insert(format, pipeline);
+    }
+
+    /// Returns how many draw calls it took, which is none for a shape without geometry.
+    pub fn draw<'pass>(
+        &'pass self,
+        pass: &mut wgpu::RenderPass<'pass>,
+        format: wgpu::TextureFormat,
+        shape: &'pass Shape,
+    ) -> u32 {
+        let Some((ref buffer, count)) = shape.vertices else {
+            return 0;
+        };
+
+        pass.set_pipeline(&self.pipelines[&format]);
+        pass.set_bind_group(0, &shape.bind_group, &[]);
+        pass.set_vertex_buffer(0, buffer.slice(..));
+        pass.draw(0..count, 0..1);
+        1
+    }
+}
+
+pub struct Shape {
+    pub config: ShapeConfig,
+    geometry: Option<ShapeGeometry>,
+    uniforms: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    vertices: Option<(wgpu::Buffer, u32)>,
+}
+
+impl Shape {
+    pub fn new(device: &wgpu::Device, renderer: &Renderer) -> Self {
+        let uniforms = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("shape uniforms"),
+            size: UNIFORM_SIZE,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("shape bind group"),
+            layout: &renderer.bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniforms.as_entire_binding(),
+            }],
+        });
+
+        Self {
+            config: ShapeConfig::default(),
+            geometry: None,
+            uniforms,
+            bind_group,
+            vertices: None,
+        }
+    }
+
+    pub fn set_geometry(&mut self, device: &wgpu::Device, geometry: ShapeGeometry) {
+        self.geometry = Some(geometry);
+        self.rebuild(device);
+    }
+
+    pub fn configure(&mut self, device: &wgpu::Device, config: ShapeConfig) {
+        self.config = config;
+        self.rebuild(device);
+    }
+
+    /// Updates the uniforms for drawing to a `target` sized surface of `format`.
+    pub fn prepare(&self, queue: &wgpu::Queue, target: (u32, u32), format: wgpu::TextureFormat) {
+        let srgb = if format.is_srgb() { 1.0 } else { 0.0 };
+        let floats = [target.0 as f32, target.1 as f32, srgb, 0.0];
+        let bytes: Vec<u8> = floats.iter().flat_map(|f| f.to_ne_bytes()).collect();
+        queue.write_buffer(&self.uniforms, 0, &bytes);
+    }
+
+    // Shapes are small, so they're tessellated again whenever anything about them changes
+    fn rebuild(&mut self, device: &wgpu::Device) {
+        let Some(geometry) = &self.geometry else {
+            return;
+        };
+
+        let color = self.config.color;
+        let mut triangles = Triangles {
+            color: [
+                color.red / 255.0,
+                color.green / 255.0,
+                color.blue / 255.0,
+                color.alpha / 255.0,
+            ],
+            vertices: Vec::new(),
+        };
+        triangles.push_geometry(geometry, self.config.filled, self.config.line_width);
+
+        self.vertices = (!triangles.vertices.is_empty()).then(|| {
+            let bytes: Vec<u8> = triangles
+                .vertices
+                .iter()
+                .flat_map(|f| f.to_ne_bytes())
+                .collect();
+            let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("shape vertices"),
+                contents: &bytes,
+                usage: wgpu::BufferUsages::VERTEX,
+            });
+            (buffer, (triangles.vertices.len() / VERTEX_FLOATS) as u32)
+        });
+    }
+}
+
+/// Solid colored triangles, as vertices for shape.wgsl.
+struct Triangles {
+    color: [f32; 4],
+    vertices: Vec<f32>,
+}
+
+impl Triangles {
+    fn push_geometry(&mut self, geometry: &ShapeGeometry, filled: bool, line_width: f32) {
+        match *geometry {
+            ShapeGeometry::Rect(rect) if filled => self.rect(rect),
+            ShapeGeometry::Rect(rect) => self.rect_outline(rect, line_width),
+            ShapeGeometry::Line { from, to } => self.line(from, to, line_width, false),
+            ShapeGeometry::Circle { center, radius } if filled => self.circle(center, radius),
+            ShapeGeometry::Circle { center, radius } => {
+                self.ring(center, radius, radius - line_width)
+            }
+            ShapeGeometry::Polygon(ref points) if filled => self.polygon(points),
+            ShapeGeometry::Polygon(ref points) => self.polygon_outline(points, line_width),
+        }
+    }
+
+    fn triangle(&mut self, points: [(f32, f32); 3]) {
+        for (x, y) in points {
+            self.vertices.extend_from_slice(&[x, y]);
+            self.vertices.extend_from_slice(&self.color);
+        }
+    }
+
+    fn quad(&mut self, [a, b, c, d]: [(f32, f32); 4]) {
+        self.triangle([a, b, c]);
+        self.triangle([a, c, d]);
+    }
+
+    fn rect(&mut self, rect: Rect) {
+        if rect.width <= 0 || rect.height <= 0 {
+            return;
+        }
+
+        let (x, y) = (rect.x as f32, rect.y as f32);
+        let (x2, y2) = (x + rect.width as f32, y + rect.height as f32);
+        self.quad([(x, y), (x2, y), (x2, y2), (x, y2)]);
+    }
+
+    // The outline is drawn inside the rect, so it covers the same pixels a filled rect would
+    fn rect_outline(&mut self, rect: Rect, line_width: f32) {
+        let Rect {
+            x,
+            y,
+            width,
+            height,
+        } = rect;
+        let line = (line_width.round() as i32).clamp(1, width.min(height).max(1));
+        if width <= line * 2 || height <= line * 2 {
+            return self.rect(rect);
+        }
+
+        let inner_height = height - line * 2;
+        self.rect(Rect {
+            x,
+            y,
+            width,
+            height: line,
+        });
+        self.rect(Rect {
+            x,
+            y: y + height - line,
+            width,
+            height: line,
+        });
+        self.rect(Rect {
+            x,
+            y: y + line,
+            width: line,
+            height: inner_height,
+        });
+        self.rect(Rect {
+            x: x + width - line,
+            y: y + line,
+            width: line,
+            height: inner_height,
+        });
+    }
+
+    /// A `width` wide line, extended by half its width at both ends if `capped`.
+    fn line(&mut self, from: (f32, f32), to: (f32, f32), width: f32, capped: bool) {
+        let (dx, dy) = (to.0 - from.0, to.1 - from.1);
+        let length = dx.hypot(dy);
+        if length <= f32::EPSILON || width <= 0.0 {
+            return;
+        }
+
+        let half = width / 2.0;
+        let (ux, uy) = (dx / length, dy / length);
+        let (nx, ny) = (-uy * half, ux * half);
+        let (cx, cy) = if capped {
+            (ux * half, uy * half)
+        } else {
+            (0.0, 0.0)
+        };
+        let (from, to) = ((from.0 - cx, from.1 - cy), (to.0 + cx, to.1 + cy));
+
+        self.quad([
+            (from.0 + nx, from.1 + ny),
+            (to.0 + nx, to.1 + ny),
+            (to.0 - nx, to.1 - ny),
+            (from.0 - nx, from.1 - ny),
+        ]);
+    }
+
+    fn circle(&mut self, center: (f32, f32), radius: f32) {
+        let points = circle_points(center, radius);
+        self.polygon(&points);
+    }
+
+    fn ring(&mut self, center: (f32, f32), outer: f32, inner: f32) {
+        if inner <= 0.0 {
+            return self.circle(center, outer);
+        }
+
+        let outer_points = circle_points(center, outer);
+        let inner_points = circle_points_with(center, inner, outer_points.len());
+        for i in 0..outer_points.len() {
+            let next = (i + 1) % outer_points.len();
+            self.quad([
+                outer_points[i],
+                outer_points[next],
+                inner_points[next],
+                inner_points[i],
+            ]);
+        }
+    }
+
+    // A fan from the first point, which only covers the polygon if it's convex
+    fn polygon(&mut self, points: &[(f32, f32)]) {
+        let Some((&first, rest)) = points.split_first() else {
+            return;
+        };
+
+        for pair in rest.windows(2) {
+            self.triangle([first, pair[0], pair[1]]);
+        }
+    }
+
+    fn polygon_outline(&mut self, points: &[(f32, f32)], line_width: f32) {
+        // Capping every edge fills in the corners between them
+        for i in 0..points.len() {
+            let next = (i + 1) % points.len();
+            self.line(points[i], points[next], line_width, true);
+        }
+    }
+}
+
+fn circle_points(center: (f32, f32), radius: f32) -> Vec<(f32, f32)> {
+    let segments =
+        ((radius * TAU / SEGMENT_LENGTH).ceil() as usize).clamp(MIN_SEGMENTS, MAX_SEGMENTS);
+    circle_points_with(center, radius, segments)
+}
+
+fn circle_points_with(center: (f32, f32), radius: f32, segments: usize) -> Vec<(f32, f32)> {
+    if radius <= 0.0 {
+        return Vec::new();
+    }
+
+    (0..segments)
+        .map(|i| {
+            let angle = i as f32 / segments as f32 * TAU;
+            (
+                center.0 + angle.cos() * radius,
+                center.1 + angle.sin() * radius,
+            )
+        })
+        .collect()
+}
@@ -0,0 +1,45 @@
+// Draws solid colored triangles, positioned in target pixels
+
+struct Uniforms {
+    target_size: vec2<f32>,
+    // 1.0 if the target is sRGB, so colors need converting to linear before they're written
+    srgb_target: f32,
+};
+
+@group(0) @binding(0)
+var<uniform> shape: Uniforms;
+
+struct VertexInput {
+    @location(0) position: vec2<f32>,
+    // sRGB, with every component between 0 and 1
+    @location(1) color: vec4<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) color: vec4<f32>,
+};
+
+fn to_linear(color: vec3<f32>) -> vec3<f32> {
+    let low = color / 12.92;
+    let high = pow((color + 0.055) / 1.055, vec3<f32>(2.4));
+    return select(high, low, color <= vec3<f32>(0.04045));
+}
+
+@vertex
+fn vs_main(in: VertexInput) -> VertexOutput {
+    let position = in.position / shape.target_size;
+
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(position.x * 2.0 - 1.0, 1.0 - position.y * 2.0, 0.0, 1.0);
+    out.color = in.color;
+    if shape.srgb_target > 0.5 {
+        out.color = vec4<f32>(to_linear(in.color.rgb), in.color.a);
+    }
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return in.color;
+}
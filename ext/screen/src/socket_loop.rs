@@ -15,28 +15,22 @@
 // You should have received a copy of the GNU General Public License
 // along with libfm.  If not, see <http://www.gnu.org/licenses/>.
 
-use async_bincode::futures::AsyncBincodeReader;
+use crate::clients::{ClientId, Request};
+use crate::Message;
 use futures::prelude::*;
-use screen::Message;
 use winit::event_loop::EventLoopProxy;
 
-pub async fn run(proxy: EventLoopProxy<Message>, reader: impl AsyncRead + Unpin) -> ! {
-    let mut stream = AsyncBincodeReader::from(reader);
-
-    // if let Err(e) = reader.read_line(&mut buf).await {
-    //     eprintln!("error reading socket buffer: {e:?}")
-    // }
-    // let Ok(message) = ron::from_str::<Message>(&buf) else {
-    //     eprintln!("error reading message");
-    //
-    //     continue;
-    // };
-
-    while let Some(Ok(message)) = stream.next().await {
+pub async fn run(
+    proxy: EventLoopProxy<Request>,
+    client: ClientId,
+    mut stream: impl Stream<Item = Message> + Unpin,
+) {
+    while let Some(message) = stream.next().await {
         proxy
-            .send_event(message)
+            .send_event(Request::Message(client, message))
             .expect("failed to send message to event loop");
     }
 
-    panic!("socket processing finished");
+    // If the event loop is already gone there's nobody to tell
+    let _ = proxy.send_event(Request::Disconnected(client));
 }
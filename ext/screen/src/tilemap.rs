@@ -0,0 +1,446 @@
+// Copyright (C) 2023 Lily Lyons
+//
+// This file is part of libfm.
+//
+// libfm is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// libfm is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with libfm.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ops::Range;
+
+use wgpu::util::DeviceExt;
+
+use crate::wgpu_state::Texture;
+use crate::{TileData, TileInfo, TileUpdate, TilemapConfig};
+
+// Tiles are uploaded in square chunks of this many tiles, so changing a tile only rebuilds its chunk
+const CHUNK_SIZE: u32 = 16;
+// Matches `Uniforms` in tilemap.wgsl, padded out to a multiple of 16 bytes
+const UNIFORM_SIZE: u64 = 48;
+
+/// The pipelines tilemaps are drawn with, one for each surface format.
+pub struct Renderer {
+    shader: wgpu::ShaderModule,
+    bind_group_layout: wgpu::BindGroupLayout,
+    layout: wgpu::PipelineLayout,
+    pipelines: HashMap<wgpu::TextureFormat, wgpu::RenderPipeline>,
+}
+
+impl Renderer {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let shader = device.create_shader_module(wgpu::include_wgsl!("tilemap.wgsl"));
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("tilemap bind group layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("tilemap pipeline layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        Self {
+            shader,
+            bind_group_layout,
+            layout,
+            pipelines: HashMap::new(),
+        }
+    }
+
+    /// Builds the pipeline for drawing to `format`, unless it already exists.
+    pub fn prepare(&mut self, device: &wgpu::Device, format: wgpu::TextureFormat) {
+        if self.pipelines.contains_key(&format) {
+            return;
+        }
+
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("tilemap pipeline"),
+            layout: Some(&self.layout),
+            vertex: wgpu::VertexState {
+                module: &self.shader,
+                entry_point: "vs_main",
+                // Every tile is an instance of a quad
+                buffers: &[wgpu::VertexBufferLayout {
+                    array_stride: std::mem::size_of::<[u32; 4]>() as u64,
+                    step_mode: wgpu::VertexStepMode::Instance,
+                    attributes: &wgpu::vertex_attr_array![0 => Uint32x4],
+                }],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &self.shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+        self.pipelines.insert(format, pipeline);
+    }
+
+    pub fn draw<'pass>(
+        &'pass self,
+        pass: &mut wgpu::RenderPass<'pass>,
+        format: wgpu::TextureFormat,
+        tileset: &'pass Tileset,
+        chunk: &'pass Chunk,
+        group: &Group,
+    ) {
+        pass.set_pipeline(&self.pipelines[&format]);
+        pass.set_bind_group(0, &tileset.bind_group, &[]);
+        pass.set_vertex_buffer(0, chunk.buffer.slice(..));
+        pass.draw(0..6, group.instances.clone());
+    }
+}
+
+/// Where a group of tiles sits among everything else drawn on the window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Layer {
+    Ground,
+    Row { row: u32, priority: u8 },
+}
+
+/// Tiles in a chunk that are drawn at the same z.
+pub struct Group {
+    pub layer: Layer,
+    instances: Range<u32>,
+}
+
+pub struct Chunk {
+    // Chunk coordinates, in chunks
+    position: (u32, u32),
+    buffer: wgpu::Buffer,
+    pub groups: Vec<Group>,
+}
+
+pub struct Tileset {
+    texture: Texture,
+    bind_group: wgpu::BindGroup,
+}
+
+/// Counts off the frames between steps of tile animations.
+#[derive(Debug, Default)]
+struct AnimationClock {
+    // The frame the animation last stepped on
+    last_step: u64,
+}
+
+impl AnimationClock {
+    /// Whether a step is due by `frame`. Catches up on every step since the last one, so a frame
+    /// counter that skips ahead doesn't skip a step.
+    fn advance(&mut self, frame: u64, interval: u64) -> bool {
+        let elapsed = frame.saturating_sub(self.last_step);
+        if elapsed < interval {
+            return false;
+        }
+        self.last_step += elapsed / interval * interval;
+        true
+    }
+}
+
+pub struct Tilemap {
+    pub config: TilemapConfig,
+    clock: AnimationClock,
+    data: TileData,
+    // Indexed by tile id
+    info: Vec<TileInfo>,
+    uniforms: wgpu::Buffer,
+    pub tileset: Option<Tileset>,
+    pub chunks: HashMap<(u32, u32), Chunk>,
+}
+
+impl Tilemap {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let uniforms = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("tilemap uniforms"),
+            size: UNIFORM_SIZE,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        Self {
+            config: TilemapConfig::default(),
+            clock: AnimationClock::default(),
+            data: TileData::default(),
+            info: Vec::new(),
+            uniforms,
+            tileset: None,
+            chunks: HashMap::new(),
+        }
+    }
+
+    pub fn set_tileset(&mut self, device: &wgpu::Device, renderer: &Renderer, texture: Texture) {
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("tilemap bind group"),
+            layout: &renderer.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&texture.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: self.uniforms.as_entire_binding(),
+                },
+            ],
+        });
+
+        self.tileset = Some(Tileset {
+            texture,
+            bind_group,
+        });
+    }
+
+    /// Replaces the whole map. Returns false if its size doesn't match its tiles.
+    pub fn set_data(&mut self, device: &wgpu::Device, data: TileData) -> bool {
+        if data.tiles.len() != (data.width * data.height * data.layers) as usize {
+            return false;
+        }
+
+        self.data = data;
+        self.rebuild_all(device);
+        true
+    }
+
+    /// Changes a few tiles, only rebuilding the chunks they are in.
+    /// Returns false if any of them were out of bounds, after applying the rest.
+    pub fn set_tiles(&mut self, device: &wgpu::Device, updates: Vec<TileUpdate>) -> bool {
+        let mut in_bounds = true;
+        let mut dirty = HashSet::new();
+        for TileUpdate { x, y, layer, tile } in updates {
+            let Some(index) = self.data.index(x, y, layer) else {
+                in_bounds = false;
+                continue;
+            };
+            self.data.tiles[index] = tile;
+            dirty.insert((x / CHUNK_SIZE, y / CHUNK_SIZE));
+        }
+
+        for position in dirty {
+            self.rebuild(device, position);
+        }
+        in_bounds
+    }
+
+    pub fn set_info(&mut self, device: &wgpu::Device, updates: Vec<(u16, TileInfo)>) {
+        for (tile, info) in updates {
+            let tile = tile as usize;
+            if tile >= self.info.len() {
+                self.info.resize(tile + 1, TileInfo::default());
+            }
+            self.info[tile] = info;
+        }
+
+        // Priorities decide how tiles are grouped, so every chunk could have changed
+        self.rebuild_all(device);
+    }
+
+    pub fn is_animated(&self) -> bool {
+        self.info.iter().any(|info| info.frames > 1)
+    }
+
+    /// Whether the animated tiles have moved on to their next frame by `frame`, and need a redraw.
+    pub fn advance_animation(&mut self, frame: u64) -> bool {
+        let interval = self.config.anim_interval.max(1) as u64;
+        let stepped = self.clock.advance(frame, interval);
+        stepped && self.config.visible && self.is_animated()
+    }
+
+    pub fn memory(&self) -> u64 {
+        self.tileset
+            .as_ref()
+            .map_or(0, |tileset| tileset.texture.memory())
+    }
+
+    /// The z a group of tiles is drawn at.
+    pub fn z(&self, layer: Layer) -> i32 {
+        match layer {
+            Layer::Ground => self.config.z,
+            Layer::Row { row, priority } => {
+                ((row + priority as u32 + 1) * self.config.tile_size) as i32 - self.config.oy
+            }
+        }
+    }
+
+    /// Whether any of `chunk` is within a `target` sized window.
+    pub fn is_chunk_visible(&self, chunk: &Chunk, target: (u32, u32)) -> bool {
+        let span = (CHUNK_SIZE * self.config.tile_size) as i32;
+        let x = chunk.position.0 as i32 * span - self.config.ox;
+        let y = chunk.position.1 as i32 * span - self.config.oy;
+
+        x < target.0 as i32 && x + span > 0 && y < target.1 as i32 && y + span > 0
+    }
+
+    /// Updates the uniforms every draw of this tilemap uses, before drawing to a `target` sized
+    /// surface on `frame`.
+    pub fn prepare(&self, queue: &wgpu::Queue, target: (u32, u32), frame: u64) {
+        let Some(ref tileset) = self.tileset else {
+            return;
+        };
+        let (texture_width, texture_height) = tileset.texture.size();
+        let tile_size = self.config.tile_size.max(1);
+        let anim_frame = frame / self.config.anim_interval.max(1) as u64;
+
+        let mut data = Vec::with_capacity(UNIFORM_SIZE as usize);
+        for value in [
+            self.config.ox as f32,
+            self.config.oy as f32,
+            target.0 as f32,
+            target.1 as f32,
+            texture_width as f32,
+            texture_height as f32,
+            tile_size as f32,
+        ] {
+            data.extend_from_slice(&value.to_ne_bytes());
+        }
+        for value in [(texture_width / tile_size).max(1), anim_frame as u32] {
+            data.extend_from_slice(&value.to_ne_bytes());
+        }
+        data.resize(UNIFORM_SIZE as usize, 0);
+
+        queue.write_buffer(&self.uniforms, 0, &data);
+    }
+
+    fn rebuild_all(&mut self, device: &wgpu::Device) {
+        self.chunks.clear();
+        for y in 0..self.data.height.div_ceil(CHUNK_SIZE) {
+            for x in 0..self.data.width.div_ceil(CHUNK_SIZE) {
+                self.rebuild(device, (x, y));
+            }
+        }
+    }
+
+    fn rebuild(&mut self, device: &wgpu::Device, position: (u32, u32)) {
+        // Layers are the outer loop so that tiles on lower layers are drawn first within a group
+        let mut groups: BTreeMap<Layer, Vec<[u32; 4]>> = BTreeMap::new();
+        let data = &self.data;
+        let columns = position.0 * CHUNK_SIZE..((position.0 + 1) * CHUNK_SIZE).min(data.width);
+        let rows = position.1 * CHUNK_SIZE..((position.1 + 1) * CHUNK_SIZE).min(data.height);
+        for layer in 0..data.layers {
+            for y in rows.clone() {
+                for x in columns.clone() {
+                    let index = ((layer * data.height + y) * data.width + x) as usize;
+                    let tile = data.tiles[index];
+                    if tile == 0 {
+                        continue;
+                    }
+
+                    let info = self.info.get(tile as usize).copied().unwrap_or_default();
+                    let layer = match info.priority {
+                        0 => Layer::Ground,
+                        priority => Layer::Row { row: y, priority },
+                    };
+                    let frames = info.frames.max(1) as u32;
+                    groups
+                        .entry(layer)
+                        .or_default()
+                        .push([x, y, tile as u32 - 1, frames]);
+                }
+            }
+        }
+
+        if groups.is_empty() {
+            self.chunks.remove(&position);
+            return;
+        }
+
+        let mut instances = Vec::new();
+        let mut chunk_groups = Vec::with_capacity(groups.len());
+        for (layer, tiles) in groups {
+            let start = instances.len() as u32;
+            instances.extend(tiles);
+            chunk_groups.push(Group {
+                layer,
+                instances: start..instances.len() as u32,
+            });
+        }
+
+        let contents: Vec<u8> = instances
+            .iter()
+            .flatten()
+            .flat_map(|value| value.to_ne_bytes())
+            .collect();
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("tilemap chunk"),
+            contents: &contents,
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+
+        self.chunks.insert(
+            position,
+            Chunk {
+                position,
+                buffer,
+                groups: chunk_groups,
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AnimationClock;
+
+    #[test]
+    fn steps_every_interval() {
+        let mut clock = AnimationClock::default();
+        let steps: Vec<u64> = (1..=12).filter(|frame| clock.advance(*frame, 4)).collect();
+        assert_eq!(steps, [4, 8, 12]);
+    }
+
+    #[test]
+    fn jumping_past_a_step_still_takes_it() {
+        let mut clock = AnimationClock::default();
+        assert!(!clock.advance(3, 4));
+        // A fixed timestep that woke up late skips frames 4 through 8
+        assert!(clock.advance(9, 4));
+        assert!(!clock.advance(11, 4));
+        assert!(clock.advance(12, 4));
+    }
+}
@@ -0,0 +1,54 @@
+// Draws tilemaps, with one instance of a quad per tile
+
+struct Uniforms {
+    // Scroll offset, in target pixels
+    offset: vec2<f32>,
+    target_size: vec2<f32>,
+    texture_size: vec2<f32>,
+    tile_size: f32,
+    // How many tiles fit across the tileset
+    columns: u32,
+    // Advances every animation interval
+    anim_frame: u32,
+};
+
+@group(0) @binding(0)
+var t_tileset: texture_2d<f32>;
+@group(0) @binding(1)
+var s_tileset: sampler;
+@group(0) @binding(2)
+var<uniform> tilemap: Uniforms;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+};
+
+@vertex
+fn vs_main(
+    @builtin(vertex_index) in_vertex_index: u32,
+    // Column, row, tileset cell and animation frames
+    @location(0) tile: vec4<u32>,
+) -> VertexOutput {
+    // Two triangles making up the tile's quad
+    let right = in_vertex_index == 1u || in_vertex_index == 4u || in_vertex_index == 5u;
+    let bottom = in_vertex_index == 2u || in_vertex_index == 3u || in_vertex_index == 5u;
+    let corner = vec2<f32>(select(0.0, 1.0, right), select(0.0, 1.0, bottom));
+
+    let pixel = (vec2<f32>(f32(tile.x), f32(tile.y)) + corner) * tilemap.tile_size - tilemap.offset;
+    let clip = pixel / tilemap.target_size * vec2<f32>(2.0, -2.0) + vec2<f32>(-1.0, 1.0);
+
+    // Animated tiles step through the cells after their own
+    let cell = tile.z + tilemap.anim_frame % max(tile.w, 1u);
+    let cell_position = vec2<f32>(f32(cell % tilemap.columns), f32(cell / tilemap.columns));
+
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(clip, 0.0, 1.0);
+    out.tex_coords = (cell_position + corner) * tilemap.tile_size / tilemap.texture_size;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return textureSample(t_tileset, s_tileset, in.tex_coords);
+}
@@ -16,12 +16,19 @@
 // along with libfm.  If not, see <http://www.gnu.org/licenses/>.
 use wgpu::util::DeviceExt;
 
+use crate::{effect, panel, plane, shader, shape, tilemap};
+
 pub struct State {
     instance: wgpu::Instance,
     adapter: wgpu::Adapter,
     device: wgpu::Device,
     queue: wgpu::Queue,
-    pub sprite_shader: Shader,
+    plane_renderer: plane::Renderer,
+    tilemap_renderer: tilemap::Renderer,
+    panel_renderer: panel::Renderer,
+    shape_renderer: shape::Renderer,
+    effect_renderer: effect::Renderer,
+    shader_renderer: shader::Renderer,
 }
 
 impl State {
@@ -52,61 +59,32 @@ impl State {
             .await
             .expect("Failed to create device");
 
-        let shader = device.create_shader_module(wgpu::include_wgsl!("shader.wgsl"));
-        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-            bind_group_layouts: &[],
-            ..Default::default()
-        });
-        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("Render Pipeline"),
-            layout: Some(&pipeline_layout),
-            vertex: wgpu::VertexState {
-                module: &shader,
-                entry_point: "vs_main", // 1.
-                buffers: &[],           // 2.
-            },
-            fragment: Some(wgpu::FragmentState {
-                // 3.
-                module: &shader,
-                entry_point: "fs_main",
-                targets: &[Some(wgpu::ColorTargetState {
-                    // 4.
-                    format: wgpu::TextureFormat::Bgra8UnormSrgb,
-                    blend: Some(wgpu::BlendState::REPLACE),
-                    write_mask: wgpu::ColorWrites::ALL,
-                })],
-            }),
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList, // 1.
-                strip_index_format: None,
-                front_face: wgpu::FrontFace::Ccw, // 2.
-                cull_mode: Some(wgpu::Face::Back),
-                // Setting this to anything other than Fill requires Features::NON_FILL_POLYGON_MODE
-                polygon_mode: wgpu::PolygonMode::Fill,
-                // Requires Features::DEPTH_CLIP_CONTROL
-                unclipped_depth: false,
-                // Requires Features::CONSERVATIVE_RASTERIZATION
-                conservative: false,
-            },
-            depth_stencil: None, // 1.
-            multisample: wgpu::MultisampleState {
-                count: 1,                         // 2.
-                mask: !0,                         // 3.
-                alpha_to_coverage_enabled: false, // 4.
-            },
-            multiview: None, // 5.
-        });
+        let plane_renderer = plane::Renderer::new(&device);
+        let tilemap_renderer = tilemap::Renderer::new(&device);
+        let panel_renderer = panel::Renderer::new(&device);
+        let shape_renderer = shape::Renderer::new(&device);
+        let effect_renderer = effect::Renderer::new(&device);
+        let shader_renderer = shader::Renderer::new(&device);
 
         State {
             instance,
             adapter,
             device,
             queue,
-            sprite_shader: Shader { pipeline },
+            plane_renderer,
+            tilemap_renderer,
+            panel_renderer,
+            shape_renderer,
+            effect_renderer,
+            shader_renderer,
         }
     }
 
-    pub fn create_surface(&self, window: &winit::window::Window) -> Surface {
+    pub fn create_surface(
+        &mut self,
+        window: &winit::window::Window,
+        present_mode: crate::PresentMode,
+    ) -> Surface {
         let surface = unsafe { self.instance.create_surface(&window) }.unwrap();
         let size = window.inner_size();
 
@@ -116,20 +94,35 @@ impl State {
             format: caps.formats[0],
             width: size.width,
             height: size.height,
-            present_mode: wgpu::PresentMode::Fifo,
+            present_mode: supported_present_mode(&caps, present_mode),
             alpha_mode: caps.alpha_modes[0],
             view_formats: vec![],
         };
 
         surface.configure(&self.device, &config);
+        self.plane_renderer.prepare(&self.device, config.format);
+        self.tilemap_renderer.prepare(&self.device, config.format);
+        self.panel_renderer.prepare(&self.device, config.format);
+        self.shape_renderer.prepare(&self.device, config.format);
+        self.effect_renderer.prepare(&self.device, config.format);
+        self.shader_renderer.prepare(&self.device, config.format);
 
         Surface { surface, config }
     }
 
-    pub fn create_texture(&mut self, path: String) -> Texture {
-        let image = image::open(path)
-            .expect("failed to load image")
-            .into_rgba8();
+    pub fn set_present_mode(&self, surface: &mut Surface, present_mode: crate::PresentMode) {
+        let caps = surface.surface.get_capabilities(&self.adapter);
+        surface.config.present_mode = supported_present_mode(&caps, present_mode);
+        surface.surface.configure(&self.device, &surface.config);
+    }
+
+    /// Loads an image into a texture, sampled with `address_mode` outside of its bounds.
+    pub fn create_texture(
+        &self,
+        path: &str,
+        address_mode: wgpu::AddressMode,
+    ) -> Result<Texture, image::ImageError> {
+        let image = image::open(path)?.into_rgba8();
 
         let texture = self.device.create_texture_with_data(
             &self.queue,
@@ -152,62 +145,20 @@ impl State {
 
         let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
         let sampler = self.device.create_sampler(&wgpu::SamplerDescriptor {
-            address_mode_u: wgpu::AddressMode::ClampToEdge,
-            address_mode_v: wgpu::AddressMode::ClampToEdge,
-            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            address_mode_u: address_mode,
+            address_mode_v: address_mode,
+            address_mode_w: address_mode,
             mag_filter: wgpu::FilterMode::Linear,
             min_filter: wgpu::FilterMode::Nearest,
             mipmap_filter: wgpu::FilterMode::Nearest,
             ..Default::default()
         });
 
-        let bind_group_layout =
-            self.device
-                .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                    entries: &[
-                        wgpu::BindGroupLayoutEntry {
-                            binding: 0,
-                            visibility: wgpu::ShaderStages::FRAGMENT,
-                            ty: wgpu::BindingType::Texture {
-                                multisampled: false,
-                                view_dimension: wgpu::TextureViewDimension::D2,
-                                sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                            },
-                            count: None,
-                        },
-                        wgpu::BindGroupLayoutEntry {
-                            binding: 1,
-                            visibility: wgpu::ShaderStages::FRAGMENT,
-                            // This should match the filterable field of the
-                            // corresponding Texture entry above.
-                            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                            count: None,
-                        },
-                    ],
-                    label: Some("texture_bind_group_layout"),
-                });
-
-        let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&sampler),
-                },
-            ],
-            label: Some("diffuse_bind_group"),
-        });
-
-        Texture {
+        Ok(Texture {
             texture,
             view,
             sampler,
-            bind_group,
-        }
+        })
     }
 
     pub fn create_command_encoder(&self) -> wgpu::CommandEncoder {
@@ -215,23 +166,377 @@ impl State {
             .create_command_encoder(&wgpu::CommandEncoderDescriptor::default())
     }
 
+    /// Loads a plane's image, which repeats across the whole window.
+    pub fn create_plane_image(&self, path: &str) -> Result<plane::Image, image::ImageError> {
+        let texture = self.create_texture(path, wgpu::AddressMode::Repeat)?;
+        Ok(self.plane_renderer.create_image(&self.device, texture))
+    }
+
+    pub fn draw_plane<'pass>(
+        &'pass self,
+        pass: &mut wgpu::RenderPass<'pass>,
+        surface: &Surface,
+        config: &crate::PlaneConfig,
+        image: &'pass plane::Image,
+    ) {
+        let target = (surface.config.width, surface.config.height);
+        self.plane_renderer.draw(
+            pass,
+            &self.queue,
+            surface.config.format,
+            target,
+            config,
+            image,
+        );
+    }
+
+    pub fn create_tilemap(&self) -> tilemap::Tilemap {
+        tilemap::Tilemap::new(&self.device)
+    }
+
+    pub fn set_tileset(
+        &self,
+        tilemap: &mut tilemap::Tilemap,
+        path: &str,
+    ) -> Result<(), image::ImageError> {
+        let texture = self.create_texture(path, wgpu::AddressMode::ClampToEdge)?;
+        tilemap.set_tileset(&self.device, &self.tilemap_renderer, texture);
+        Ok(())
+    }
+
+    pub fn set_tile_data(&self, tilemap: &mut tilemap::Tilemap, data: crate::TileData) -> bool {
+        tilemap.set_data(&self.device, data)
+    }
+
+    pub fn set_tiles(
+        &self,
+        tilemap: &mut tilemap::Tilemap,
+        updates: Vec<crate::TileUpdate>,
+    ) -> bool {
+        tilemap.set_tiles(&self.device, updates)
+    }
+
+    pub fn set_tile_info(
+        &self,
+        tilemap: &mut tilemap::Tilemap,
+        updates: Vec<(u16, crate::TileInfo)>,
+    ) {
+        tilemap.set_info(&self.device, updates)
+    }
+
+    /// Updates what every draw of `tilemap` to `surface` on `frame` shares.
+    pub fn prepare_tilemap(&self, surface: &Surface, tilemap: &tilemap::Tilemap, frame: u64) {
+        let target = (surface.config.width, surface.config.height);
+        tilemap.prepare(&self.queue, target, frame);
+    }
+
+    pub fn draw_tiles<'pass>(
+        &'pass self,
+        pass: &mut wgpu::RenderPass<'pass>,
+        surface: &Surface,
+        tileset: &'pass tilemap::Tileset,
+        chunk: &'pass tilemap::Chunk,
+        group: &tilemap::Group,
+    ) {
+        self.tilemap_renderer
+            .draw(pass, surface.config.format, tileset, chunk, group);
+    }
+
+    pub fn create_panel(&self) -> panel::Panel {
+        panel::Panel::new(&self.device, &self.panel_renderer)
+    }
+
+    pub fn set_panel_skin(
+        &self,
+        panel: &mut panel::Panel,
+        path: &str,
+    ) -> Result<(), image::ImageError> {
+        let texture = self.create_texture(path, wgpu::AddressMode::ClampToEdge)?;
+        let image = self.panel_renderer.create_image(&self.device, texture);
+        panel.set_skin(&self.device, image);
+        Ok(())
+    }
+
+    /// Sets the image drawn inside the panel, or clears it if `path` is `None`.
+    pub fn set_panel_contents(
+        &self,
+        panel: &mut panel::Panel,
+        path: Option<&str>,
+    ) -> Result<(), image::ImageError> {
+        let image = path
+            .map(|path| self.create_texture(path, wgpu::AddressMode::ClampToEdge))
+            .transpose()?
+            .map(|texture| self.panel_renderer.create_image(&self.device, texture));
+        panel.set_contents(&self.device, image);
+        Ok(())
+    }
+
+    pub fn configure_panel(&self, panel: &mut panel::Panel, config: crate::PanelConfig) {
+        panel.configure(&self.device, config)
+    }
+
+    /// Returns how many draw calls it took.
+    pub fn draw_panel<'pass>(
+        &'pass self,
+        pass: &mut wgpu::RenderPass<'pass>,
+        surface: &Surface,
+        panel: &'pass panel::Panel,
+    ) -> u32 {
+        panel.prepare(&self.queue, surface.size());
+        self.panel_renderer.draw(pass, surface.config.format, panel)
+    }
+
+    pub fn create_shape(&self) -> shape::Shape {
+        shape::Shape::new(&self.device, &self.shape_renderer)
+    }
+
+    pub fn set_shape_geometry(&self, shape: &mut shape::Shape, geometry: crate::ShapeGeometry) {
+        shape.set_geometry(&self.device, geometry)
+    }
+
+    pub fn configure_shape(&self, shape: &mut shape::Shape, config: crate::ShapeConfig) {
+        shape.configure(&self.device, config)
+    }
+
+    /// Returns how many draw calls it took.
+    pub fn draw_shape<'pass>(
+        &'pass self,
+        pass: &mut wgpu::RenderPass<'pass>,
+        surface: &Surface,
+        shape: &'pass shape::Shape,
+    ) -> u32 {
+        shape.prepare(&self.queue, surface.size(), surface.config.format);
+        self.shape_renderer.draw(pass, surface.config.format, shape)
+    }
+
+    /// Gets `effects` ready to run over `surface` on `frame`.
+    pub fn prepare_effects(&self, surface: &Surface, effects: &mut effect::Chain, frame: u64) {
+        effects.prepare(
+            &self.device,
+            &self.queue,
+            &self.effect_renderer,
+            surface.config.format,
+            surface.size(),
+            frame,
+        );
+    }
+
+    /// Runs `effects` over the scene, into `output`. Returns how many passes ran.
+    pub fn run_effects(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        effects: &effect::Chain,
+        output: &wgpu::TextureView,
+    ) -> u32 {
+        effects.run(encoder, &self.effect_renderer, output)
+    }
+
+    /// Loads a sprite's image, which shaders sample.
+    pub fn create_sprite_image(&self, path: &str) -> Result<shader::Image, image::ImageError> {
+        let texture = self.create_texture(path, wgpu::AddressMode::ClampToEdge)?;
+        Ok(self.shader_renderer.create_image(&self.device, texture))
+    }
+
+    pub fn create_shader_uniforms(&self) -> shader::Uniforms {
+        self.shader_renderer.create_uniforms(&self.device)
+    }
+
+    /// Compiles a client's shader for drawing to `surface`.
+    pub fn create_shader(
+        &self,
+        surface: &Surface,
+        source: &str,
+    ) -> Result<shader::Program, crate::ShaderError> {
+        self.shader_renderer
+            .create_program(&self.device, source, surface.config.format)
+    }
+
+    /// Draws a sprite's image with `program`, or the default shader if it's `None`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn draw_sprite<'pass>(
+        &'pass self,
+        pass: &mut wgpu::RenderPass<'pass>,
+        surface: &Surface,
+        program: Option<&'pass shader::Program>,
+        uniforms: &'pass shader::Uniforms,
+        image: &'pass shader::Image,
+        position: (i32, i32),
+        frame: u64,
+    ) {
+        let program =
+            program.unwrap_or_else(|| self.shader_renderer.default_program(surface.config.format));
+        self.shader_renderer.draw_sprite(
+            pass,
+            &self.queue,
+            surface.size(),
+            frame,
+            program,
+            uniforms,
+            image,
+            position,
+        );
+    }
+
+    /// Gets `pass` ready to draw `surface`'s scene into, or drops it if it isn't `enabled`.
+    pub fn prepare_shader_pass(
+        &self,
+        surface: &Surface,
+        pass: &mut Option<shader::WindowPass>,
+        enabled: bool,
+    ) {
+        self.shader_renderer.prepare_pass(
+            &self.device,
+            pass,
+            surface.config.format,
+            surface.size(),
+            enabled,
+        );
+    }
+
+    /// Runs a window's shader over the scene in `pass`, into `output`.
+    pub fn run_shader(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        program: &shader::Program,
+        pass: &shader::WindowPass,
+        output: &wgpu::TextureView,
+        frame: u64,
+    ) {
+        self.shader_renderer
+            .run_pass(encoder, &self.queue, frame, program, pass, output);
+    }
+
     pub fn submit_encoder(&self, encoder: wgpu::CommandEncoder) {
         self.queue.submit(std::iter::once(encoder.finish()));
     }
 
+    /// Calls `done` once the GPU has finished everything submitted so far. It only runs while
+    /// the device is polled, as in [`State::poll`].
+    pub fn on_submitted_work_done(&self, done: impl FnOnce() + Send + 'static) {
+        self.queue.on_submitted_work_done(done);
+    }
+
+    /// Runs the callbacks for whatever the GPU has finished, without waiting on the rest.
+    pub fn poll(&self) {
+        self.device.poll(wgpu::Maintain::Poll);
+    }
+
     // pub fn render(&self, output: wgpu::SurfaceTexture, f: impl FnOnce(&mut wgpu::RenderPass)) {}
 
+    /// Renders into an offscreen copy of `surface` and reads the result back as RGBA pixels.
+    pub fn snapshot(
+        &self,
+        surface: &Surface,
+        render: impl FnOnce(&wgpu::TextureView) -> wgpu::CommandEncoder,
+    ) -> Option<image::RgbaImage> {
+        // Only 8 bit RGBA and BGRA convert to an RGBA image without any work
+        let format = surface.config.format;
+        let bgra = match format {
+            wgpu::TextureFormat::Rgba8Unorm | wgpu::TextureFormat::Rgba8UnormSrgb => false,
+            wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb => true,
+            _ => return None,
+        };
+        let pixel_bytes = format.block_size(None)?;
+
+        let (width, height) = (surface.config.width, surface.config.height);
+        let size = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        };
+        let texture = self.device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("snapshot texture"),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        // Buffer copies need every row padded out to a fixed alignment
+        let row_bytes = width * pixel_bytes;
+        let padded_row_bytes = row_bytes.div_ceil(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT)
+            * wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("snapshot buffer"),
+            size: padded_row_bytes as u64 * height as u64,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = render(&view);
+        encoder.copy_texture_to_buffer(
+            texture.as_image_copy(),
+            wgpu::ImageCopyBuffer {
+                buffer: &buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_row_bytes),
+                    rows_per_image: None,
+                },
+            },
+            size,
+        );
+        self.submit_encoder(encoder);
+
+        let slice = buffer.slice(..);
+        slice.map_async(wgpu::MapMode::Read, |result| {
+            result.expect("failed to map snapshot buffer")
+        });
+        self.device.poll(wgpu::Maintain::Wait);
+
+        let mut pixels = Vec::with_capacity((row_bytes * height) as usize);
+        for row in slice.get_mapped_range().chunks(padded_row_bytes as usize) {
+            pixels.extend_from_slice(&row[..row_bytes as usize]);
+        }
+        if bgra {
+            for pixel in pixels.chunks_mut(pixel_bytes as usize) {
+                pixel.swap(0, 2);
+            }
+        }
+        buffer.unmap();
+
+        image::RgbaImage::from_raw(width, height, pixels)
+    }
+
     pub fn resize_surface(&self, surface: &mut Surface, size: winit::dpi::PhysicalSize<u32>) {
         surface.resize(&self.device, size);
     }
 }
 
+// Fifo is the only mode every backend has to support, so anything else falls back towards it
+fn supported_present_mode(
+    caps: &wgpu::SurfaceCapabilities,
+    present_mode: crate::PresentMode,
+) -> wgpu::PresentMode {
+    let preferred: &[wgpu::PresentMode] = match present_mode {
+        crate::PresentMode::Fifo => &[],
+        crate::PresentMode::Mailbox => &[wgpu::PresentMode::Mailbox],
+        crate::PresentMode::Immediate => {
+            &[wgpu::PresentMode::Immediate, wgpu::PresentMode::Mailbox]
+        }
+    };
+
+    preferred
+        .iter()
+        .copied()
+        .find(|mode| caps.present_modes.contains(mode))
+        .unwrap_or(wgpu::PresentMode::Fifo)
+}
+
 pub struct Surface {
     surface: wgpu::Surface,
     config: wgpu::SurfaceConfiguration,
 }
 
 impl Surface {
+    pub fn size(&self) -> (u32, u32) {
+        (self.config.width, self.config.height)
+    }
+
     pub fn resize(&mut self, device: &wgpu::Device, size: winit::dpi::PhysicalSize<u32>) {
         self.config.width = size.width;
         self.config.height = size.height;
@@ -245,25 +550,20 @@ impl Surface {
     }
 }
 
-pub struct Shader {
-    pipeline: wgpu::RenderPipeline,
-}
-
-impl Shader {
-    pub fn bind<'pass>(&'pass self, pass: &mut wgpu::RenderPass<'pass>) {
-        pass.set_pipeline(&self.pipeline);
-    }
-}
-
 pub struct Texture {
     texture: wgpu::Texture,
-    view: wgpu::TextureView,
-    sampler: wgpu::Sampler,
-    bind_group: wgpu::BindGroup,
+    pub(crate) view: wgpu::TextureView,
+    pub(crate) sampler: wgpu::Sampler,
 }
 
 impl Texture {
-    pub fn bind<'pass>(&'pass self, pass: &mut wgpu::RenderPass<'pass>) {
-        pass.set_bind_group(0, &self.bind_group, &[])
+    pub fn size(&self) -> (u32, u32) {
+        let size = self.texture.size();
+        (size.width, size.height)
+    }
+
+    pub fn memory(&self) -> u64 {
+        let size = self.texture.size();
+        size.width as u64 * size.height as u64 * 4
     }
 }
@@ -0,0 +1,185 @@
+// Copyright (C) 2023 Lily Lyons
+//
+// This file is part of libfm.
+//
+// libfm is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// libfm is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with libfm.  If not, see <http://www.gnu.org/licenses/>.
+
+//! How messages are encoded on the socket between a client and the screen.
+//!
+//! The client starts the connection with a handshake line naming the protocol version and
+//! the encoding it wants, like `libfm 13 json`. The screen answers with a line naming its own
+//! version and whether it accepted, like `libfm 13 ok` or `libfm 13 error unknown encoding`,
+//! and hangs up if it didn't. Everything after that is in the chosen encoding:
+//!
+//! - `bincode`: compact binary frames, what libfm uses by default.
+//! - `json`: one JSON value per line, so the traffic can be read with `socat` or written by
+//!   hand. For example `{"RepositionWindow":[10,20,1]}` or `"Shutdown"`.
+
+use futures::prelude::*;
+use serde::{de::DeserializeOwned, Serialize};
+use std::pin::Pin;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    #[default]
+    Bincode,
+    Json,
+}
+
+pub type Reader<T> = Pin<Box<dyn Stream<Item = T> + Send>>;
+pub type Writer<T> = Pin<Box<dyn Sink<T, Error = std::io::Error> + Send>>;
+
+impl Encoding {
+    pub fn name(self) -> &'static str {
+        match self {
+            Encoding::Bincode => "bincode",
+            Encoding::Json => "json",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "bincode" => Some(Encoding::Bincode),
+            "json" => Some(Encoding::Json),
+            _ => None,
+        }
+    }
+
+    /// The line a client opens the connection with, newline included.
+    pub fn handshake(self) -> String {
+        format!("libfm {} {}\n", crate::PROTOCOL_VERSION, self.name())
+    }
+
+    /// Checks a client's handshake line, returning the encoding it asked for.
+    pub fn from_handshake(line: &str) -> Result<Self, String> {
+        let mut parts = line.split_whitespace();
+        let (Some("libfm"), Some(version), Some(name), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(format!("malformed handshake {line:?}"));
+        };
+
+        if version != crate::PROTOCOL_VERSION.to_string() {
+            return Err(format!(
+                "client speaks protocol version {version}, but this screen speaks {}",
+                crate::PROTOCOL_VERSION
+            ));
+        }
+        Self::from_name(name).ok_or_else(|| format!("unknown encoding {name:?}"))
+    }
+
+    /// The line a screen answers a handshake with, newline included.
+    pub fn handshake_reply(result: &Result<Self, String>) -> String {
+        match result {
+            Ok(_) => format!("libfm {} ok\n", crate::PROTOCOL_VERSION),
+            Err(reason) => format!("libfm {} error {reason}\n", crate::PROTOCOL_VERSION),
+        }
+    }
+
+    /// Checks the screen's answer to our handshake.
+    pub fn check_handshake_reply(line: &str) -> Result<(), Rejection> {
+        let mut parts = line.trim_end().splitn(3, ' ');
+        let (Some("libfm"), Some(version), Some(status)) =
+            (parts.next(), parts.next(), parts.next())
+        else {
+            return Err(Rejection::Other(format!(
+                "malformed handshake reply {line:?}"
+            )));
+        };
+        let version = version
+            .parse()
+            .map_err(|_| Rejection::Other(format!("malformed handshake reply {line:?}")))?;
+
+        // Whatever else went wrong, different versions are the root of it
+        if version != crate::PROTOCOL_VERSION {
+            return Err(Rejection::Version(version));
+        }
+        match status.split_once(' ') {
+            None if status == "ok" => Ok(()),
+            Some(("error", reason)) => Err(Rejection::Other(reason.to_string())),
+            _ => Err(Rejection::Other(format!(
+                "malformed handshake reply {line:?}"
+            ))),
+        }
+    }
+
+    /// Decodes messages from `reader`, stopping once it closes.
+    pub fn reader<T, R>(self, reader: R) -> Reader<T>
+    where
+        T: DeserializeOwned + Send + 'static,
+        R: AsyncBufRead + Unpin + Send + 'static,
+    {
+        match self {
+            // Stop at the first message we fail to decode, since we can't tell where the next one starts
+            Encoding::Bincode => Box::pin(
+                async_bincode::futures::AsyncBincodeReader::from(reader)
+                    .scan((), |_, message| future::ready(message.ok())),
+            ),
+            // Lines are easy to get wrong by hand, so skip the bad ones instead of giving up
+            Encoding::Json => Box::pin(
+                reader
+                    .lines()
+                    .scan((), |_, line| future::ready(line.ok()))
+                    .filter(|line| future::ready(!line.trim().is_empty()))
+                    .filter_map(|line| {
+                        future::ready(match serde_json::from_str(&line) {
+                            Ok(message) => Some(message),
+                            Err(e) => {
+                                eprintln!("failed to decode {line:?}: {e}");
+                                None
+                            }
+                        })
+                    }),
+            ),
+        }
+    }
+
+    /// Encodes messages onto `writer`.
+    pub fn writer<T, W>(self, writer: W) -> Writer<T>
+    where
+        T: Serialize + Send + 'static,
+        W: AsyncWrite + Unpin + Send + 'static,
+    {
+        match self {
+            Encoding::Bincode => Box::pin(
+                async_bincode::futures::AsyncBincodeWriter::from(writer)
+                    .for_async()
+                    .sink_map_err(into_io),
+            ),
+            Encoding::Json => Box::pin(sink::unfold(writer, |mut writer, message: T| async move {
+                let mut line = serde_json::to_vec(&message)?;
+                line.push(b'\n');
+                writer.write_all(&line).await?;
+                writer.flush().await?;
+                Ok(writer)
+            })),
+        }
+    }
+}
+
+/// Why a screen turned down a handshake.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Rejection {
+    /// The screen speaks this protocol version instead of ours.
+    Version(u32),
+    /// Anything else, like an encoding the screen doesn't know.
+    Other(String),
+}
+
+pub(crate) fn into_io(error: bincode::Error) -> std::io::Error {
+    match error.as_ref() {
+        bincode::ErrorKind::Io(e) => std::io::Error::new(e.kind(), error),
+        _ => std::io::Error::new(std::io::ErrorKind::InvalidData, error),
+    }
+}
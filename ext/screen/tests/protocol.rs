@@ -0,0 +1,379 @@
+// Copyright (C) 2023 Lily Lyons
+//
+// This file is part of libfm.
+//
+// libfm is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// libfm is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with libfm.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Checks that every message survives the trip over the wire, and that the wire format only
+//! changes along with `PROTOCOL_VERSION`.
+
+use futures::prelude::*;
+use screen::*;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+// The wire format these samples encode to, and the protocol version it was recorded for.
+// If this test fails, the format changed: bump PROTOCOL_VERSION and record both again.
+const FINGERPRINT: (u32, u64) = (14, 0xf8e9_c767_db76_b71e);
+
+// Every variant is numbered in declaration order, so adding one fails to compile until it's
+// numbered here and given a sample below.
+fn message_index(message: &Message) -> usize {
+    match message {
+        Message::CreateWindow(..) => 0,
+        Message::DeleteWindow(..) => 1,
+        Message::ResizeWindow(..) => 2,
+        Message::RepositionWindow(..) => 3,
+        Message::CreateSprite(..) => 4,
+        Message::RemoveSprite(..) => 5,
+        Message::SetSprite(..) => 6,
+        Message::RepositionSprite(..) => 7,
+        Message::CreatePlane(..) => 8,
+        Message::RemovePlane(..) => 9,
+        Message::SetPlane(..) => 10,
+        Message::ConfigurePlane(..) => 11,
+        Message::CreateTilemap(..) => 12,
+        Message::RemoveTilemap(..) => 13,
+        Message::SetTileset(..) => 14,
+        Message::ConfigureTilemap(..) => 15,
+        Message::SetTileData(..) => 16,
+        Message::SetTiles(..) => 17,
+        Message::SetTileInfo(..) => 18,
+        Message::CreatePanel(..) => 19,
+        Message::RemovePanel(..) => 20,
+        Message::SetPanelSkin(..) => 21,
+        Message::SetPanelContents(..) => 22,
+        Message::ConfigurePanel(..) => 23,
+        Message::CreateShape(..) => 24,
+        Message::RemoveShape(..) => 25,
+        Message::SetShape(..) => 26,
+        Message::ConfigureShape(..) => 27,
+        Message::ConfigureFrames(..) => 28,
+        Message::Snapshot(..) => 29,
+        Message::SetEffects(..) => 30,
+        Message::CreateShader(..) => 31,
+        Message::RemoveShader(..) => 32,
+        Message::SetShaderUniforms(..) => 33,
+        Message::SetSpriteShader(..) => 34,
+        Message::SetWindowShader(..) => 35,
+        Message::Batch(..) => 36,
+        Message::Shutdown => 37,
+    }
+}
+
+fn return_message_index(message: &ReturnMessage) -> usize {
+    match message {
+        ReturnMessage::CloseRequested(..) => 0,
+        ReturnMessage::Moved(..) => 1,
+        ReturnMessage::Resized(..) => 2,
+        ReturnMessage::Focused(..) => 3,
+        ReturnMessage::Frame(..) => 4,
+        ReturnMessage::Stats(..) => 5,
+        ReturnMessage::Snapshot(..) => 6,
+        ReturnMessage::Error(error) => 7 + error_index(error),
+        ReturnMessage::LoadFailed(..) => 7 + ERRORS,
+    }
+}
+
+const ERRORS: usize = 19;
+
+fn error_index(error: &ProtocolError) -> usize {
+    match error {
+        ProtocolError::DuplicateWindow(..) => 0,
+        ProtocolError::DuplicateSprite(..) => 1,
+        ProtocolError::UnknownWindow(..) => 2,
+        ProtocolError::UnknownSprite(..) => 3,
+        ProtocolError::DuplicatePlane(..) => 4,
+        ProtocolError::UnknownPlane(..) => 5,
+        ProtocolError::DuplicateTilemap(..) => 6,
+        ProtocolError::UnknownTilemap(..) => 7,
+        ProtocolError::TilesOutOfBounds(..) => 8,
+        ProtocolError::DuplicatePanel(..) => 9,
+        ProtocolError::UnknownPanel(..) => 10,
+        ProtocolError::DuplicateShape(..) => 11,
+        ProtocolError::UnknownShape(..) => 12,
+        ProtocolError::DuplicateShader(..) => 13,
+        ProtocolError::UnknownShader(..) => 14,
+        ProtocolError::InvalidShader(..) => 15,
+        ProtocolError::FramesOwned => 16,
+        ProtocolError::VersionMismatch(..) => 17,
+        ProtocolError::SnapshotUnsupported(..) => 18,
+    }
+}
+
+fn messages() -> Vec<Message> {
+    let window = WindowConfig {
+        title: "libfm".to_string(),
+        pos: Some((10, -20)),
+        visible: true,
+        decorations: false,
+        size: (640, 480),
+        z: Some(3),
+    };
+    let mut uniforms = [0.0; SHADER_UNIFORMS];
+    uniforms[1] = 0.5;
+
+    vec![
+        Message::CreateWindow(window, 1),
+        Message::DeleteWindow(1),
+        Message::ResizeWindow(800, 600, 1),
+        Message::RepositionWindow(-5, 7, 1),
+        Message::CreateSprite(2, 1),
+        Message::RemoveSprite(2, 1),
+        Message::SetSprite(2, 1, "sprite.png".to_string()),
+        Message::RepositionSprite(2, 1, 3, -4, 5),
+        Message::CreatePlane(3, 1),
+        Message::RemovePlane(3, 1),
+        Message::SetPlane(3, 1, "plane.png".to_string()),
+        Message::ConfigurePlane(3, 1, PlaneConfig::default()),
+        Message::CreateTilemap(4, 1),
+        Message::RemoveTilemap(4, 1),
+        Message::SetTileset(4, 1, "tileset.png".to_string()),
+        Message::ConfigureTilemap(4, 1, TilemapConfig::default()),
+        Message::SetTileData(4, 1, TileData::new(2, 2, 1)),
+        Message::SetTiles(
+            4,
+            1,
+            vec![TileUpdate {
+                x: 1,
+                y: 0,
+                layer: 0,
+                tile: 9,
+            }],
+        ),
+        Message::SetTileInfo(4, 1, vec![(9, TileInfo::default())]),
+        Message::CreatePanel(5, 1),
+        Message::RemovePanel(5, 1),
+        Message::SetPanelSkin(5, 1, "skin.png".to_string()),
+        Message::SetPanelContents(5, 1, Some("contents.png".to_string())),
+        Message::ConfigurePanel(5, 1, PanelConfig::default()),
+        Message::CreateShape(6, 1),
+        Message::RemoveShape(6, 1),
+        Message::SetShape(6, 1, ShapeGeometry::Polygon(vec![(0.0, 0.0), (4.0, 2.5)])),
+        Message::ConfigureShape(6, 1, ShapeConfig::default()),
+        Message::ConfigureFrames(FrameConfig::default()),
+        Message::Snapshot(1),
+        Message::SetEffects(
+            vec![
+                Effect::Blur { radius: 2.0 },
+                Effect::Shake {
+                    amplitude: 4.0,
+                    speed: 0.5,
+                },
+            ],
+            30,
+            1,
+        ),
+        Message::CreateShader(7, 1, "@fragment fn fs_main() {}".to_string()),
+        Message::RemoveShader(7, 1),
+        Message::SetShaderUniforms(7, 1, uniforms),
+        Message::SetSpriteShader(2, 1, Some(7)),
+        Message::SetWindowShader(None, 1),
+        Message::Batch(vec![Message::Snapshot(1), Message::DeleteWindow(1)]),
+        Message::Shutdown,
+    ]
+}
+
+fn return_messages() -> Vec<ReturnMessage> {
+    let errors = [
+        ProtocolError::DuplicateWindow(1),
+        ProtocolError::DuplicateSprite(2, 1),
+        ProtocolError::UnknownWindow(1),
+        ProtocolError::UnknownSprite(2, 1),
+        ProtocolError::DuplicatePlane(3, 1),
+        ProtocolError::UnknownPlane(3, 1),
+        ProtocolError::DuplicateTilemap(4, 1),
+        ProtocolError::UnknownTilemap(4, 1),
+        ProtocolError::TilesOutOfBounds(4, 1),
+        ProtocolError::DuplicatePanel(5, 1),
+        ProtocolError::UnknownPanel(5, 1),
+        ProtocolError::DuplicateShape(6, 1),
+        ProtocolError::UnknownShape(6, 1),
+        ProtocolError::DuplicateShader(7, 1),
+        ProtocolError::UnknownShader(7, 1),
+        ProtocolError::InvalidShader(7, 1),
+        ProtocolError::FramesOwned,
+        ProtocolError::VersionMismatch(1, 2),
+        ProtocolError::SnapshotUnsupported(1),
+    ];
+
+    let mut messages = vec![
+        ReturnMessage::CloseRequested(1),
+        ReturnMessage::Moved(1, -3, 4),
+        ReturnMessage::Resized(1, 640, 480),
+        ReturnMessage::Focused(1, true),
+        ReturnMessage::Frame(120, 1.0 / 60.0),
+        ReturnMessage::Stats(FrameStats {
+            frames: 30,
+            elapsed: 0.5,
+            cpu_time: 0.01,
+            gpu_time: 0.02,
+            draw_calls: 90,
+            sprites_drawn: 60,
+            messages: 12,
+            texture_memory: 4096,
+        }),
+        ReturnMessage::Snapshot(1, vec![0x89, b'P', b'N', b'G']),
+    ];
+    messages.extend(errors.into_iter().map(ReturnMessage::Error));
+    messages.push(ReturnMessage::LoadFailed(
+        "missing.png".to_string(),
+        "file does not exist".to_string(),
+    ));
+    messages
+}
+
+// FNV-1a, which unlike std's hasher is the same on every Rust version
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+#[test]
+fn every_variant_has_a_sample() {
+    let indices: Vec<_> = messages().iter().map(message_index).collect();
+    assert_eq!(indices, (0..=37).collect::<Vec<_>>());
+
+    let indices: Vec<_> = return_messages().iter().map(return_message_index).collect();
+    assert_eq!(indices, (0..=7 + ERRORS).collect::<Vec<_>>());
+}
+
+#[test]
+fn wire_format_changes_bump_the_version() {
+    let mut bytes = bincode::serialize(&messages()).unwrap();
+    bytes.extend(bincode::serialize(&return_messages()).unwrap());
+
+    let fingerprint = (PROTOCOL_VERSION, fnv1a(&bytes));
+    assert_eq!(
+        fingerprint, FINGERPRINT,
+        "the wire format changed, so PROTOCOL_VERSION needs a bump"
+    );
+}
+
+// Somewhere to write to that we can still read from once the writer is done with it
+#[derive(Clone, Default)]
+struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+impl AsyncWrite for Buffer {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+// Sends `messages` through `encoding` and reads them back. Messages can't be compared, so they
+// come back formatted with Debug
+fn round_trip<T>(encoding: wire::Encoding, messages: Vec<T>) -> (Vec<String>, Vec<String>)
+where
+    T: serde::Serialize + serde::de::DeserializeOwned + std::fmt::Debug + Send + 'static,
+{
+    let sent: Vec<_> = messages
+        .iter()
+        .map(|message| format!("{message:?}"))
+        .collect();
+
+    let buffer = Buffer::default();
+    let mut writer = encoding.writer(buffer.clone());
+    futures::executor::block_on(async {
+        for message in messages {
+            writer.send(message).await.unwrap();
+        }
+        writer.close().await.unwrap();
+    });
+
+    let bytes = std::mem::take(&mut *buffer.0.lock().unwrap());
+    let reader = encoding.reader::<T, _>(futures::io::Cursor::new(bytes));
+    let received: Vec<_> = futures::executor::block_on(reader.collect());
+    let received = received
+        .iter()
+        .map(|message| format!("{message:?}"))
+        .collect();
+
+    (sent, received)
+}
+
+#[test]
+fn messages_survive_both_encodings() {
+    for encoding in [wire::Encoding::Bincode, wire::Encoding::Json] {
+        let (sent, received) = round_trip(encoding, messages());
+        assert_eq!(sent, received, "messages changed over {}", encoding.name());
+
+        let (sent, received) = round_trip(encoding, return_messages());
+        assert_eq!(
+            sent,
+            received,
+            "return messages changed over {}",
+            encoding.name()
+        );
+    }
+}
+
+#[test]
+fn handshakes_are_accepted_with_matching_versions() {
+    let line = wire::Encoding::Json.handshake();
+    let encoding = wire::Encoding::from_handshake(&line);
+    assert_eq!(encoding, Ok(wire::Encoding::Json));
+
+    let reply = wire::Encoding::handshake_reply(&encoding);
+    assert_eq!(reply, format!("libfm {PROTOCOL_VERSION} ok\n"));
+    assert_eq!(wire::Encoding::check_handshake_reply(&reply), Ok(()));
+}
+
+#[test]
+fn version_mismatches_are_reported_with_the_screens_version() {
+    let line = format!("libfm {} bincode\n", PROTOCOL_VERSION + 1);
+    let encoding = wire::Encoding::from_handshake(&line);
+    assert!(encoding.is_err());
+
+    let reply = wire::Encoding::handshake_reply(&encoding);
+    assert!(reply.starts_with(&format!("libfm {PROTOCOL_VERSION} error ")));
+
+    // What the newer client sees, once its version is the odd one out
+    let newer_reply = format!("libfm {} error whatever\n", PROTOCOL_VERSION - 1);
+    assert_eq!(
+        wire::Encoding::check_handshake_reply(&newer_reply),
+        Err(wire::Rejection::Version(PROTOCOL_VERSION - 1))
+    );
+}
+
+#[test]
+fn other_rejections_keep_their_reason() {
+    let encoding = wire::Encoding::from_handshake(&format!("libfm {PROTOCOL_VERSION} xml\n"));
+    let reply = wire::Encoding::handshake_reply(&encoding);
+
+    assert_eq!(
+        wire::Encoding::check_handshake_reply(&reply),
+        Err(wire::Rejection::Other(
+            "unknown encoding \"xml\"".to_string()
+        ))
+    );
+    assert!(matches!(
+        wire::Encoding::check_handshake_reply("hello\n"),
+        Err(wire::Rejection::Other(_))
+    ));
+}
@@ -0,0 +1,116 @@
+// Copyright (C) 2023 Lily Lyons
+//
+// This file is part of libfm.
+//
+// libfm is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// libfm is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with libfm.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Checks what shaders from clients are accepted, and that rejections point at the right line.
+
+use screen::validate_shader;
+
+fn rejection(source: &str) -> String {
+    validate_shader(source)
+        .expect_err("the shader should be rejected")
+        .to_string()
+}
+
+#[test]
+fn the_default_shader_is_valid() {
+    assert_eq!(validate_shader(include_str!("../src/shader.wgsl")), Ok(()));
+}
+
+#[test]
+fn shaders_can_use_the_globals() {
+    let source = "
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(t_source, s_source, in.uv);
+    let pulse = sin(globals.time / 10.0) * globals.user[0].x;
+    return color + vec4<f32>(pulse, pulse, pulse, 0.0);
+}";
+    assert_eq!(validate_shader(source), Ok(()));
+}
+
+#[test]
+fn compile_errors_are_counted_from_the_shaders_own_source() {
+    let source = "@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return undefined_color;
+}";
+    let error = rejection(source);
+    assert!(error.starts_with("line 3:"), "{error}");
+}
+
+#[test]
+fn type_errors_are_rejected() {
+    let source = "@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let x: f32 = in.uv;
+    return vec4<f32>(x);
+}";
+    let error = rejection(source);
+    assert!(error.starts_with("line 3:"), "{error}");
+}
+
+#[test]
+fn fs_main_is_required() {
+    let source = "
+@fragment
+fn main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(1.0);
+}";
+    assert_eq!(rejection(source), "shaders must define fs_main");
+}
+
+#[test]
+fn fs_main_has_to_return_a_color() {
+    let source = "
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec3<f32> {
+    return vec3<f32>(1.0);
+}";
+    assert_eq!(
+        rejection(source),
+        "fs_main must return a vec4<f32> at @location(0)"
+    );
+}
+
+#[test]
+fn bindings_of_their_own_are_rejected() {
+    let source = "
+@group(2) @binding(0)
+var<uniform> extra: vec4<f32>;
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return extra;
+}";
+    assert_eq!(
+        rejection(source),
+        "shaders can't declare bindings of their own"
+    );
+}
+
+#[test]
+fn only_the_uv_can_be_passed_in() {
+    let source = "
+@fragment
+fn fs_main(@location(1) color: vec4<f32>) -> @location(0) vec4<f32> {
+    return color;
+}";
+    assert_eq!(
+        rejection(source),
+        "fs_main can only take the prelude's VertexOutput"
+    );
+}